@@ -0,0 +1,668 @@
+//! A typed syntax tree for faraday source.
+//!
+//! Tools which only need to inspect source (formatters, linters, etc.) should use
+//! [`parse_to_ast`] instead of walking the parser's [`Pairs`] directly. [`Node`]
+//! doesn't change when the grammar is restructured, and (unlike [`crate::process`])
+//! building it does no type checking.
+use crate::data::{
+    AssociationType, ExecutionType, FunctionArguments, MutabilityModifier, Type, TypeAlias,
+    TypeVisibility,
+};
+use crate::fcompiler_error;
+use parser::{Error, FaradayParser, Pair, Pairs, Parser, Rule};
+use serde::{Deserialize, Serialize};
+
+/// A single syntax tree node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Node {
+    /// `#[ident(...)]`, or `#![ident(...)]` when `module` is set.
+    Macro {
+        module: bool,
+        call: Call,
+    },
+    /// `use "path" as ident`
+    Use {
+        visibility: Option<TypeVisibility>,
+        path: String,
+        ident: String,
+    },
+    Struct {
+        visibility: Option<TypeVisibility>,
+        r#type: Type,
+    },
+    Enum {
+        visibility: Option<TypeVisibility>,
+        r#type: Type,
+    },
+    TypeAlias {
+        visibility: Option<TypeVisibility>,
+        alias: TypeAlias,
+    },
+    /// `impl Type { ... }`, or `impl Trait for Type { ... }`.
+    Impl {
+        r#trait: Option<String>,
+        ident: String,
+        methods: Vec<FunctionNode>,
+    },
+    /// `trait Name { ... }`. The bodies of its methods are empty.
+    Trait {
+        ident: String,
+        methods: Vec<FunctionNode>,
+    },
+    Function(FunctionNode),
+    /// An anonymous function value (`fn(int x) -> int { ... }`). Its ident is empty.
+    Closure(FunctionNode),
+    Return(Box<Node>),
+    /// `break`, or `break 'label` (without the `'`).
+    Break(Option<String>),
+    /// `continue`.
+    Continue,
+    ForLoop {
+        /// `'label:` (without the `'`).
+        label: Option<String>,
+        bindings: Vec<Binding>,
+        iterator: Box<Node>,
+        body: Vec<Node>,
+    },
+    /// `for (i) in start..stop {}` or `for (i) in start, stop, step {}`.
+    NumericForLoop {
+        label: Option<String>,
+        binding: Binding,
+        start: Box<Node>,
+        stop: Box<Node>,
+        step: Option<Box<Node>>,
+        body: Vec<Node>,
+    },
+    WhileLoop {
+        label: Option<String>,
+        condition: Box<Node>,
+        body: Vec<Node>,
+    },
+    /// `repeat {} until condition`.
+    RepeatLoop {
+        label: Option<String>,
+        body: Vec<Node>,
+        condition: Box<Node>,
+    },
+    /// An `if` and all of its `else if`/`else` branches.
+    Conditional {
+        branches: Vec<Branch>,
+    },
+    /// `match value { pattern => {} }`. The condition of each branch is its
+    /// pattern (`None` for `_`).
+    Match {
+        scrutinee: Box<Node>,
+        arms: Vec<Branch>,
+    },
+    /// A variable declaration.
+    Variable {
+        visibility: Option<TypeVisibility>,
+        mutability: Option<MutabilityModifier>,
+        /// Empty for `let` declarations (the type is inferred).
+        r#type: Type,
+        ident: String,
+        /// `None` for declarations without a value.
+        value: Option<Box<Node>>,
+        /// `@align(N)`
+        align: Option<usize>,
+        /// `@size(N)`
+        size: Option<usize>,
+    },
+    /// Several variables declared in one statement (`int a, b = 1, 2`).
+    Variables {
+        visibility: Option<TypeVisibility>,
+        mutability: Option<MutabilityModifier>,
+        r#type: Type,
+        idents: Vec<String>,
+        values: Vec<Node>,
+    },
+    Reassignment {
+        ident: String,
+        value: Box<Node>,
+    },
+    Call(Call),
+    Block(Vec<Node>),
+    Identifier(String),
+    /// A string literal (including its quotes).
+    String(String),
+    Integer(String),
+    Float(String),
+    Boolean(bool),
+    Nil,
+    /// `key = value`
+    Item {
+        key: String,
+        value: Box<Node>,
+    },
+    Table(Vec<Node>),
+    /// `Type { field: value; }`
+    StructValue {
+        r#type: Type,
+        fields: Vec<(String, Node)>,
+    },
+    /// `(lhs operator rhs)` where `operator` is a comparison (or `and`/`or`).
+    Comparison {
+        lhs: Box<Node>,
+        operator: String,
+        rhs: Box<Node>,
+    },
+    /// `(lhs operator rhs)` where `operator` is arithmetic.
+    Mathematical {
+        lhs: Box<Node>,
+        operator: String,
+        rhs: Box<Node>,
+    },
+    /// `(value as Type)`.
+    Cast {
+        value: Box<Node>,
+        r#type: Type,
+    },
+}
+
+/// A function call (`ident(...)`), awaited if prefixed with `#`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Call {
+    pub ident: String,
+    pub awaited: bool,
+    pub arguments: Vec<Node>,
+}
+
+/// A function or method definition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionNode {
+    pub ident: String,
+    pub visibility: Option<TypeVisibility>,
+    pub execution: ExecutionType,
+    pub association: AssociationType,
+    /// `const fn`
+    pub constant: bool,
+    /// `@export("name")`
+    pub export_name: Option<String>,
+    /// `fn ident<T, U>(...)`
+    pub generics: Vec<String>,
+    pub arguments: FunctionArguments,
+    pub return_type: Type,
+    pub body: Vec<Node>,
+}
+
+/// A (possibly typed) loop binding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Binding {
+    pub r#type: Option<Type>,
+    pub ident: String,
+}
+
+/// A single branch of a [`Node::Conditional`]. `condition` is empty for `else`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    pub condition: Option<Node>,
+    pub body: Vec<Node>,
+}
+
+/// Parse the given source into a list of top-level [`Node`]s.
+pub fn parse_to_ast(src: &str) -> Result<Vec<Node>, Box<Error<Rule>>> {
+    let parsed = FaradayParser::parse(Rule::document, src)?
+        .next()
+        .unwrap()
+        .into_inner();
+
+    Ok(nodes(parsed))
+}
+
+/// Convert all pairs in `input` into [`Node`]s.
+pub fn nodes(input: Pairs<'_, Rule>) -> Vec<Node> {
+    input
+        .filter(|pair| pair.as_rule() != Rule::EOI)
+        .map(Node::from)
+        .collect()
+}
+
+/// Get the name of a loop label (without the `'`).
+fn label(pair: Pair<'_, Rule>) -> String {
+    pair.as_str()[1..].to_string()
+}
+
+/// Get the operator between the two operands of a binary expression.
+pub(crate) fn operator(
+    pair: &Pair<'_, Rule>,
+    lhs: &Pair<'_, Rule>,
+    rhs: &Pair<'_, Rule>,
+) -> String {
+    let start = pair.as_span().start();
+    pair.as_str()[lhs.as_span().end() - start..rhs.as_span().start() - start]
+        .trim()
+        .to_string()
+}
+
+/// Get the `(lhs, operator, rhs)` of an ordered binary expression.
+fn binary(pair: Pair<'_, Rule>) -> (Box<Node>, String, Box<Node>) {
+    // ordered_* -> comparison/mathematical
+    let expression = pair.into_inner().next().unwrap();
+    let mut inner = expression.clone().into_inner();
+
+    let lhs = inner.next().unwrap();
+    let rhs = inner.next().unwrap();
+    let operator = operator(&expression, &lhs, &rhs);
+
+    (Box::new(lhs.into()), operator, Box::new(rhs.into()))
+}
+
+impl From<Pair<'_, Rule>> for Call {
+    fn from(value: Pair<'_, Rule>) -> Self {
+        let mut inner = value.into_inner();
+        let ident = inner.next().unwrap().as_str();
+
+        Self {
+            ident: ident.trim_start_matches('#').to_string(),
+            awaited: ident.starts_with('#'),
+            arguments: nodes(inner),
+        }
+    }
+}
+
+impl From<Pair<'_, Rule>> for FunctionNode {
+    fn from(value: Pair<'_, Rule>) -> Self {
+        let mut function = Self {
+            ident: String::new(),
+            visibility: None,
+            execution: ExecutionType::Sync,
+            association: AssociationType::None,
+            constant: false,
+            export_name: None,
+            generics: Vec::new(),
+            arguments: FunctionArguments {
+                keys: Vec::new(),
+                types: Vec::new(),
+                variadic: false,
+                optional: 0,
+            },
+            return_type: Type::default(),
+            body: Vec::new(),
+        };
+
+        for pair in value.into_inner() {
+            match pair.as_rule() {
+                Rule::shadow_attribute => continue,
+                Rule::export_attribute => {
+                    let name = pair.into_inner().next().unwrap().as_str();
+                    function.export_name = Some(name.replace("\"", ""));
+                }
+                Rule::identifier => function.ident = pair.as_str().to_string(),
+                Rule::type_modifier => function.visibility = Some(pair.into()),
+                Rule::const_modifier => function.constant = true,
+                Rule::sync_modifier => function.execution = pair.into(),
+                Rule::method_modifier => function.association = pair.into(),
+                Rule::generic => {
+                    function.generics = pair.into_inner().map(|p| p.as_str().to_string()).collect()
+                }
+                Rule::typed_parameter => {
+                    let mut inner = pair.into_inner();
+                    function.arguments.types.push(inner.next().unwrap().into());
+                    function
+                        .arguments
+                        .keys
+                        .push(inner.next().unwrap().as_str().to_string());
+                }
+                Rule::r#type => function.return_type = pair.into(),
+                Rule::block => function.body = nodes(pair.into_inner()),
+                _ => unreachable!("reached impossible rule in function node"),
+            }
+        }
+
+        function
+    }
+}
+
+impl From<Pair<'_, Rule>> for Branch {
+    fn from(value: Pair<'_, Rule>) -> Self {
+        let mut condition = None;
+        let mut body = Vec::new();
+
+        for pair in value.into_inner() {
+            match pair.as_rule() {
+                Rule::block => body = nodes(pair.into_inner()),
+                // nested branches are flattened by `Node::Conditional`
+                Rule::conditional_elseif | Rule::conditional_else => continue,
+                _ => condition = Some(pair.into()),
+            }
+        }
+
+        Self { condition, body }
+    }
+}
+
+impl From<Pair<'_, Rule>> for Node {
+    fn from(value: Pair<'_, Rule>) -> Self {
+        let visibility = value
+            .clone()
+            .into_inner()
+            .find(|p| p.as_rule() == Rule::type_modifier)
+            .map(TypeVisibility::from);
+
+        match value.as_rule() {
+            Rule::r#macro => Node::Macro {
+                module: false,
+                call: value.into_inner().next().unwrap().into(),
+            },
+            Rule::module_macro => Node::Macro {
+                module: true,
+                call: value.into_inner().next().unwrap().into(),
+            },
+            Rule::r#use => {
+                let mut inner = value
+                    .into_inner()
+                    .filter(|p| p.as_rule() != Rule::type_modifier);
+
+                Node::Use {
+                    visibility,
+                    path: inner.next().unwrap().as_str().replace("\"", ""),
+                    ident: inner.next().unwrap().as_str().to_string(),
+                }
+            }
+            Rule::r#struct => Node::Struct {
+                visibility,
+                r#type: value.into(),
+            },
+            Rule::r#enum => Node::Enum {
+                visibility,
+                r#type: value.into(),
+            },
+            Rule::type_alias => Node::TypeAlias {
+                visibility,
+                alias: value.into(),
+            },
+            Rule::r#impl => {
+                let mut inner = value.into_inner().peekable();
+
+                Node::Impl {
+                    r#trait: inner
+                        .next_if(|pair| pair.as_rule() == Rule::impl_trait)
+                        .map(|pair| pair.as_str().to_string()),
+                    ident: inner.next().unwrap().as_str().to_string(),
+                    methods: inner
+                        .next()
+                        .unwrap()
+                        .into_inner()
+                        .map(FunctionNode::from)
+                        .collect(),
+                }
+            }
+            Rule::r#trait => {
+                let mut inner = value.into_inner();
+
+                Node::Trait {
+                    ident: inner.next().unwrap().as_str().to_string(),
+                    methods: inner.map(FunctionNode::from).collect(),
+                }
+            }
+            Rule::function => Node::Function(value.into()),
+            Rule::closure => Node::Closure(value.into()),
+            Rule::r#return => Node::Return(Box::new(value.into_inner().next().unwrap().into())),
+            Rule::r#break => Node::Break(value.into_inner().next().map(label)),
+            Rule::r#continue => Node::Continue,
+            Rule::for_loop => {
+                // the label, bindings, then the iterator, then the body
+                let mut inner: Vec<Pair<'_, Rule>> = value.into_inner().collect();
+                let label = match inner.first() {
+                    Some(pair) if pair.as_rule() == Rule::loop_label => {
+                        Some(self::label(inner.remove(0)))
+                    }
+                    _ => None,
+                };
+                let body = nodes(inner.pop().unwrap().into_inner());
+                let iterator = Box::new(inner.pop().unwrap().into());
+
+                let bindings = inner
+                    .into_iter()
+                    .map(|pair| match pair.as_rule() {
+                        Rule::typed_parameter => {
+                            let mut inner = pair.into_inner();
+
+                            Binding {
+                                r#type: Some(inner.next().unwrap().into()),
+                                ident: inner.next().unwrap().as_str().to_string(),
+                            }
+                        }
+                        _ => Binding {
+                            r#type: None,
+                            ident: pair.as_str().to_string(),
+                        },
+                    })
+                    .collect();
+
+                Node::ForLoop {
+                    label,
+                    bindings,
+                    iterator,
+                    body,
+                }
+            }
+            Rule::numeric_for => {
+                let mut inner = value.into_inner().peekable();
+                let label = inner
+                    .next_if(|pair| pair.as_rule() == Rule::loop_label)
+                    .map(self::label);
+
+                let binding = inner.next().unwrap();
+                let binding = match binding.as_rule() {
+                    Rule::typed_parameter => {
+                        let mut inner = binding.into_inner();
+
+                        Binding {
+                            r#type: Some(inner.next().unwrap().into()),
+                            ident: inner.next().unwrap().as_str().to_string(),
+                        }
+                    }
+                    _ => Binding {
+                        r#type: None,
+                        ident: binding.as_str().to_string(),
+                    },
+                };
+
+                let mut bounds = inner.next().unwrap().into_inner().map(|pair| {
+                    Box::new(match pair.as_rule() {
+                        Rule::range_bound if pair.as_str().parse::<i64>().is_ok() => {
+                            Node::Integer(pair.as_str().to_string())
+                        }
+                        Rule::range_bound => Node::Identifier(pair.as_str().to_string()),
+                        _ => pair.into(),
+                    })
+                });
+
+                Node::NumericForLoop {
+                    label,
+                    binding,
+                    start: bounds.next().unwrap(),
+                    stop: bounds.next().unwrap(),
+                    step: bounds.next(),
+                    body: nodes(inner.next().unwrap().into_inner()),
+                }
+            }
+            Rule::while_loop => {
+                let mut inner = value.into_inner().peekable();
+                let label = inner
+                    .next_if(|pair| pair.as_rule() == Rule::loop_label)
+                    .map(label);
+
+                Node::WhileLoop {
+                    label,
+                    condition: Box::new(inner.next().unwrap().into()),
+                    body: nodes(inner.next().unwrap().into_inner()),
+                }
+            }
+            Rule::repeat_loop => {
+                let mut inner = value.into_inner().peekable();
+                let label = inner
+                    .next_if(|pair| pair.as_rule() == Rule::loop_label)
+                    .map(label);
+
+                Node::RepeatLoop {
+                    label,
+                    body: nodes(inner.next().unwrap().into_inner()),
+                    condition: Box::new(inner.next().unwrap().into()),
+                }
+            }
+            Rule::conditional => {
+                let mut branches = vec![Branch::from(value.clone())];
+
+                for pair in value.into_inner() {
+                    if matches!(
+                        pair.as_rule(),
+                        Rule::conditional_elseif | Rule::conditional_else
+                    ) {
+                        branches.push(pair.into());
+                    }
+                }
+
+                Node::Conditional { branches }
+            }
+            Rule::r#match => {
+                let mut inner = value.into_inner();
+                let scrutinee = Box::new(inner.next().unwrap().into());
+
+                let arms = inner
+                    .map(|arm| {
+                        let mut inner = arm.into_inner();
+                        let pattern = inner.next().unwrap();
+
+                        Branch {
+                            condition: match pattern.as_rule() {
+                                Rule::match_wildcard => None,
+                                _ => Some(pattern.into()),
+                            },
+                            body: nodes(inner.next().unwrap().into_inner()),
+                        }
+                    })
+                    .collect();
+
+                Node::Match { scrutinee, arms }
+            }
+            Rule::pair => {
+                let mut mutability = None;
+                let mut r#type = Type::default();
+                let mut ident = String::new();
+                let mut value_node = None;
+                let mut align = None;
+                let mut size = None;
+
+                for pair in value.into_inner() {
+                    match pair.as_rule() {
+                        Rule::variable_attribute => {
+                            let mut inner = pair.into_inner();
+                            let name = inner.next().unwrap().as_str();
+                            let value = inner.next().unwrap().as_str().parse().ok();
+
+                            if name == "align" {
+                                align = value;
+                            } else {
+                                size = value;
+                            }
+                        }
+                        Rule::type_modifier => continue,
+                        Rule::mutability_modifier => mutability = Some(pair.into()),
+                        Rule::r#type => r#type = pair.into(),
+                        Rule::let_type => continue,
+                        Rule::identifier if ident.is_empty() => ident = pair.as_str().to_string(),
+                        _ => value_node = Some(Box::new(pair.into())),
+                    }
+                }
+
+                Node::Variable {
+                    visibility,
+                    mutability,
+                    r#type,
+                    ident,
+                    value: value_node,
+                    align,
+                    size,
+                }
+            }
+            Rule::multi_pair => {
+                let mut mutability = None;
+                let mut r#type = Type::default();
+                let mut idents = Vec::new();
+                let mut values = Vec::new();
+
+                for pair in value.into_inner() {
+                    match pair.as_rule() {
+                        Rule::type_modifier => continue,
+                        Rule::mutability_modifier => mutability = Some(pair.into()),
+                        Rule::r#type => r#type = pair.into(),
+                        Rule::let_type => continue,
+                        Rule::multi_ident => {
+                            idents = pair.into_inner().map(|p| p.as_str().to_string()).collect()
+                        }
+                        _ => values.push(pair.into()),
+                    }
+                }
+
+                Node::Variables {
+                    visibility,
+                    mutability,
+                    r#type,
+                    idents,
+                    values,
+                }
+            }
+            Rule::reassignment => {
+                let mut inner = value.into_inner();
+
+                Node::Reassignment {
+                    ident: inner.next().unwrap().as_str().to_string(),
+                    value: Box::new(inner.next().unwrap().into()),
+                }
+            }
+            Rule::call => Node::Call(value.into()),
+            Rule::block => Node::Block(nodes(value.into_inner())),
+            Rule::identifier => Node::Identifier(value.as_str().to_string()),
+            Rule::string => Node::String(value.as_str().to_string()),
+            Rule::integer => Node::Integer(value.as_str().to_string()),
+            Rule::float => Node::Float(value.as_str().to_string()),
+            Rule::boolean => Node::Boolean(value.as_str() == "true"),
+            Rule::nil => Node::Nil,
+            Rule::item => {
+                let mut inner = value.into_inner();
+
+                Node::Item {
+                    key: inner.next().unwrap().as_str().to_string(),
+                    value: Box::new(inner.next().unwrap().into()),
+                }
+            }
+            Rule::table => Node::Table(nodes(value.into_inner())),
+            Rule::struct_value => {
+                let mut inner = value.into_inner();
+                let r#type = inner.next().unwrap().into();
+                let mut fields = Vec::new();
+
+                for pair in inner {
+                    let mut inner = pair.into_inner();
+
+                    fields.push((
+                        inner.next().unwrap().as_str().to_string(),
+                        inner.next().unwrap().into(),
+                    ));
+                }
+
+                Node::StructValue { r#type, fields }
+            }
+            Rule::ordered_comparison => {
+                let (lhs, operator, rhs) = binary(value);
+                Node::Comparison { lhs, operator, rhs }
+            }
+            Rule::ordered_mathematical => {
+                let (lhs, operator, rhs) = binary(value);
+                Node::Mathematical { lhs, operator, rhs }
+            }
+            Rule::ordered_cast => {
+                let mut inner = value.into_inner();
+
+                Node::Cast {
+                    value: Box::new(inner.next().unwrap().into()),
+                    r#type: inner.next().unwrap().into(),
+                }
+            }
+            rule => fcompiler_error!("cannot convert {rule:?} to a syntax tree node"),
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use crate::data::{Function, Type, TypeVisibility};
+use crate::data::{Function, Type, TypeVisibility, Variable};
 use std::collections::BTreeMap;
 use std::sync::LazyLock;
 
 pub const TYPE_NAME_EMPTY: &str = "void";
+pub const TYPE_NAME_EMPTY_ALT: &str = "#";
 pub const TYPE_NAME_ANY: &str = "any";
 pub const TYPE_NAME_INT: &str = "int";
 pub const TYPE_NAME_FLOAT: &str = "float";
@@ -10,7 +11,16 @@ pub const TYPE_NAME_NUMBER: &str = "number";
 pub const TYPE_NAME_BOOLEAN: &str = "bool";
 pub const TYPE_NAME_STRING: &str = "String";
 pub const TYPE_NAME_TABLE: &str = "Table";
+/// Several values returned at once (`(int, String)`), the generics are the types of
+/// the values.
+pub const TYPE_NAME_TUPLE: &str = "Tuple";
 pub const TYPE_NAME_REF: &str = "ref";
+/// The type of `nil`, which can only be assigned to optional types (`int?`).
+pub const TYPE_NAME_NIL: &str = "nil";
+/// The type of function values ([`crate::data::Closure`]s and functions passed as arguments).
+pub const TYPE_NAME_FUNCTION: &str = "Function";
+/// The type a method is implemented on (only available in `impl` blocks).
+pub const TYPE_NAME_SELF: &str = "Self";
 
 macro_rules! import_default_type {
     ($type_name:ident >> $map:ident) => {
@@ -29,22 +39,67 @@ macro_rules! import_default_type {
 }
 
 macro_rules! lua_builtin_fn {
+    // no parameters
+    ($fn_name:literal() -> $return_type:ident >> $map:ident) => {
+        lua_builtin_fn!(@ false, 0, $fn_name(;) -> $return_type >> $map);
+    };
     ($fn_name:literal($($names:expr),+ ; $($types:expr),+) -> $return_type:ident >> $map:ident) => {
+        lua_builtin_fn!(@ false, 0, $fn_name($($names),+ ; $($types),+) -> $return_type >> $map);
+    };
+    // the last parameter accepts any number of arguments
+    (variadic $fn_name:literal($($names:expr),+ ; $($types:expr),+) -> $return_type:ident >> $map:ident) => {
+        lua_builtin_fn!(@ true, 0, $fn_name($($names),+ ; $($types),+) -> $return_type >> $map);
+    };
+    // the last `$optional` parameters can be left out
+    (optional($optional:literal) $fn_name:literal($($names:expr),+ ; $($types:expr),+) -> $return_type:ident >> $map:ident) => {
+        lua_builtin_fn!(@ false, $optional, $fn_name($($names),+ ; $($types),+) -> $return_type >> $map);
+    };
+    (@ $variadic:literal, $optional:literal, $fn_name:literal($($names:expr),* ; $($types:expr),*) -> $return_type:ident >> $map:ident) => {
         $map.insert($fn_name.to_string(), crate::data::Function {
             ident: $fn_name.to_string(),
             arguments: $crate::data::FunctionArguments {
-                keys: vec![$($names.to_string()),+],
-                types: vec![$(Type::from(($types, TypeVisibility::Public))),+],
+                keys: vec![$($names.to_string()),*],
+                types: vec![$(builtin_type($types)),*],
+                variadic: $variadic,
+                optional: $optional,
             },
             return_type: $crate::data::Type::from($return_type),
             body: String::new(),
             visibility: $crate::data::TypeVisibility::Private,
             execution: $crate::data::ExecutionType::Sync,
-            association: $crate::data::AssociationType::Static
+            association: $crate::data::AssociationType::Static,
+            constant: false,
+            export_name: None,
+            generics: Vec::new()
         });
     };
 }
 
+macro_rules! lua_builtin_var {
+    ($var_name:literal: $type:ident >> $map:ident) => {
+        $map.insert(
+            $var_name.to_string(),
+            (
+                $var_name.to_string(),
+                Type::from(($type, TypeVisibility::Public)),
+            )
+                .into(),
+        );
+    };
+}
+
+/// Get a parameter type of a builtin. Builtin types keep their generics, so a
+/// `Table` parameter accepts any `Table<K, V>`.
+fn builtin_type(ident: &str) -> Type {
+    match TYPE_BINDINGS.get(ident) {
+        Some(t) => Type {
+            visibility: TypeVisibility::Public,
+            ..t.clone()
+        },
+        None => Type::from((ident, TypeVisibility::Public)),
+    }
+}
+
 pub static TYPE_BINDINGS: LazyLock<BTreeMap<String, Type>> = LazyLock::new(|| {
     let mut map = BTreeMap::default();
 
@@ -52,12 +107,17 @@ pub static TYPE_BINDINGS: LazyLock<BTreeMap<String, Type>> = LazyLock::new(|| {
     import_default_type!(TYPE_NAME_INT >> map);
     import_default_type!(TYPE_NAME_FLOAT >> map);
     import_default_type!(TYPE_NAME_NUMBER >> map);
+    import_default_type!(TYPE_NAME_BOOLEAN >> map);
 
     import_default_type!(TYPE_NAME_EMPTY >> map);
+    import_default_type!(TYPE_NAME_EMPTY_ALT >> map);
     import_default_type!(TYPE_NAME_ANY >> map);
     import_default_type!(TYPE_NAME_REF >> map);
+    import_default_type!(TYPE_NAME_NIL >> map);
+    import_default_type!(TYPE_NAME_FUNCTION >> map);
 
     import_default_type!(TYPE_NAME_TABLE("K", "V") >> map);
+    import_default_type!(TYPE_NAME_TUPLE >> map);
 
     map
 });
@@ -66,16 +126,65 @@ pub static FUNCTION_BINDINGS: LazyLock<BTreeMap<String, Function>> = LazyLock::n
     let mut map = BTreeMap::default();
 
     // misc
-    lua_builtin_fn!("print"("message"; TYPE_NAME_STRING) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!(variadic "print"("values"; "any") -> TYPE_NAME_STRING >> map);
     lua_builtin_fn!("tonumber"("value"; "any") -> TYPE_NAME_INT >> map);
     lua_builtin_fn!("tostring"("value"; "any") -> TYPE_NAME_STRING >> map);
+    // resolved by the compiler, see `FunctionCall`
+    lua_builtin_fn!("typeof"("value"; "any") -> TYPE_NAME_STRING >> map);
 
     // string
-    lua_builtin_fn!("String.format"("value", "value"; TYPE_NAME_STRING, "any") -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!(variadic "String.format"("format", "values"; TYPE_NAME_STRING, "any") -> TYPE_NAME_STRING >> map);
+    // also the methods of strings (`s:upper()`), see `Registers::get_fn`
+    lua_builtin_fn!(variadic "string.sub"("s", "i", "j"; TYPE_NAME_STRING, TYPE_NAME_INT, TYPE_NAME_INT) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!("string.len"("s"; TYPE_NAME_STRING) -> TYPE_NAME_INT >> map);
+    lua_builtin_fn!("string.upper"("s"; TYPE_NAME_STRING) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!("string.lower"("s"; TYPE_NAME_STRING) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!("string.rep"("s", "n"; TYPE_NAME_STRING, TYPE_NAME_INT) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!(variadic "string.find"("s", "pattern", "init"; TYPE_NAME_STRING, TYPE_NAME_STRING, TYPE_NAME_INT) -> TYPE_NAME_INT >> map);
+    lua_builtin_fn!("string.gsub"("s", "pattern", "repl"; TYPE_NAME_STRING, TYPE_NAME_STRING, "any") -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!(variadic "string.match"("s", "pattern", "init"; TYPE_NAME_STRING, TYPE_NAME_STRING, TYPE_NAME_INT) -> TYPE_NAME_STRING >> map);
+
+    // table
+    lua_builtin_fn!(variadic "table.insert"("t", "values"; TYPE_NAME_TABLE, "any") -> TYPE_NAME_EMPTY >> map);
+    lua_builtin_fn!(variadic "table.remove"("t", "pos"; TYPE_NAME_TABLE, TYPE_NAME_INT) -> TYPE_NAME_EMPTY >> map);
+    lua_builtin_fn!(variadic "table.concat"("t", "sep"; TYPE_NAME_TABLE, TYPE_NAME_STRING) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!(variadic "table.sort"("t", "comp"; TYPE_NAME_TABLE, TYPE_NAME_FUNCTION) -> TYPE_NAME_EMPTY >> map);
+
+    // math
+    lua_builtin_fn!("math.floor"("x"; TYPE_NAME_NUMBER) -> TYPE_NAME_INT >> map);
+    lua_builtin_fn!("math.ceil"("x"; TYPE_NAME_NUMBER) -> TYPE_NAME_INT >> map);
+    lua_builtin_fn!("math.abs"("x"; TYPE_NAME_NUMBER) -> TYPE_NAME_NUMBER >> map);
+    lua_builtin_fn!("math.sqrt"("x"; TYPE_NAME_NUMBER) -> TYPE_NAME_FLOAT >> map);
+    lua_builtin_fn!(variadic "math.max"("x", "values"; TYPE_NAME_NUMBER, TYPE_NAME_NUMBER) -> TYPE_NAME_NUMBER >> map);
+    lua_builtin_fn!(variadic "math.min"("x", "values"; TYPE_NAME_NUMBER, TYPE_NAME_NUMBER) -> TYPE_NAME_NUMBER >> map);
+    // a float without arguments, an int with them
+    lua_builtin_fn!(optional(2) "math.random"("m", "n"; TYPE_NAME_INT, TYPE_NAME_INT) -> TYPE_NAME_NUMBER >> map);
+
+    // os
+    lua_builtin_fn!(optional(1) "os.time"("t"; TYPE_NAME_TABLE) -> TYPE_NAME_INT >> map);
+    lua_builtin_fn!("os.clock"() -> TYPE_NAME_FLOAT >> map);
+    lua_builtin_fn!(optional(2) "os.date"("format", "time"; TYPE_NAME_STRING, TYPE_NAME_INT) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!("os.getenv"("name"; TYPE_NAME_STRING) -> TYPE_NAME_STRING >> map);
+    lua_builtin_fn!(optional(1) "os.exit"("code"; "any") -> TYPE_NAME_EMPTY >> map);
 
     // io
-    lua_builtin_fn!("io.read"("_" ; "empty") -> TYPE_NAME_EMPTY >> map);
-    lua_builtin_fn!("io.write"("message"; "string") -> TYPE_NAME_EMPTY >> map);
+    lua_builtin_fn!(variadic "io.read"("formats"; "any") -> TYPE_NAME_EMPTY >> map);
+    lua_builtin_fn!(variadic "io.write"("values"; "any") -> TYPE_NAME_EMPTY >> map);
+    // files are userdata, which can't be typed
+    lua_builtin_fn!(optional(1) "io.open"("filename", "mode"; TYPE_NAME_STRING, TYPE_NAME_STRING) -> TYPE_NAME_ANY >> map);
+    lua_builtin_fn!(optional(1) "io.close"("file"; "any") -> TYPE_NAME_EMPTY >> map);
+    lua_builtin_fn!(variadic "io.lines"("filename", "formats"; TYPE_NAME_STRING, "any") -> TYPE_NAME_ANY >> map);
+
+    // ...
+    map
+});
+
+pub static VARIABLE_BINDINGS: LazyLock<BTreeMap<String, Variable>> = LazyLock::new(|| {
+    let mut map = BTreeMap::default();
+
+    // math
+    lua_builtin_var!("math.pi": TYPE_NAME_FLOAT >> map);
+    lua_builtin_var!("math.huge": TYPE_NAME_FLOAT >> map);
 
     // ...
     map
@@ -1,16 +1,28 @@
 use crate::{
     bindings::{
-        FUNCTION_BINDINGS, TYPE_BINDINGS, TYPE_NAME_ANY, TYPE_NAME_REF, TYPE_NAME_STRING,
-        TYPE_NAME_TABLE,
+        FUNCTION_BINDINGS, TYPE_BINDINGS, TYPE_NAME_ANY, TYPE_NAME_FLOAT, TYPE_NAME_FUNCTION,
+        TYPE_NAME_INT, TYPE_NAME_REF, TYPE_NAME_SELF, TYPE_NAME_STRING, TYPE_NAME_TABLE,
+        TYPE_NAME_TUPLE, VARIABLE_BINDINGS,
     },
-    data::{Function, FunctionCall, Type, Variable},
+    config::{COMPILER_TEMPLATES, CompilerConfig},
+    data::{
+        AssociationType, ExecutionType, Function, FunctionArguments, FunctionCall, Trait, Type,
+        TypeVisibility, Variable,
+    },
+    scope::Scope,
 };
 use parser::Rule;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fmt::Display};
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    sync::{Arc, LazyLock, Mutex, RwLock},
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompilerError {
     InvalidGenericCount,
+    InvalidArgumentCount,
     ExpectedReference,
     NoReturnReference,
     CannotAssignConst,
@@ -20,6 +32,27 @@ pub enum CompilerError {
     NoSuchVariant,
     InvalidType,
     NoSuchType,
+    AsyncNotAwaited,
+    CannotAwaitSync,
+    IgnoredAttribute,
+    NoSuchLabel,
+    DuplicateLabel,
+    AssertionFailed,
+    NotConstant,
+    ConstWithoutValue,
+    Uninitialized,
+    MissingField,
+    ShadowedBuiltin,
+    NoSuchTemplate,
+    PrivateMethod,
+    IndexBelowOne,
+    CircularImport,
+    PrivateAccess,
+    InvalidConfig,
+    MissingReturn,
+    NoSuchTrait,
+    MissingMethod,
+    DuplicateTrait,
     Unknown,
 }
 
@@ -28,6 +61,7 @@ impl Display for CompilerError {
         use CompilerError::*;
         write!(f, "{}", match self {
             InvalidGenericCount => "invalid generic count",
+            InvalidArgumentCount => "invalid argument count",
             ExpectedReference => "expected reference, got copy",
             NoReturnReference => "cannot return reference to variable",
             CannotAssignConst => "cannot assign to constant variable",
@@ -37,11 +71,218 @@ impl Display for CompilerError {
             NoSuchVariant => "no such variant in enum",
             InvalidType => "invalid type for operation",
             NoSuchType => "no such type id found in registers",
+            AsyncNotAwaited => "async function must be awaited (call it with `#`)",
+            CannotAwaitSync => "cannot await a function which isn't async",
+            IgnoredAttribute => "layout attributes are ignored by the lua backend",
+            NoSuchLabel => "no enclosing loop has this label",
+            DuplicateLabel => "label is already used by an enclosing loop",
+            AssertionFailed => "static assertion failed",
+            NotConstant => "expression can't be evaluated at compile time",
+            ConstWithoutValue => "constant variables need a value (use `mut` to assign it later)",
+            Uninitialized => "variable may be read before it's assigned",
+            MissingField => "struct field has no value and no default",
+            ShadowedBuiltin => "declaration replaces a built-in (mark it `@shadow` if intended)",
+            NoSuchTemplate => "no such type template in the compiler config",
+            PrivateMethod => "private methods can only be called from their type's impl blocks",
+            IndexBelowOne => "lua tables start at index 1",
+            CircularImport => "module imports itself (through the given imports)",
+            PrivateAccess => "private functions can only be called from their own module",
+            InvalidConfig => "invalid compiler config",
+            MissingReturn => "function has a return type, but never returns",
+            NoSuchTrait => "no such trait found in registers",
+            MissingMethod => "impl doesn't match its trait (missing method or different signature)",
+            DuplicateTrait => "trait is already defined in this module",
             Unknown => "unknown compiler error",
         })
     }
 }
 
+/// The format diagnostics are reported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Colored text for terminals.
+    Human,
+    /// A JSON array of [`Diagnostic`]s (printed when compilation stops).
+    Json,
+}
+
+pub static COMPILER_MESSAGE_FORMAT: LazyLock<RwLock<MessageFormat>> =
+    LazyLock::new(|| RwLock::new(MessageFormat::Human));
+
+/// If errors should unwind (with the [`Diagnostic`] as the payload) instead of
+/// exiting the process, so they can be recovered from with [`std::panic::catch_unwind`].
+pub static COMPILER_RECOVERABLE: LazyLock<RwLock<bool>> = LazyLock::new(|| RwLock::new(false));
+
+/// Every [`Diagnostic`] reported during this compilation.
+pub static COMPILER_DIAGNOSTICS: LazyLock<Mutex<Vec<Diagnostic>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// A module imported with `use` (or `expr_use`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Import {
+    /// The file containing the `use` statement.
+    pub file: String,
+    /// The identifier the module's items are prefixed with (`ident.Item`).
+    pub ident: String,
+    /// The path of the imported file.
+    pub path: String,
+    /// The location of the `use` statement (`file:line:col`).
+    pub location: String,
+}
+
+/// Every [`Import`] made during this compilation.
+///
+/// Diagnostics mentioning an imported item (`ident.Item`) are given a note saying
+/// where the module was imported from.
+pub static COMPILER_IMPORTS: LazyLock<Mutex<Vec<Import>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Record an [`Import`] of the given file in the file at the current [`crate::COMPILER_MARKER`].
+pub fn fcompiler_record_import(ident: String, path: String) {
+    let location = match crate::COMPILER_MARKER.lock() {
+        Ok(m) => m.0.clone(),
+        Err(_) => String::new(),
+    };
+
+    let import = Import {
+        file: split_marker(&location).0,
+        ident,
+        path,
+        location,
+    };
+
+    match COMPILER_IMPORTS.lock() {
+        Ok(mut w) => w.push(import),
+        Err(_) => COMPILER_IMPORTS.clear_poison(),
+    }
+}
+
+/// Check if `message` mentions an item prefixed with `ident.`.
+fn mentions_import(message: &str, ident: &str) -> bool {
+    let prefix = format!("{ident}.");
+
+    message.match_indices(&prefix).any(|(i, _)| {
+        // make sure this isn't the end of a longer identifier
+        match message[..i].chars().last() {
+            Some(c) => !(c.is_alphanumeric() || (c == '_') || (c == '.') || (c == ':')),
+            None => true,
+        }
+    })
+}
+
+/// The severity of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A compiler error or warning as data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// The name of the [`CompilerError`] which caused this diagnostic.
+    pub code: String,
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub col_start: usize,
+    pub line_end: usize,
+    pub col_end: usize,
+    /// Extra context, such as where imported items mentioned in the message come from.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Create a new [`Diagnostic`] located at the current [`crate::COMPILER_MARKER`].
+    pub fn new(code: CompilerError, severity: Severity, message: String) -> Self {
+        let marker = match crate::COMPILER_MARKER.lock() {
+            Ok(m) => m.clone(),
+            Err(_) => (String::new(), String::new()),
+        };
+
+        let (file, line, col_start) = split_marker(&marker.0);
+        let (_, line_end, col_end) = split_marker(&marker.1);
+
+        let notes = match COMPILER_IMPORTS.lock() {
+            Ok(imports) => imports
+                .iter()
+                .filter(|i| (i.file == file) && mentions_import(&message, &i.ident))
+                .map(|i| {
+                    format!(
+                        "`{}` is imported from {} (at {})",
+                        i.ident, i.path, i.location
+                    )
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Self {
+            code: format!("{code:?}"),
+            severity,
+            message,
+            file,
+            line,
+            col_start,
+            line_end,
+            col_end,
+            notes,
+        }
+    }
+
+    /// The source line this diagnostic starts on, with the span underlined.
+    ///
+    /// Spans over multiple lines are underlined until the end of the first line.
+    /// `None` if the file can't be read (like source which isn't in a file).
+    pub fn snippet(&self) -> Option<String> {
+        let source = std::fs::read_to_string(&self.file).ok()?;
+        let line = source.lines().nth(self.line.checked_sub(1)?)?;
+
+        let start = self.col_start.max(1) - 1;
+        let end = if self.line_end == self.line {
+            (self.col_end.max(1) - 1).max(start + 1)
+        } else {
+            line.chars().count().max(start + 1)
+        };
+
+        // keep tabs so the caret lines up with the source
+        let padding: String = line
+            .chars()
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        Some(format!(
+            "    \x1b[2m{gutter} |\x1b[0m
+    \x1b[2m{number} |\x1b[0m {line}
+    \x1b[2m{gutter} |\x1b[0m {padding}\x1b[31;1m{}\x1b[0m",
+            "^".repeat(end - start)
+        ))
+    }
+
+    /// The full span of this diagnostic (`file:line:col-line:col`).
+    pub fn span(&self) -> String {
+        format!(
+            "{}:{}:{}-{}:{}",
+            self.file, self.line, self.col_start, self.line_end, self.col_end
+        )
+    }
+}
+
+/// Split a `file:line:col` marker into its parts.
+pub(crate) fn split_marker(marker: &str) -> (String, usize, usize) {
+    let mut split = marker.rsplitn(3, ":");
+    let col = split.next().unwrap_or("0").parse::<usize>().unwrap_or(0);
+    let line = split.next().unwrap_or("0").parse::<usize>().unwrap_or(0);
+    let file = split.next().unwrap_or("").to_string();
+    (file, line, col)
+}
+
 pub fn fcompiler_error_print(args: std::fmt::Arguments) -> String {
     let string = if let Some(s) = args.as_str() {
         s.to_string()
@@ -52,46 +293,150 @@ pub fn fcompiler_error_print(args: std::fmt::Arguments) -> String {
     return string;
 }
 
-#[macro_export]
-macro_rules! fcompiler_error {
-    ($($arg:tt)*) => {
-        {
-            let marker = $crate::COMPILER_MARKER.lock().unwrap();
+/// Store the given [`Diagnostic`] and print it (in [`MessageFormat::Human`]).
+pub fn fcompiler_report(diagnostic: Diagnostic) {
+    if *COMPILER_MESSAGE_FORMAT.read().unwrap() == MessageFormat::Human {
+        let label = match diagnostic.severity {
+            Severity::Error => "\x1b[31;1merror:",
+            Severity::Warning => "\x1b[33;1mwarning:",
+        };
+
+        // highlight the error category
+        let message = match diagnostic.message.split_once(": ") {
+            Some((category, rest)) if diagnostic.code != "Unknown" => {
+                format!("\x1b[93m{category}:\x1b[0m {rest}")
+            }
+            _ => diagnostic.message.clone(),
+        };
+
+        println!(
+            "{label}\x1b[0m \x1b[1m{message}\x1b[0m\n    \x1b[2maround {}:{}:{} to {}:{}\x1b[0m",
+            diagnostic.file,
+            diagnostic.line,
+            diagnostic.col_start,
+            diagnostic.line_end,
+            diagnostic.col_end
+        );
 
-            println!(
-                "\x1b[31;1merror:\x1b[0m \x1b[1m{}\x1b[0m\n    \x1b[2maround {}\x1b[0m\n    \x1b[2mto {}\x1b[0m",
-                $crate::checking::fcompiler_error_print(std::format_args!($($arg)*)),
-                marker.0,
-                marker.1
-            );
+        if let Some(snippet) = diagnostic.snippet() {
+            println!("{snippet}");
+        }
 
-            std::process::exit(1);
+        for note in &diagnostic.notes {
+            println!("    \x1b[36;1mnote:\x1b[0m {note}");
         }
     }
+
+    match COMPILER_DIAGNOSTICS.lock() {
+        Ok(mut w) => w.push(diagnostic),
+        Err(_) => COMPILER_DIAGNOSTICS.clear_poison(),
+    }
+}
+
+/// Print every collected [`Diagnostic`] as JSON.
+pub fn fcompiler_print_json() {
+    let diagnostics = COMPILER_DIAGNOSTICS.lock().unwrap();
+    println!("{}", serde_json::to_string(&*diagnostics).unwrap());
+}
+
+/// Report an error [`Diagnostic`] and stop compiling.
+pub fn fcompiler_abort(diagnostic: Diagnostic) -> ! {
+    fcompiler_report(diagnostic.clone());
+
+    if *COMPILER_RECOVERABLE.read().unwrap() {
+        // `resume_unwind` skips the panic hook, the diagnostic was already reported.
+        // whoever recovers is in charge of printing json
+        std::panic::resume_unwind(Box::new(diagnostic));
+    }
+
+    if *COMPILER_MESSAGE_FORMAT.read().unwrap() == MessageFormat::Json {
+        fcompiler_print_json();
+    }
+
+    std::process::exit(1);
+}
+
+/// Run `f`, returning the error [`Diagnostic`] which stopped it (it's still reported)
+/// instead of exiting the process.
+pub fn fcompiler_recover<T>(f: impl FnOnce() -> T) -> Result<T, Box<Diagnostic>> {
+    let recoverable = std::mem::replace(&mut *COMPILER_RECOVERABLE.write().unwrap(), true);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    *COMPILER_RECOVERABLE.write().unwrap() = recoverable;
+
+    match result {
+        Ok(value) => Ok(value),
+        Err(payload) => match payload.downcast::<Diagnostic>() {
+            Ok(diagnostic) => Err(diagnostic),
+            // not a compiler error, so it's a bug in the compiler
+            Err(payload) => std::panic::resume_unwind(payload),
+        },
+    }
+}
+
+#[macro_export]
+macro_rules! fcompiler_error {
+    ($($arg:tt)*) => {
+        $crate::checking::fcompiler_abort($crate::checking::Diagnostic::new(
+            $crate::checking::CompilerError::Unknown,
+            $crate::checking::Severity::Error,
+            $crate::checking::fcompiler_error_print(std::format_args!($($arg)*)),
+        ))
+    }
 }
 
 #[macro_export]
 macro_rules! fcompiler_marker {
     ($($arg:tt)*) => {
-        println!("\x1b[36;1mmarker:\x1b[0m \x1b[1m{}\x1b[0m", $crate::checking::fcompiler_error_print(std::format_args!($($arg)*)))
+        if *$crate::checking::COMPILER_MESSAGE_FORMAT.read().unwrap() == $crate::checking::MessageFormat::Human {
+            println!("\x1b[36;1mmarker:\x1b[0m \x1b[1m{}\x1b[0m", $crate::checking::fcompiler_error_print(std::format_args!($($arg)*)))
+        }
     }
 }
 
 /// Create a type error.
 pub fn fcompiler_type_error(expected: String, received: String) -> ! {
-    fcompiler_error!(
-        "\x1b[93m{}:\x1b[0m expected \"{expected}\", received \"{received}\"",
-        CompilerError::InvalidType
-    )
+    fcompiler_abort(Diagnostic::new(
+        CompilerError::InvalidType,
+        Severity::Error,
+        format!(
+            "{}: expected \"{expected}\", received \"{received}\"",
+            CompilerError::InvalidType
+        ),
+    ))
 }
 
 /// Create a general error.
 pub fn fcompiler_general_error(error: CompilerError, additional: String) -> ! {
-    fcompiler_error!("\x1b[93m{error}:\x1b[0m {additional}")
+    fcompiler_abort(Diagnostic::new(
+        error,
+        Severity::Error,
+        format!("{error}: {additional}"),
+    ))
+}
+
+/// Create a general warning.
+pub fn fcompiler_general_warning(error: CompilerError, additional: String) {
+    fcompiler_report(Diagnostic::new(
+        error,
+        Severity::Warning,
+        format!("{error}: {additional}"),
+    ))
 }
 
 /// Create a general marker.
 pub fn fcompiler_general_marker(rule: Rule, start: (usize, usize), end: (usize, usize)) -> () {
+    // narrow the current marker down to this construct
+    match crate::COMPILER_MARKER.lock() {
+        Ok(mut w) => {
+            let (file, _, _) = split_marker(&w.0);
+            *w = (
+                format!("{file}:{}:{}", start.0, start.1),
+                format!("{file}:{}:{}", end.0, end.1),
+            )
+        }
+        Err(_) => crate::COMPILER_MARKER.clear_poison(),
+    }
+
     fcompiler_marker!(
         "\x1b[96;1m{:?}:\x1b[0m start \x1b[2m{start:?}\x1b[0m, end \x1b[2m{end:?}\x1b[0m",
         rule
@@ -100,7 +445,7 @@ pub fn fcompiler_general_marker(rule: Rule, start: (usize, usize), end: (usize,
 
 // traits
 pub trait ToSource {
-    fn transform(&self) -> String;
+    fn transform(&self, config: &CompilerConfig) -> String;
 }
 
 pub trait TypeChecking {
@@ -123,63 +468,290 @@ pub trait MultipleGenericChecking {
 /// Compiler state registers.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Registers {
-    pub types: BTreeMap<String, Type>,
-    pub functions: BTreeMap<String, Function>,
-    pub variables: BTreeMap<String, Variable>,
+    pub types: Scope<Type>,
+    pub functions: Scope<Function>,
+    pub variables: Scope<Variable>,
+    /// Traits (`trait Name { ... }`) which impls can implement.
+    #[serde(default)]
+    pub traits: Scope<Trait>,
+    /// Features enabled for `#[cfg(feature)]` pragmas.
+    pub features: BTreeSet<String>,
+    /// The visibility of items without a `pub`/`prv` modifier in the current module.
+    /// Set with `#![default_visibility(pub)]`.
+    pub default_visibility: TypeVisibility,
+    /// The labels of the loops enclosing the current block (innermost last).
+    /// Unlabeled loops are `None`.
+    pub loops: Vec<Option<String>>,
+    /// The label `continue` jumps to in the innermost loop (`None` outside of loops).
+    pub continue_label: Option<String>,
+    /// Every file imported while compiling, including the imports of imported files.
+    #[serde(default)]
+    pub dependencies: BTreeSet<std::path::PathBuf>,
+    /// The templates used to generate output. Defaults to a copy of [`COMPILER_TEMPLATES`]
+    /// when the registers are created, so each compilation can have its own config.
+    #[serde(skip, default = "default_config")]
+    pub config: Arc<CompilerConfig<'static>>,
 }
 
+fn default_config() -> Arc<CompilerConfig<'static>> {
+    Arc::new(COMPILER_TEMPLATES.read().unwrap().clone())
+}
+
+/// The items every [`Registers`] starts with (shared by all of them).
+static DEFAULT_TYPES: LazyLock<Scope<Type>> = LazyLock::new(|| TYPE_BINDINGS.clone().into());
+static DEFAULT_FUNCTIONS: LazyLock<Scope<Function>> =
+    LazyLock::new(|| FUNCTION_BINDINGS.clone().into());
+static DEFAULT_VARIABLES: LazyLock<Scope<Variable>> = LazyLock::new(|| {
+    let mut out = VARIABLE_BINDINGS.clone();
+
+    // defaults
+    out.insert(
+        "self".to_string(),
+        ("".to_string(), TYPE_NAME_TABLE.into()).into(),
+    );
+
+    out.insert(
+        "@@FARADAY_PATH".to_string(),
+        ("".to_string(), TYPE_NAME_STRING.into()).into(),
+    );
+
+    out.insert(
+        "@@FARADAY_NO_COMPILE".to_string(),
+        ("false".to_string(), TYPE_NAME_STRING.into()).into(),
+    );
+
+    out.insert(
+        "@@FARADAY_BARE_MODULE".to_string(),
+        ("false".to_string(), TYPE_NAME_STRING.into()).into(),
+    );
+
+    // return
+    out.into()
+});
+
 impl Default for Registers {
     fn default() -> Self {
         Self {
-            types: TYPE_BINDINGS.clone(),
-            functions: FUNCTION_BINDINGS.clone(),
-            variables: {
-                let mut out = BTreeMap::default();
-
-                // defaults
-                out.insert(
-                    "self".to_string(),
-                    ("".to_string(), TYPE_NAME_TABLE.into()).into(),
-                );
-
-                out.insert(
-                    "@@FARADAY_PATH".to_string(),
-                    ("".to_string(), TYPE_NAME_STRING.into()).into(),
-                );
-
-                out.insert(
-                    "@@FARADAY_NO_COMPILE".to_string(),
-                    ("false".to_string(), TYPE_NAME_STRING.into()).into(),
-                );
-
-                // return
-                out
-            },
+            types: DEFAULT_TYPES.clone(),
+            functions: DEFAULT_FUNCTIONS.clone(),
+            variables: DEFAULT_VARIABLES.clone(),
+            traits: Scope::default(),
+            features: BTreeSet::new(),
+            default_visibility: TypeVisibility::Private,
+            loops: Vec::new(),
+            continue_label: None,
+            dependencies: BTreeSet::new(),
+            config: default_config(),
         }
     }
 }
 
 impl Registers {
+    /// Create fresh [`Registers`] which keep the enabled features of `self`.
+    pub fn scoped(&self) -> Self {
+        Self {
+            features: self.features.clone(),
+            loops: self.loops.clone(),
+            continue_label: self.continue_label.clone(),
+            config: self.config.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Create default [`Registers`] which only keep the config of `self`.
+    pub fn fresh(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Clone `self` for a nested block, where the module's default visibility
+    /// doesn't apply.
+    pub fn nested(&self) -> Self {
+        Self {
+            default_visibility: TypeVisibility::Private,
+            ..self.clone()
+        }
+    }
+
+    /// Serialize every type, function, and variable (the config isn't included) as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Clone `self` for the body of a loop with the given label.
+    ///
+    /// Every loop gets its own [`Registers::continue_label`], so `continue` in a
+    /// nested loop never jumps to the end of an outer one.
+    pub fn looped(&self, label: Option<String>) -> Self {
+        if let Some(ref label) = label {
+            if self.loops.contains(&Some(label.to_owned())) {
+                fcompiler_general_error(CompilerError::DuplicateLabel, label.to_owned())
+            }
+        }
+
+        let mut out = self.nested();
+        out.loops.push(label);
+        out.continue_label = Some(format!("continue_{}", crate::tempfile::unique()));
+        out
+    }
+
+    /// Get the uninitialized variables of `self` which were assigned in `nested`
+    /// (the registers a nested block was processed with).
+    pub fn assigned_in(&self, nested: &Registers) -> BTreeSet<String> {
+        self.variables
+            .iter()
+            .filter(|(ident, var)| {
+                var.uninitialized
+                    && nested
+                        .variables
+                        .get(ident)
+                        .is_some_and(|v| !v.uninitialized)
+            })
+            .map(|(ident, _)| ident.to_owned())
+            .collect()
+    }
+
+    /// Mark the given variables as assigned.
+    pub fn mark_assigned(&mut self, idents: &BTreeSet<String>) {
+        for ident in idents {
+            if let Some(var) = self.variables.get_mut(ident) {
+                var.uninitialized = false;
+            }
+        }
+    }
+
     pub fn get_type(&self, key: &str) -> Type {
+        match self.find_type(key) {
+            Some(t) => t,
+            None => fcompiler_general_error(CompilerError::NoSuchType, key.to_string()),
+        }
+    }
+
+    /// Get the type named `key` (like [`Registers::get_type`]), or `None` if there's
+    /// no such type.
+    pub fn find_type(&self, key: &str) -> Option<Type> {
         match self.types.get(key) {
-            Some(t) => t.to_owned(),
+            Some(t) => Some(t.to_owned()),
             None => {
                 // deep check
                 match self.types.iter().find(|t| t.1.ident == key) {
-                    Some(t) => t.1.to_owned(),
-                    None => fcompiler_general_error(CompilerError::NoSuchType, key.to_string()),
+                    Some(t) => Some(t.1.to_owned()),
+                    None => {
+                        // the type of an item from a nested module is qualified by the
+                        // module which imported it (`sub.Type`), not by us (`mod.sub.Type`)
+                        let suffix = format!(".{key}");
+
+                        self.types
+                            .iter()
+                            .find(|t| key.contains(".") && t.0.ends_with(&suffix))
+                            .map(|t| t.1.to_owned())
+                    }
                 }
             }
         }
     }
 
+    pub fn get_trait(&self, key: &str) -> Trait {
+        match self.traits.get(key) {
+            Some(t) => t.to_owned(),
+            None => fcompiler_general_error(CompilerError::NoSuchTrait, key.to_string()),
+        }
+    }
+
     pub fn get_var_ref(&self, key: &str) -> Variable {
         let mut var = self.get_var(key);
         var.is_referenced = true;
         var
     }
 
+    /// Get the `(string, start, end)` of a string slice (`s[start..end]`) or string
+    /// index (`s[i]`, where `start` and `end` are both `i`).
+    ///
+    /// Returns `None` if `key` isn't indexing a `String`.
+    pub fn string_slice(&self, key: &str) -> Option<(String, String, String)> {
+        let (root, index) = key.strip_suffix("]")?.split_once("[")?;
+
+        if root.starts_with("&") || (self.get_var(root).r#type.ident != TYPE_NAME_STRING) {
+            return None;
+        }
+
+        let (start, end) = index.split_once("..").unwrap_or((index, index));
+
+        for bound in [start, end] {
+            if bound.parse::<i64>().is_ok() {
+                continue;
+            }
+
+            let r#type = self.get_var(bound).r#type;
+            if r#type.ident != TYPE_NAME_INT {
+                fcompiler_type_error(TYPE_NAME_INT.to_string(), r#type.ident);
+            }
+        }
+
+        Some((root.to_string(), start.to_string(), end.to_string()))
+    }
+
+    /// Get the type of a computed table key (the `k` in `t[k]` or `{ [k] = v }`).
+    pub fn key_type(&self, key: &str) -> Type {
+        if key.parse::<i64>().is_ok() {
+            TYPE_NAME_INT.into()
+        } else if key.parse::<f64>().is_ok() {
+            TYPE_NAME_FLOAT.into()
+        } else if key.starts_with("\"") {
+            TYPE_NAME_STRING.into()
+        } else {
+            self.get_var(key).r#type
+        }
+    }
+
+    /// Split a read of a table with an `int` index (`t[i]`) into `(table, index)`.
+    ///
+    /// Returns `None` if `key` isn't indexing a `Table` with an `int`.
+    pub fn int_index(&self, key: &str) -> Option<(String, String)> {
+        let (root, index) = key.strip_suffix("]")?.split_once("[")?;
+
+        if root.starts_with("&")
+            || root.contains(['.', ':', '['])
+            || index.contains(['[', ']'])
+            || (self.get_var(root).r#type.ident != TYPE_NAME_TABLE)
+            || (self.key_type(index).ident != TYPE_NAME_INT)
+        {
+            return None;
+        }
+
+        Some((root.to_string(), index.to_string()))
+    }
+
+    /// Check that a key of the given type can index `table` (a `Table<K, V>`).
+    pub fn check_key(&self, table: &Type, key: Type) {
+        let generics = if table.generics.is_empty() {
+            // aliases store the generics of the table they're for
+            self.get_type(&table.ident).generics
+        } else {
+            table.generics.clone()
+        };
+
+        let Some(expected) = generics.first() else {
+            return;
+        };
+
+        if (expected == TYPE_NAME_ANY) || (key.ident == TYPE_NAME_ANY) {
+            return;
+        }
+
+        if self.get_type(&key.ident).ident != self.get_type(expected).ident {
+            fcompiler_type_error(expected.to_owned(), key.ident);
+        }
+    }
+
     pub fn get_var(&self, key: &str) -> Variable {
+        if key.contains("[") && self.string_slice(key).is_some() {
+            // string slices are always strings
+            return (key.to_string(), Type::from(TYPE_NAME_STRING)).into();
+        }
+
         let mut key_split = key.split("[");
         let mut attempting_to_reference = false;
         let true_key = {
@@ -198,13 +770,23 @@ impl Registers {
             }
         };
 
-        let mut property_key_split = key.split(".");
-        let possible_root_name = property_key_split.next().unwrap();
-        if let Some(property) = property_key_split.next() {
+        // the root is the longest dotted prefix which is a variable, so items of nested
+        // modules (`mod.sub.item`) can be reached
+        let segments: Vec<&str> = key.split(".").collect();
+        let root_length = (1..segments.len())
+            .rev()
+            .find(|i| self.variables.contains_key(&segments[..*i].join(".")))
+            .unwrap_or(1);
+
+        let possible_root_name = segments[..root_length].join(".");
+        if let Some(property) = segments.get(root_length).copied().filter(|_| {
+            // the whole key is a variable of a module
+            !self.variables.contains_key(key)
+        }) {
             // being at this point means that we're trying to access a property
             // which belongs on the type of the root variable; we just need to
             // check if the type of the root variable has a property with this ident
-            let var = self.get_var(possible_root_name);
+            let var = self.get_var(&possible_root_name);
             let expanded_type = self.get_type(&var.r#type.ident); // we need to expand the type to access its properties
 
             if expanded_type.ident != TYPE_NAME_TABLE {
@@ -217,6 +799,11 @@ impl Registers {
                         return (property.to_string(), property_type.r#type.clone()).into();
                     }
                     None => {
+                        // values constructed from variants with payloads are tagged with them
+                        if !expanded_type.payloads.is_empty() && (property == "tag") {
+                            return (property.to_string(), TYPE_NAME_STRING.into()).into();
+                        }
+
                         // check variant
                         if !expanded_type.variants.is_empty() {
                             match expanded_type.variants.get(property) {
@@ -255,14 +842,19 @@ impl Registers {
             let table = self.get_var(&true_key);
 
             if table.r#type.ident != TYPE_NAME_TABLE {
-                if table.r#type.ident == TYPE_NAME_STRING {
-                    // string slices, acceptable (returns string)
-                    return (key.to_string(), Type::from(TYPE_NAME_STRING)).into();
-                }
-
                 fcompiler_type_error(TYPE_NAME_TABLE.to_owned(), table.r#type.ident.clone());
             }
 
+            // the key is everything in the (outermost) brackets
+            let index = &key[true_key.len() + 1..];
+            if let Some(index) = index.strip_suffix("]").filter(|i| !i.contains(['[', ']'])) {
+                self.check_key(&table.r#type, self.key_type(index));
+
+                if index.parse::<i64>().is_ok_and(|i| i < 1) {
+                    fcompiler_general_warning(CompilerError::IndexBelowOne, key.to_string());
+                }
+            }
+
             return (
                 key.to_string(),
                 // the generic values stored in `table` is actually the values
@@ -314,13 +906,70 @@ impl Registers {
                 .contains_key(&format!("{}:{method}", var.r#type.ident))
             {
                 return self.shallow_get_fn(&format!("{}:{method}", var.r#type.ident));
+            } else if (var.r#type.ident == TYPE_NAME_STRING)
+                && self.functions.contains_key(&format!("string.{method}"))
+            {
+                // the methods of strings are the string library, the string itself
+                // being the first argument
+                let mut function = self.shallow_get_fn(&format!("string.{method}"));
+                function.arguments.keys.remove(0);
+                function.arguments.types.remove(0);
+                return function;
             }
         }
 
         // return function
         match self.functions.get(key) {
             Some(f) => f.to_owned(),
-            None => fcompiler_general_error(CompilerError::NoSuchFunction, key.to_string()),
+            None => match self.variables.get(key) {
+                // function values can be called with anything, their signature isn't known
+                Some(var) if var.r#type.ident == TYPE_NAME_FUNCTION => Function {
+                    ident: key.to_string(),
+                    arguments: FunctionArguments {
+                        keys: vec!["values".to_string()],
+                        types: vec![TYPE_NAME_ANY.into()],
+                        variadic: true,
+                        optional: 0,
+                    },
+                    return_type: TYPE_NAME_ANY.into(),
+                    body: String::new(),
+                    visibility: TypeVisibility::Private,
+                    execution: ExecutionType::Sync,
+                    association: AssociationType::None,
+                    constant: false,
+                    export_name: None,
+                    generics: Vec::new(),
+                },
+                _ => fcompiler_general_error(CompilerError::NoSuchFunction, key.to_string()),
+            },
+        }
+    }
+
+    /// Make sure a private method is only called from an impl block of its type, and
+    /// that private functions of imported modules aren't called at all.
+    ///
+    /// `key` is the identifier the function was called with.
+    pub fn check_access(&self, key: &str, function: &Function) {
+        // imported functions are registered under the module's prefix (`module.ident`)
+        if (function.visibility == TypeVisibility::Private)
+            && (function.association == AssociationType::None)
+            && (key != function.ident)
+            && key.contains(".")
+            && !FUNCTION_BINDINGS.contains_key(key)
+        {
+            fcompiler_general_error(CompilerError::PrivateAccess, key.to_string())
+        }
+
+        let Some(owner) = function.private_owner() else {
+            return;
+        };
+
+        if self
+            .types
+            .get(TYPE_NAME_SELF)
+            .is_none_or(|r#type| r#type.ident != owner)
+        {
+            fcompiler_general_error(CompilerError::PrivateMethod, function.ident.clone())
         }
     }
 
@@ -348,18 +997,26 @@ impl TypeChecking for Variable {
 
 impl MultipleTypeChecking for FunctionCall<'_> {
     fn check_multiple(&self, supplied: Vec<Type>, registers: &Registers) -> () {
-        let function = registers.get_fn(&self.ident);
+        let function = registers.get_fn(&self.ident).instantiate(&supplied);
+        self.check_argument_count(&function);
 
-        for (i, r#type) in function.arguments.types.iter().enumerate() {
-            let matching = match supplied.get(i) {
-                Some(t) => t, // expand type
+        for (i, matching) in supplied.iter().enumerate() {
+            let r#type = match function.arguments.type_of(i) {
+                Some(t) => t,
                 None => continue,
             };
 
-            let expanded = registers.get_type(&r#type.ident);
+            if r#type.ident == TYPE_NAME_ANY {
+                // "any" arguments accept every type, generics included
+                continue;
+            }
+
+            let mut expanded = registers.get_type(&r#type.ident);
+            expanded.optional = r#type.optional;
             let expanded_matching = registers.get_type(&matching.ident);
             if expanded != expanded_matching {
-                fcompiler_type_error(expanded.ident.clone(), expanded_matching.ident.clone());
+                // report the types as written (`module.Type`, not `Type`)
+                fcompiler_type_error(r#type.ident.clone(), matching.ident.clone());
             } else {
                 // check generics
                 r#type.check_generics(matching.generics.clone(), registers);
@@ -390,7 +1047,10 @@ impl MultipleGenericChecking for Type {
     /// Go through all generics applied and make sure there aren't too few,
     /// too many, or invalid types.
     fn check_generics(&self, supplied: Vec<String>, registers: &Registers) -> () {
-        if (supplied.len() < self.generics.len()) | (supplied.len() > self.generics.len()) {
+        // tuples can have any number of values
+        if (self.ident != TYPE_NAME_TUPLE)
+            && ((supplied.len() < self.generics.len()) | (supplied.len() > self.generics.len()))
+        {
             fcompiler_general_error(
                 CompilerError::InvalidGenericCount,
                 format!(
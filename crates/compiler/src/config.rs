@@ -1,11 +1,113 @@
-use std::sync::{LazyLock, RwLock};
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, RwLock},
+};
 
-use serde::{Deserialize, Serialize};
+use pathbufd::PathBufD;
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{IntoDeserializer, Visitor, value::MapDeserializer},
+    forward_to_deserialize_any,
+};
+use serde_json::Value;
+
+use crate::checking::{CompilerError, fcompiler_general_error};
 
 pub static COMPILER_TEMPLATES: LazyLock<RwLock<CompilerConfig>> =
     LazyLock::new(|| RwLock::new(CompilerConfig::lua()));
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A function which transforms the generated Lua of a file.
+pub type PostProcessor = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Post-processing hooks applied by [`crate::process_file`], in the order they
+/// were registered.
+///
+/// Hooks receive the full module (including the `-- faraday.module` export
+/// table) and their output is what gets written. Imported files are processed
+/// through the same hooks.
+pub static COMPILER_POST_PROCESSORS: LazyLock<RwLock<Vec<PostProcessor>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// Register a new hook in [`COMPILER_POST_PROCESSORS`].
+pub fn register_post_processor(hook: impl Fn(&str) -> String + Send + Sync + 'static) {
+    COMPILER_POST_PROCESSORS
+        .write()
+        .unwrap()
+        .push(Box::new(hook));
+}
+
+/// If the generated `-- faraday.*` comments should be left out of the module
+/// export table.
+pub static COMPILER_STRIP_COMMENTS: LazyLock<RwLock<bool>> = LazyLock::new(|| RwLock::new(false));
+
+/// If every emitted item should be preceded by a `-- file:line:col` comment pointing
+/// at its source. Ignored when [`COMPILER_STRIP_COMMENTS`] is set.
+pub static COMPILER_SOURCE_COMMENTS: LazyLock<RwLock<bool>> = LazyLock::new(|| RwLock::new(false));
+
+/// If reading a table with an `int` index (`t[i]`) should assert that the index is in
+/// bounds at runtime.
+pub static COMPILER_BOUNDS_CHECKS: LazyLock<RwLock<bool>> = LazyLock::new(|| RwLock::new(false));
+
+/// If a [`crate::sourcemap::SourceMap`] should be built for every compiled file
+/// (see [`crate::sourcemap::COMPILER_SOURCEMAPS`]).
+pub static COMPILER_SOURCEMAP: LazyLock<RwLock<bool>> = LazyLock::new(|| RwLock::new(false));
+
+/// The directory imported files are written to (`use "nested/other"` is written to
+/// `nested/other.lua` in this directory). Defaults to `build` in the current directory.
+pub static COMPILER_OUT_DIR: LazyLock<RwLock<PathBufD>> =
+    LazyLock::new(|| RwLock::new(PathBufD::current().join("build")));
+
+/// A function which maps the path in a `use` statement (as written, without the
+/// `.fd`) to the name passed to `require`.
+pub type ModuleNameMapper = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// The [`ModuleNameMapper`] used for `use` statements. Paths are used as written by default.
+pub static COMPILER_MODULE_NAME: LazyLock<RwLock<ModuleNameMapper>> =
+    LazyLock::new(|| RwLock::new(Box::new(|path| path.to_string())));
+
+/// Replace the mapper in [`COMPILER_MODULE_NAME`].
+pub fn set_module_name_mapper(mapper: impl Fn(&str) -> String + Send + Sync + 'static) {
+    *COMPILER_MODULE_NAME.write().unwrap() = Box::new(mapper);
+}
+
+/// Map a file path to a dotted Lua module name (`./nested/other.lua` -> `nested.other`),
+/// which is what `require` expects when modules are found through `package.path`.
+///
+/// Paths leaving the current directory (`../`) can't be expressed as module names
+/// and are returned as written.
+pub fn lua_module_name(path: &str) -> String {
+    if path.split('/').any(|segment| segment == "..") {
+        return path.to_string();
+    }
+
+    let path = path.strip_suffix(".lua").unwrap_or(path);
+
+    path.split('/')
+        .filter(|segment| !segment.is_empty() && (*segment != "."))
+        .collect::<Vec<&str>>()
+        .join(".")
+}
+
+/// A custom representation for a type, used instead of the default templates by
+/// types declared with `@template(name)`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct TypeTemplate<'a> {
+    /// The type definition. (replaces `r#type`/`r#enum`)
+    ///
+    /// # Variables
+    /// * `$visibility`
+    /// * `$ident`
+    /// * `$body` (enum variants)
+    pub definition: &'a str,
+    /// A struct constructed from a table. (replaces `struct_literal`)
+    ///
+    /// # Variables
+    /// * `$body`
+    /// * `$field` (the value of each field, by name)
+    pub literal: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct CompilerConfig<'a> {
     /// An argument in a function parameters list. (not last argument)
     ///
@@ -33,6 +135,18 @@ pub struct CompilerConfig<'a> {
     /// * `$body`
     /// * `$ident`
     pub function: &'a str,
+    /// An asynchronous anonymous function (closure).
+    ///
+    /// # Variables
+    /// * `$args`
+    /// * `$body`
+    pub async_closure: &'a str,
+    /// A synchronous anonymous function (closure).
+    ///
+    /// # Variables
+    /// * `$args`
+    /// * `$body`
+    pub closure: &'a str,
     /// A variable declaration.
     ///
     /// # Variables
@@ -60,6 +174,24 @@ pub struct CompilerConfig<'a> {
     /// * `$ident`
     /// * `$value`
     pub enum_field: &'a str,
+    /// The constructor of an enum variant carrying values, returning them in a
+    /// table tagged with the variant.
+    ///
+    /// # Variables
+    /// * `$ident`
+    /// * `$args`
+    pub enum_constructor: &'a str,
+    /// A struct constructed from a table.
+    ///
+    /// # Variables
+    /// * `$body`
+    pub struct_literal: &'a str,
+    /// A field of a struct constructed from a table.
+    ///
+    /// # Variables
+    /// * `$ident`
+    /// * `$value`
+    pub struct_literal_field: &'a str,
     /// A type alias.
     ///
     /// # Variables
@@ -87,6 +219,44 @@ pub struct CompilerConfig<'a> {
     /// * `$ident`
     /// * `$args`
     pub call: &'a str,
+    /// A slice of a string (`s[start..end]`, 1-based and inclusive).
+    ///
+    /// # Variables
+    /// * `$ident`
+    /// * `$start`
+    /// * `$end`
+    pub string_slice: &'a str,
+    /// A table index (`t[i]`) checked to be in bounds at runtime (see
+    /// [`COMPILER_BOUNDS_CHECKS`]).
+    ///
+    /// # Variables
+    /// * `$table`
+    /// * `$index`
+    pub checked_index: &'a str,
+    /// The operator joining the parts of an interpolated string.
+    pub concat: &'a str,
+    /// A non-string value in an interpolated string (`"{value}"`).
+    ///
+    /// # Variables
+    /// * `$value`
+    pub to_string: &'a str,
+    /// A value cast to a number (`(value as int)`, where `value` is a `String`).
+    ///
+    /// # Variables
+    /// * `$value`
+    pub to_number: &'a str,
+    /// The division of two `int`s (`(a / b)` assigned to an `int`).
+    ///
+    /// # Variables
+    /// * `$lhs`
+    /// * `$rhs`
+    pub integer_division: &'a str,
+    /// The `and` operator.
+    pub and: &'a str,
+    /// The `or` operator.
+    pub or: &'a str,
+    /// The `!=` operator.
+    pub not_equal: &'a str,
     /// For loop.
     ///
     /// # Variables
@@ -94,24 +264,88 @@ pub struct CompilerConfig<'a> {
     /// * `$iter`
     /// * `$body`
     pub r#for: &'a str,
+    /// Numeric for loop (`start` to `stop`, inclusive).
+    ///
+    /// # Variables
+    /// * `$ident`
+    /// * `$start`
+    /// * `$stop`
+    /// * `$step`
+    /// * `$body`
+    pub numeric_for: &'a str,
     /// While loop.
     ///
     /// # Variables
     /// * `$condition`
     /// * `$body`
     pub r#while: &'a str,
+    /// Repeat loop (the condition is checked after the body, and ends the loop once
+    /// it's true).
+    ///
+    /// # Variables
+    /// * `$condition`
+    /// * `$body`
+    pub repeat: &'a str,
+    /// A labeled loop, followed by the label `break 'label` jumps to.
+    ///
+    /// # Variables
+    /// * `$loop`
+    /// * `$label`
+    pub labeled_loop: &'a str,
+    /// Break out of the innermost loop.
+    pub r#break: &'a str,
+    /// Break out of the loop with the given label.
+    ///
+    /// # Variables
+    /// * `$label`
+    pub break_label: &'a str,
+    /// Skip to the next iteration of the innermost loop.
+    ///
+    /// # Variables
+    /// * `$label` (the loop's [`Self::continue_label`])
+    pub r#continue: &'a str,
+    /// The label `continue` jumps to, placed at the end of the loop's body.
+    ///
+    /// # Variables
+    /// * `$label`
+    pub continue_label: &'a str,
     /// Conditional.
     ///
     /// # Variables
+    /// * `$keyword` (one of the `conditional_keyword_*` templates)
     /// * `$condition`
+    /// * `$opening`
     /// * `$body`
+    /// * `$closing` (only for the last branch)
     pub conditional: &'a str,
+    /// The keyword of the first branch of a conditional.
+    pub conditional_keyword_if: &'a str,
+    /// The keyword of the following branches of a conditional with a condition.
+    pub conditional_keyword_else_if: &'a str,
+    /// The keyword of the last branch of a conditional without a condition.
+    pub conditional_keyword_else: &'a str,
+    /// A block which always runs (a folded conditional).
+    ///
+    /// # Variables
+    /// * `$body`
+    pub block: &'a str,
     /// Conditional opening. (else block)
     pub conditional_opening_else: &'a str,
     /// Conditional opening. (not else block)
     pub conditional_opening_no_else: &'a str,
     /// Conditional closing.
     pub conditional_closing: &'a str,
+    /// The value of variables declared without one.
+    pub nil: &'a str,
+    /// Import of another module (`use`).
+    ///
+    /// # Variables
+    /// * `$ident`
+    /// * `$module` (mapped by [`COMPILER_MODULE_NAME`])
+    pub require: &'a str,
+    /// Named [`TypeTemplate`]s.
+    #[serde(borrow)]
+    pub type_templates: BTreeMap<&'a str, TypeTemplate<'a>>,
 }
 
 impl CompilerConfig<'_> {
@@ -122,10 +356,15 @@ impl CompilerConfig<'_> {
             last_arg: "$param",
             async_function: "$visibility$ident = function ($args)\n   return coroutine.create(function ()\n    $body\nend)\nend\n",
             function: "$visibilityfunction $ident($args)\n    $body\nend\n",
+            async_closure: "function ($args)\n   return coroutine.create(function ()\n    $body\nend)\nend",
+            closure: "function ($args)\n    $body\nend",
             variable: "$visibility$ident = $value\n",
             r#type: "$visibility$ident = {}\n",
             r#enum: "$visibility$ident = {\n$body}\n",
             enum_field: "$ident = $value,\n",
+            enum_constructor: "$ident = function ($args)\n    return { tag = \"$ident\", $args }\nend,\n",
+            struct_literal: "{\n$body}",
+            struct_literal_field: "$ident = $value,\n",
             type_alias: "$visibility$ident = {}\n",
             visibility_public: "",
             visibility_private: "local ",
@@ -133,12 +372,180 @@ impl CompilerConfig<'_> {
             mutability_constant: "",
             async_call: "select(2, coroutine.resume($ident($args)))\n",
             call: "$ident($args)",
+            string_slice: "string.sub($ident, $start, $end)",
+            checked_index: "$table[assert($index >= 1 and $index <= #$table and $index, \"index out of bounds\")]",
+            concat: "..",
+            to_string: "tostring($value)",
+            to_number: "tonumber($value)",
+            integer_division: "math.floor($lhs / $rhs)",
+            and: "and",
+            or: "or",
+            not_equal: "~=",
             r#for: "for $idents in $iter do\n$body\nend\n",
+            numeric_for: "for $ident = $start, $stop, $step do\n$body\nend\n",
             r#while: "while $condition do\n$body\nend\n",
+            repeat: "repeat\n$body\nuntil $condition\n",
+            labeled_loop: "do\n$loop::$label::\nend\n",
+            r#break: "break\n",
+            break_label: "goto $label\n",
+            r#continue: "goto $label\n",
+            continue_label: "\n::$label::\n",
             conditional: "\n$keyword $condition $opening\n$body\n$closing",
+            conditional_keyword_if: "if",
+            conditional_keyword_else_if: "elseif",
+            conditional_keyword_else: "else",
+            block: "do\n$body\nend\n",
             conditional_opening_else: "",
             conditional_opening_no_else: " then",
             conditional_closing: "end\n",
+            nil: "nil",
+            require: "local $ident = require \"$module\"\n",
+            type_templates: BTreeMap::new(),
         }
     }
+
+    /// JavaScript preset for [`CompilerConfig`] (`faradayc main.fd --target js`).
+    ///
+    /// Some features still emit Lua no matter the config:
+    /// * table literals (`{ [1] = "a" }`) and `for ... in` iterators (which must
+    ///   yield `[key, value]` arrays, like `Object.entries(t)`)
+    /// * `use` (imports are compiled to `.lua` files), and the module export table
+    ///   (`faradayc` compiles JS output as a bare script instead)
+    /// * `const fn` calls and expression calls, which are run with `luajit`
+    /// * `-- file:line:col` source comments
+    /// * returning several values (`return a, b`), and destructuring them
+    ///
+    /// Builtins (`print`, `math.floor`, ...) are called as written, so they have to be
+    /// defined for the script (`const print = console.log`).
+    pub fn javascript() -> Self {
+        Self {
+            arg: "$param, ",
+            last_arg: "$param",
+            async_function: "$visibilityasync function $ident($args) {\n    $body\n}\n",
+            function: "$visibilityfunction $ident($args) {\n    $body\n}\n",
+            async_closure: "async function ($args) {\n    $body\n}",
+            closure: "function ($args) {\n    $body\n}",
+            variable: "$visibilitylet $ident = $value;\n",
+            r#type: "$visibilityconst $ident = {};\n",
+            r#enum: "$visibilityconst $ident = {\n$body};\n",
+            enum_field: "$ident: $value,\n",
+            enum_constructor: "$ident: function ($args) {\n    return { tag: \"$ident\", $args };\n},\n",
+            struct_literal: "{\n$body}",
+            struct_literal_field: "$ident: $value,\n",
+            type_alias: "$visibilityconst $ident = {};\n",
+            visibility_public: "",
+            visibility_private: "",
+            mutability_mutable: "",
+            mutability_constant: "",
+            async_call: "await $ident($args)\n",
+            call: "$ident($args)",
+            string_slice: "$ident.substring($start - 1, $end)",
+            checked_index: "$table[(($index >= 1) && ($index <= $table.length)) ? $index : (() => { throw new RangeError(\"index out of bounds\") })()]",
+            concat: "+",
+            to_string: "String($value)",
+            to_number: "Number($value)",
+            integer_division: "Math.floor($lhs / $rhs)",
+            and: "&&",
+            or: "||",
+            not_equal: "!=",
+            r#for: "for (const [$idents] of $iter) {\n$body\n}\n",
+            numeric_for: "for (let $ident = $start; ($step > 0) ? ($ident <= $stop) : ($ident >= $stop); $ident += $step) {\n$body\n}\n",
+            r#while: "while ($condition) {\n$body\n}\n",
+            repeat: "do {\n$body\n} while (!($condition));\n",
+            labeled_loop: "$label:\n$loop",
+            r#break: "break;\n",
+            break_label: "break $label;\n",
+            r#continue: "continue;\n",
+            continue_label: "",
+            conditional: "\n$keyword$condition$opening\n$body\n$closing",
+            conditional_keyword_if: "if (",
+            conditional_keyword_else_if: "} else if (",
+            conditional_keyword_else: "} else",
+            block: "{\n$body\n}\n",
+            conditional_opening_else: " {",
+            conditional_opening_no_else: ") {",
+            conditional_closing: "}\n",
+            nil: "undefined",
+            require: "const $ident = require(\"$module\");\n",
+            type_templates: BTreeMap::new(),
+        }
+    }
+
+    /// Load a config from a JSON file (`faraday.json`). Fields missing from the file
+    /// keep their [`CompilerConfig::lua`] defaults.
+    pub fn from_file(path: PathBufD) -> CompilerConfig<'static> {
+        let overrides: Value = match std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+        {
+            Ok(v) => v,
+            Err(e) => fcompiler_general_error(CompilerError::InvalidConfig, format!("{path}: {e}")),
+        };
+
+        let mut config = serde_json::to_value(CompilerConfig::lua()).unwrap();
+        merge_json(&mut config, overrides);
+
+        match CompilerConfig::deserialize(Leaked(config)) {
+            Ok(c) => c,
+            Err(e) => fcompiler_general_error(CompilerError::InvalidConfig, format!("{path}: {e}")),
+        }
+    }
+
+    /// Get the [`TypeTemplate`] with the given name.
+    pub fn type_template(&self, name: &str) -> &TypeTemplate<'_> {
+        match self.type_templates.get(name) {
+            Some(t) => t,
+            None => fcompiler_general_error(CompilerError::NoSuchTemplate, name.to_string()),
+        }
+    }
+}
+
+/// Replace the fields of `base` with the ones given in `overrides` (objects are merged).
+fn merge_json(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(field) => merge_json(field, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// A JSON value deserialized with its strings leaked, so a loaded [`CompilerConfig`]
+/// can borrow them for the rest of the program.
+struct Leaked(Value);
+
+impl<'de> Deserializer<'de> for Leaked {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::String(s) => visitor.visit_borrowed_str(s.leak()),
+            Value::Object(map) => visitor.visit_map(MapDeserializer::new(
+                map.into_iter()
+                    .map(|(k, v)| (Leaked(Value::String(k)), Leaked(v))),
+            )),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for Leaked {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
 }
@@ -1,17 +1,22 @@
 use crate::bindings::*;
 use crate::checking::{
     CompilerError, MultipleGenericChecking, MultipleTypeChecking, Registers, ToSource,
-    TypeChecking, fcompiler_general_error, fcompiler_general_marker,
+    TypeChecking, fcompiler_general_error, fcompiler_general_marker, fcompiler_general_warning,
+    fcompiler_record_import, fcompiler_type_error,
 };
-use crate::config::COMPILER_TEMPLATES;
+use crate::config::{COMPILER_BOUNDS_CHECKS, COMPILER_OUT_DIR, COMPILER_TEMPLATES, CompilerConfig};
 use crate::fcompiler_error;
+use crate::fold::Constant;
 use parser::{Pair, Rule};
 use serde::{Deserialize, Serialize};
 
 use std::fs::write;
 use std::process::{Command, Stdio};
-use std::sync::{LazyLock, Mutex};
-use std::{collections::BTreeMap, fmt::Display};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+};
 
 macro_rules! merge_register {
     ($prefix:ident; $registers:ident.$sub:ident + $other_registers:ident.$other_sub:ident) => {
@@ -24,25 +29,180 @@ macro_rules! merge_register {
     };
 }
 
+/// A module in [`COMPILER_MODULE_CACHE`]: its canonicalized path, if it was only checked,
+/// if it was compiled as a bare module, and the config and features it was compiled with.
+pub type ModuleCacheKey = (
+    std::path::PathBuf,
+    bool,
+    bool,
+    Arc<CompilerConfig<'static>>,
+    BTreeSet<String>,
+);
+
+/// The lua output and registers of every module in [`COMPILER_MODULE_CACHE`].
+pub type ModuleCache = HashMap<ModuleCacheKey, (String, Registers)>;
+
+/// Every module compiled by [`use_file`].
+///
+/// A module imported more than once (with the same config and features) is only
+/// compiled the first time.
+pub static COMPILER_MODULE_CACHE: LazyLock<Mutex<ModuleCache>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Forget every module in [`COMPILER_MODULE_CACHE`] (so changed files are compiled again).
+pub fn clear_module_cache() {
+    match COMPILER_MODULE_CACHE.lock() {
+        Ok(mut w) => w.clear(),
+        Err(_) => COMPILER_MODULE_CACHE.clear_poison(),
+    }
+}
+
+/// Every file which is being compiled, in the order they were imported (the file which
+/// started the compilation is first).
+pub static COMPILER_IMPORT_STACK: LazyLock<Mutex<Vec<std::path::PathBuf>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Keeps a file on [`COMPILER_IMPORT_STACK`] until it's dropped (even if compiling it failed).
+///
+/// Dropping the guard of the first file also runs [`clear_expressions`].
+pub struct ImportGuard;
+
+impl ImportGuard {
+    /// Push the file at `path` onto [`COMPILER_IMPORT_STACK`].
+    ///
+    /// Raises [`CompilerError::CircularImport`] if the file is already being compiled.
+    pub fn push(path: &std::path::Path) -> Self {
+        let path = std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        let mut stack = match COMPILER_IMPORT_STACK.lock() {
+            Ok(w) => w,
+            Err(e) => e.into_inner(),
+        };
+
+        if let Some(index) = stack.iter().position(|p| *p == path) {
+            let cycle: Vec<String> = stack[index..]
+                .iter()
+                .chain([&path])
+                .map(|p| p.display().to_string())
+                .collect();
+
+            drop(stack);
+            fcompiler_general_error(CompilerError::CircularImport, cycle.join(" -> "));
+        }
+
+        stack.push(path);
+        Self
+    }
+}
+
+impl Drop for ImportGuard {
+    fn drop(&mut self) {
+        let empty = match COMPILER_IMPORT_STACK.lock() {
+            Ok(mut w) => {
+                w.pop();
+                w.is_empty()
+            }
+            Err(e) => {
+                let mut w = e.into_inner();
+                w.pop();
+                w.is_empty()
+            }
+        };
+
+        if empty {
+            // the first file (and everything it imports) is done
+            clear_expressions();
+        }
+    }
+}
+
+/// Process the file at `path` and merge its registers into `registers` (under `ident`).
+///
+/// Bare modules are compiled without the export table at the end of the file.
+///
+/// # Returns
+/// The lua output of the file.
 pub fn use_file(
     path: pathbufd::PathBufD,
     relative_file_path: String,
     ident: String,
     do_compile: bool,
+    bare: bool,
     registers: &mut Registers,
-) {
-    // process file and merge registers
-    let compiled = crate::process_file(path.clone(), Registers::default(), !do_compile);
-    let compiled_regs = compiled.1;
+) -> String {
+    fcompiler_record_import(ident.clone(), path.to_string());
+
+    let mut file_registers = registers.scoped();
+    if let Some(var) = file_registers.variables.get_mut("@@FARADAY_BARE_MODULE") {
+        var.value = bare.to_string();
+    }
+
+    // process file (unless it was already compiled) and merge registers
+    let dependency = std::fs::canonicalize(path.as_path()).unwrap_or(path.as_path().to_path_buf());
+    let key = (
+        dependency.clone(),
+        !do_compile,
+        bare,
+        registers.config.clone(),
+        registers.features.clone(),
+    );
+
+    let cached = COMPILER_MODULE_CACHE.lock().unwrap().get(&key).cloned();
+    let compiled = match cached {
+        Some(compiled) => compiled,
+        None => {
+            let compiled = crate::process_file(path.clone(), file_registers, !do_compile);
+            let compiled = (compiled.0, compiled.1);
+
+            COMPILER_MODULE_CACHE
+                .lock()
+                .unwrap()
+                .insert(key, compiled.clone());
+
+            compiled
+        }
+    };
+
+    let mut compiled_regs = compiled.1;
+
+    registers.dependencies.insert(dependency);
+    registers
+        .dependencies
+        .extend(compiled_regs.dependencies.clone());
+
+    // private methods aren't part of the type's public api
+    let private: Vec<String> = compiled_regs
+        .functions
+        .iter()
+        .filter(|(_, f)| f.private_owner().is_some())
+        .map(|(ident, _)| ident.clone())
+        .collect();
+
+    for ident in private {
+        compiled_regs.functions.remove(&ident);
+    }
+
+    // functions are called by the name they're exported as
+    let renamed: Vec<(String, String)> = compiled_regs
+        .functions
+        .iter()
+        .filter_map(|(ident, f)| Some((ident.clone(), f.export_name.clone()?)))
+        .collect();
+
+    for (ident, export_name) in renamed {
+        let function = compiled_regs.functions.remove(&ident).unwrap();
+        compiled_regs.functions.insert(export_name, function);
+    }
 
     if !ident.is_empty() {
         merge_register!(ident; registers.types + compiled_regs.types);
         merge_register!(ident; registers.functions + compiled_regs.functions);
         merge_register!(ident; registers.variables + compiled_regs.variables);
+        merge_register!(ident; registers.traits + compiled_regs.traits);
     }
 
-    let output_path = pathbufd::PathBufD::current()
-        .join("build")
+    let output_path = COMPILER_OUT_DIR
+        .read()
+        .unwrap()
         .join(format!("{}.lua", relative_file_path));
 
     let parent = output_path.as_path().parent().unwrap();
@@ -52,9 +212,17 @@ pub fn use_file(
         std::fs::create_dir_all(parent).unwrap();
     }
 
-    if let Err(e) = write(output_path, compiled.0) {
+    if let Some(map) = crate::sourcemap::take_sourcemap(&path.to_string()) {
+        if let Err(e) = write(format!("{output_path}.map"), map.to_json()) {
+            fcompiler_error!("{e}")
+        }
+    }
+
+    if let Err(e) = write(output_path, &compiled.0) {
         fcompiler_error!("{e}")
     }
+
+    compiled.0
 }
 
 /// The parameter supplied to a function during creation.
@@ -62,6 +230,11 @@ pub fn use_file(
 pub struct FunctionArguments {
     pub keys: Vec<String>,
     pub types: Vec<Type>,
+    /// If the last parameter accepts any number of arguments (only used by bindings).
+    pub variadic: bool,
+    /// The number of parameters at the end which can be left out (only used by bindings).
+    #[serde(default)]
+    pub optional: usize,
 }
 
 impl FunctionArguments {
@@ -73,6 +246,33 @@ impl FunctionArguments {
 
         None
     }
+
+    /// Get the parameter list of a function definition.
+    pub fn source(&self, config: &CompilerConfig) -> String {
+        let mut src_out: String = String::new();
+
+        for (i, param) in self.keys.iter().enumerate() {
+            if i != self.keys.len() - 1 {
+                src_out.push_str(&config.arg.replace("$param", param));
+            } else {
+                src_out.push_str(&config.last_arg.replace("$param", param));
+            }
+        }
+
+        src_out
+    }
+
+    /// Get the required type of the argument at the given index (if there is one).
+    ///
+    /// Every argument after the last parameter of a variadic function has the
+    /// type of the last parameter.
+    pub fn type_of(&self, index: usize) -> Option<&Type> {
+        match self.types.get(index) {
+            Some(t) => Some(t),
+            None if self.variadic => self.types.last(),
+            None => None,
+        }
+    }
 }
 
 /// Async/sync modifiers for [`Function`]s.
@@ -132,43 +332,331 @@ pub struct Function {
     pub visibility: TypeVisibility,
     pub execution: ExecutionType,
     pub association: AssociationType,
+    /// If the function is a `const fn` (evaluated at compile time when called
+    /// with constant arguments to initialize a constant variable).
+    pub constant: bool,
+    /// The name the function is exported as (`@export("name")`), if it isn't its ident.
+    pub export_name: Option<String>,
+    /// The type parameters of the function (`fn ident<T, U>(...)`).
+    #[serde(default)]
+    pub generics: Vec<String>,
 }
 
 impl Function {
-    pub fn args_string(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
-        let mut src_out: String = String::new();
+    pub fn args_string(&self, config: &CompilerConfig) -> String {
+        self.arguments.source(config)
+    }
 
-        for (i, param) in self.arguments.keys.clone().iter().enumerate() {
-            if i != self.arguments.keys.len() - 1 {
-                src_out.push_str(&config.arg.replace("$param", param));
-            } else {
-                src_out.push_str(&config.last_arg.replace("$param", param));
+    /// Get the type this function is implemented on, if it's a private method.
+    pub fn private_owner(&self) -> Option<&str> {
+        if (self.visibility == TypeVisibility::Public)
+            || (self.association == AssociationType::None)
+            // bindings are private static functions too
+            || FUNCTION_BINDINGS.contains_key(&self.ident)
+        {
+            return None;
+        }
+
+        self.ident.rsplit_once([':', '.']).map(|(owner, _)| owner)
+    }
+
+    /// Make sure a `const fn` can be evaluated at compile time.
+    fn check_constant(&self) {
+        if !self.constant {
+            return;
+        }
+
+        if self.execution == ExecutionType::Async {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!("const function \"{}\" cannot be async", self.ident),
+            )
+        }
+
+        if ![
+            TYPE_NAME_INT,
+            TYPE_NAME_FLOAT,
+            TYPE_NAME_NUMBER,
+            TYPE_NAME_BOOLEAN,
+            TYPE_NAME_STRING,
+        ]
+        .contains(&self.return_type.ident.as_str())
+        {
+            // the result has to be inlined as a literal
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "const function \"{}\" must return a number, bool, or String (returns \"{}\")",
+                    self.ident, self.return_type.ident
+                ),
+            )
+        }
+    }
+}
+
+impl Function {
+    /// Get the registers the body of a function with the given parameters is processed with.
+    fn body_registers(reg: &Registers, keys: &[String], types: &[Type]) -> Registers {
+        // we must update the registries with the arguments in order
+        // to allow the body to pass the type check
+        let mut body_reg = reg.nested();
+        body_reg.loops.clear();
+        body_reg.continue_label = None;
+
+        for (k, t) in std::iter::zip(keys, types) {
+            body_reg
+                .variables
+                .insert(k.clone(), (k.clone(), t.to_owned()).into());
+        }
+
+        body_reg
+    }
+
+    /// Get the type parameters (`fn ident<T, U>(...)`) of a function (or method).
+    fn type_parameters(pair: &Pair<'_, Rule>) -> Vec<String> {
+        pair.clone()
+            .into_inner()
+            .find(|pair| pair.as_rule() == Rule::generic)
+            .map(|pair| pair.into_inner().map(|p| p.as_str().to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get the given registers with the type parameters of a function registered as
+    /// types, so they can be used in its signature and body.
+    fn generic_registers(reg: &Registers, generics: &[String]) -> Registers {
+        let mut reg = reg.to_owned();
+
+        for ident in generics {
+            reg.types.insert(
+                ident.clone(),
+                (ident.as_str(), TypeVisibility::Private).into(),
+            );
+        }
+
+        reg
+    }
+
+    /// Get this function with its type parameters replaced by the types of the given
+    /// arguments. Each parameter is bound to the type of the first argument typed
+    /// with it, so later arguments are checked against that type. Parameters which
+    /// aren't bound are `any`.
+    pub fn instantiate(&self, supplied: &[Type]) -> Function {
+        if self.generics.is_empty() {
+            return self.to_owned();
+        }
+
+        let mut bound: BTreeMap<&str, &Type> = BTreeMap::new();
+
+        for (i, supplied) in supplied.iter().enumerate() {
+            let Some(r#type) = self.arguments.type_of(i) else {
+                continue;
+            };
+
+            if self.generics.contains(&r#type.ident) && (supplied.ident != TYPE_NAME_NIL) {
+                bound.entry(r#type.ident.as_str()).or_insert(supplied);
             }
         }
 
-        src_out
+        let ident = |ident: &String| match bound.get(ident.as_str()) {
+            Some(t) => t.ident.clone(),
+            None if self.generics.contains(ident) => TYPE_NAME_ANY.to_string(),
+            None => ident.clone(),
+        };
+
+        let substitute = |r#type: &Type| {
+            let mut out = match bound.get(r#type.ident.as_str()) {
+                Some(t) => (*t).to_owned(),
+                None => Type {
+                    ident: ident(&r#type.ident),
+                    generics: r#type.generics.iter().map(ident).collect(),
+                    ..r#type.to_owned()
+                },
+            };
+
+            out.optional |= r#type.optional;
+            out
+        };
+
+        let mut function = self.to_owned();
+        function.arguments.types = self.arguments.types.iter().map(substitute).collect();
+        function.return_type = substitute(&self.return_type);
+        function.generics.clear();
+        function
+    }
+
+    /// Check if the given block contains a `return` (not counting nested functions).
+    fn find_return(block: Pair<'_, Rule>) -> bool {
+        block.into_inner().any(|pair| match pair.as_rule() {
+            Rule::r#return => true,
+            Rule::function | Rule::closure | Rule::r#impl => false,
+            _ => Function::find_return(pair),
+        })
+    }
+
+    /// Check that every `return` in the given method body returns the type the
+    /// method is implemented on ([`TYPE_NAME_SELF`]).
+    fn check_self_returns(block: Pair<'_, Rule>, reg: &Registers) {
+        let expected = reg.get_type(TYPE_NAME_SELF);
+
+        if !expected.variants.is_empty() {
+            // enum variants are typed by their values, so they can't be checked
+            return;
+        }
+
+        for pair in block.into_inner() {
+            match pair.as_rule() {
+                Rule::r#return => {
+                    let value = pair.into_inner().next().unwrap();
+                    let root = value.as_str().split(['.', '[', ':']).next().unwrap();
+
+                    let t = match value.as_rule() {
+                        // variables declared in the body aren't registered yet
+                        Rule::identifier if !reg.variables.contains_key(root) => continue,
+                        Rule::boolean | Rule::ordered_comparison => TYPE_NAME_BOOLEAN.into(),
+                        Rule::string
+                        | Rule::integer
+                        | Rule::float
+                        | Rule::identifier
+                        | Rule::call
+                        | Rule::ordered_mathematical
+                        | Rule::ordered_cast => Type::from_parser_type(value, reg),
+                        _ => continue,
+                    };
+
+                    if reg.get_type(&t.ident).ident != expected.ident {
+                        fcompiler_general_error(
+                            CompilerError::InvalidType,
+                            format!(
+                                "method returns \"{}\", but returns \"{}\"",
+                                expected.ident, t.ident
+                            ),
+                        )
+                    }
+                }
+                Rule::function | Rule::r#impl => continue,
+                _ => Function::check_self_returns(pair, reg),
+            }
+        }
+    }
+
+    /// Get the name given to an `@export("name")` attribute.
+    fn export_name(pair: Pair<'_, Rule>) -> String {
+        let name = pair.into_inner().next().unwrap().as_str().replace("\"", "");
+
+        let mut chars = name.chars();
+        if !chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || (c == '_'))
+            || !chars.all(|c| c.is_ascii_alphanumeric() || (c == '_'))
+        {
+            // the name is used as a key in the module table
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!("\"{name}\" is not a valid export name"),
+            )
+        }
+
+        name
+    }
+
+    /// Get the signature of a function (or method) without processing its body.
+    pub fn signature(pair: Pair<'_, Rule>, reg: &Registers) -> Self {
+        let generics = Function::type_parameters(&pair);
+        let reg = &Function::generic_registers(reg, &generics);
+
+        let mut name = String::new();
+        let mut keys: Vec<String> = Vec::new();
+        let mut types: Vec<Type> = Vec::new();
+        let mut return_type: Type = Type::default();
+        let mut visibility: TypeVisibility = reg.default_visibility.clone();
+        let mut execution: ExecutionType = ExecutionType::Sync;
+        let mut association: AssociationType = AssociationType::None;
+        let mut constant: bool = false;
+        let mut export_name: Option<String> = None;
+
+        for pair in pair.into_inner() {
+            match pair.as_rule() {
+                Rule::identifier => name = pair.as_str().to_string(),
+                Rule::type_modifier => visibility = pair.into(),
+                Rule::shadow_attribute => continue,
+                Rule::export_attribute => export_name = Some(Function::export_name(pair)),
+                Rule::const_modifier => constant = true,
+                Rule::sync_modifier => execution = pair.into(),
+                Rule::method_modifier => association = pair.into(),
+                Rule::generic => continue,
+                Rule::typed_parameter => {
+                    let mut inner = pair.into_inner();
+                    types.push(inner.next().unwrap().into());
+                    keys.push(inner.next().unwrap().as_str().to_string());
+                }
+                Rule::r#type => return_type = pair.into(),
+                Rule::block => continue,
+                _ => unreachable!("reached impossible rule in function signature processing"),
+            }
+        }
+
+        let fun = Function {
+            ident: name,
+            arguments: FunctionArguments {
+                keys,
+                types,
+                variadic: false,
+                optional: 0,
+            },
+            return_type,
+            body: String::new(),
+            visibility,
+            execution,
+            association,
+            constant,
+            export_name,
+            generics,
+        };
+
+        fun.check(fun.return_type.clone(), reg);
+        fun.check_multiple(fun.arguments.types.clone(), reg);
+        fun.check_constant();
+
+        fun
+    }
+
+    /// Get the signature of a function (like [`Function::signature`]), or `None` if it
+    /// uses a type which isn't registered yet (such as a type from a `use`d module).
+    pub fn try_signature(pair: Pair<'_, Rule>, reg: &Registers) -> Option<Self> {
+        let generics = Function::type_parameters(&pair);
+        let generic_reg = Function::generic_registers(reg, &generics);
+
+        let resolved = pair.clone().into_inner().all(|pair| {
+            let r#type: Type = match pair.as_rule() {
+                Rule::typed_parameter => pair.into_inner().next().unwrap().into(),
+                Rule::r#type => pair.into(),
+                _ => return true,
+            };
+
+            generic_reg.find_type(&r#type.ident).is_some()
+        });
+
+        resolved.then(|| Function::signature(pair, reg))
     }
 }
 
 impl ToSource for Function {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
+    fn transform(&self, config: &CompilerConfig) -> String {
         if self.execution == ExecutionType::Async {
             // async coroutine function
             config
                 .async_function
-                .replace("$visibility", &self.visibility.to_string())
+                .replace("$visibility", self.visibility.source(config))
                 .replace("$ident", &self.ident)
-                .replace("$args", &self.args_string())
+                .replace("$args", &self.args_string(config))
                 .replace("$body", &self.body)
         } else {
             // regular, sync function
             config
                 .function
-                .replace("$visibility", &self.visibility.to_string())
+                .replace("$visibility", self.visibility.source(config))
                 .replace("$ident", &self.ident)
-                .replace("$args", &self.args_string())
+                .replace("$args", &self.args_string(config))
                 .replace("$body", &self.body)
         }
     }
@@ -176,16 +664,19 @@ impl ToSource for Function {
 
 impl From<(Pair<'_, Rule>, &Registers)> for Function {
     fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
-        let reg = value.1;
+        let generics = Function::type_parameters(&value.0);
+        let reg = &Function::generic_registers(value.1, &generics);
         let mut inner = value.0.into_inner();
 
         let mut name = String::new();
         let mut keys: Vec<String> = Vec::new();
         let mut types: Vec<Type> = Vec::new();
         let mut return_type: Type = Type::default();
-        let mut visibility: TypeVisibility = TypeVisibility::Private;
+        let mut visibility: TypeVisibility = reg.default_visibility.clone();
         let mut execution: ExecutionType = ExecutionType::Sync;
         let mut association: AssociationType = AssociationType::None;
+        let mut constant: bool = false;
+        let mut export_name: Option<String> = None;
         let mut body: String = String::new();
 
         while let Some(pair) = inner.next() {
@@ -195,11 +686,15 @@ impl From<(Pair<'_, Rule>, &Registers)> for Function {
                 Rule::identifier => {
                     name = pair.as_str().to_string();
                 }
+                Rule::shadow_attribute => continue,
+                Rule::export_attribute => export_name = Some(Function::export_name(pair)),
                 Rule::type_modifier => visibility = pair.into(),
+                Rule::const_modifier => constant = true,
                 Rule::sync_modifier => execution = pair.into(),
                 Rule::method_modifier => {
                     association = pair.into();
                 }
+                Rule::generic => continue,
                 Rule::typed_parameter => {
                     let mut inner = pair.into_inner();
                     // it's safe to unwrap here because the grammar REQUIRES
@@ -209,57 +704,169 @@ impl From<(Pair<'_, Rule>, &Registers)> for Function {
                 }
                 Rule::r#type => return_type = pair.into(),
                 Rule::block => {
-                    body = crate::process(pair.into_inner(), {
-                        // we must update the registries with the arguments in order
-                        // to allow the body to pass the type check
-                        let mut reg = reg.clone();
-
-                        for (k, t) in std::iter::zip(&keys, &types) {
-                            reg.variables
-                                .insert(k.clone(), (k.clone(), t.to_owned()).into());
-                        }
+                    let body_reg = Function::body_registers(reg, &keys, &types);
+
+                    if reg.types.contains_key(TYPE_NAME_SELF)
+                        && (reg.get_type(&return_type.ident).ident
+                            == reg.get_type(TYPE_NAME_SELF).ident)
+                    {
+                        // builder methods must return the type they're implemented on
+                        Function::check_self_returns(pair.clone(), &body_reg);
+                    }
+
+                    if return_type.is_empty() && Function::find_return(pair.clone()) {
+                        // there's nothing to return
+                        fcompiler_general_error(
+                            CompilerError::InvalidType,
+                            format!(
+                                "\"{name}\" returns \"{}\", but has a return value",
+                                return_type.ident
+                            ),
+                        )
+                    }
+
+                    // struct constructors return `self` implicitly (see `Impl`)
+                    let constructor = (name == "new")
+                        && (association == AssociationType::Static)
+                        && reg.types.contains_key(TYPE_NAME_SELF)
+                        && reg.get_type(TYPE_NAME_SELF).variants.is_empty();
+
+                    if !return_type.is_empty()
+                        && !return_type.ident.is_empty()
+                        && (return_type.ident != TYPE_NAME_ANY)
+                        && !constructor
+                        && !Function::find_return(pair.clone())
+                    {
+                        fcompiler_general_error(
+                            CompilerError::MissingReturn,
+                            format!("\"{name}\" returns \"{}\"", return_type.ident),
+                        )
+                    }
 
-                        reg
-                    })
-                    .0
+                    body = crate::process(pair.into_inner(), body_reg).0
                 }
                 _ => unreachable!("reached impossible rule in function processing"),
             }
         }
 
-        // special function names
-        let name_association_split = name.split(":");
-        let true_name = name_association_split.skip(1).next().unwrap_or(&name);
-
-        if (true_name == "new") && (association == AssociationType::Static) {
-            // imitate class
-            body = format!(
-                "__data_struct.__index = __data_struct
-local self = {{}}
-setmetatable(self, __data_struct)
-{body}
-return self"
-            )
-        }
-
         // ...
         let fun = Function {
             ident: name.clone(),
-            arguments: FunctionArguments { keys, types },
+            arguments: FunctionArguments {
+                keys,
+                types,
+                variadic: false,
+                optional: 0,
+            },
             return_type,
             body,
             visibility,
             execution,
             association,
+            constant,
+            export_name,
+            generics,
         };
 
         fun.check(fun.return_type.clone(), reg);
         fun.check_multiple(fun.arguments.types.clone(), reg);
+        fun.check_constant();
 
         fun
     }
 }
 
+/// An anonymous function value (`fn(int x) -> int { ... }`), which is a
+/// [`TYPE_NAME_FUNCTION`].
+#[derive(Debug, Clone)]
+pub struct Closure {
+    pub arguments: FunctionArguments,
+    pub return_type: Type,
+    pub execution: ExecutionType,
+    pub body: String,
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Closure {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let reg = value.1;
+
+        let mut keys: Vec<String> = Vec::new();
+        let mut types: Vec<Type> = Vec::new();
+        let mut return_type: Type = Type::default();
+        let mut execution: ExecutionType = ExecutionType::Sync;
+        let mut body: String = String::new();
+
+        for pair in value.0.into_inner() {
+            match pair.as_rule() {
+                Rule::sync_modifier => execution = pair.into(),
+                Rule::typed_parameter => {
+                    let mut inner = pair.into_inner();
+                    types.push(inner.next().unwrap().into());
+                    keys.push(inner.next().unwrap().as_str().to_string());
+                }
+                Rule::r#type => return_type = pair.into(),
+                Rule::block => {
+                    if return_type.is_empty() && Function::find_return(pair.clone()) {
+                        // there's nothing to return
+                        fcompiler_general_error(
+                            CompilerError::InvalidType,
+                            format!(
+                                "closure returns \"{}\", but has a return value",
+                                return_type.ident
+                            ),
+                        )
+                    }
+
+                    if !return_type.is_empty()
+                        && !return_type.ident.is_empty()
+                        && (return_type.ident != TYPE_NAME_ANY)
+                        && !Function::find_return(pair.clone())
+                    {
+                        fcompiler_general_error(
+                            CompilerError::MissingReturn,
+                            format!("closure returns \"{}\"", return_type.ident),
+                        )
+                    }
+
+                    let body_reg = Function::body_registers(reg, &keys, &types);
+                    body = crate::process(pair.into_inner(), body_reg).0
+                }
+                _ => unreachable!("reached impossible rule in closure processing"),
+            }
+        }
+
+        reg.get_type(&return_type.ident);
+        for r#type in &types {
+            reg.get_type(&r#type.ident);
+        }
+
+        Self {
+            arguments: FunctionArguments {
+                keys,
+                types,
+                variadic: false,
+                optional: 0,
+            },
+            return_type,
+            execution,
+            body,
+        }
+    }
+}
+
+impl ToSource for Closure {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let template = match self.execution {
+            ExecutionType::Async => config.async_closure,
+            ExecutionType::Sync => config.closure,
+        };
+
+        template
+            .replace("$args", &self.arguments.source(config))
+            .replace("$body", &self.body)
+    }
+}
+
 /// A variable binding.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Variable {
@@ -269,48 +876,303 @@ pub struct Variable {
     pub visibility: TypeVisibility,
     pub mutable: MutabilityModifier,
     pub is_referenced: bool,
+    /// If the variable was declared without a value and hasn't been assigned since.
+    pub uninitialized: bool,
+    /// The alignment given with `@align(N)`. Ignored by the Lua backend.
+    pub align: Option<usize>,
+    /// The size given with `@size(N)`. Ignored by the Lua backend.
+    pub size: Option<usize>,
 }
 
-impl ToSource for Variable {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
-        config
-            .variable
-            .replace("$visibility", &self.visibility.to_string())
-            .replace("$ident", &self.ident)
-            .replace("$value", &self.value)
-            .replace("$typename", &self.r#type.ident)
-    }
-}
+impl Variable {
+    /// Apply a `@align(N)`/`@size(N)` attribute to the variable.
+    fn apply_attribute(&mut self, pair: Pair<'_, Rule>) {
+        let mut inner = pair.into_inner();
+        let name = inner.next().unwrap().as_str();
+        let value = match inner.next().unwrap().as_str().parse::<usize>() {
+            Ok(v) => v,
+            Err(e) => fcompiler_error!("{e}"),
+        };
 
-impl From<(String, Type)> for Variable {
-    fn from(value: (String, Type)) -> Self {
-        Self {
-            ident: value.0,
-            r#type: value.1,
-            value: String::new(),
-            visibility: TypeVisibility::Private,
-            mutable: MutabilityModifier::Constant,
-            is_referenced: false,
+        match name {
+            "align" => self.align = Some(value),
+            "size" => self.size = Some(value),
+            _ => unreachable!("reached impossible variable attribute"),
         }
     }
-}
 
-impl From<(String, Type, TypeVisibility)> for Variable {
-    fn from(value: (String, Type, TypeVisibility)) -> Self {
-        Self {
-            ident: value.0,
-            r#type: value.1,
-            value: String::new(),
-            visibility: value.2,
-            mutable: MutabilityModifier::Constant,
-            is_referenced: false,
+    /// Get the type of a value assigned to a `let` binding.
+    fn infer_type(pair: &Pair<'_, Rule>, reg: &Registers) -> Type {
+        match pair.as_rule() {
+            Rule::identifier if reg.string_slice(pair.as_str()).is_some() => {
+                TYPE_NAME_STRING.into()
+            }
+            Rule::string
+            | Rule::integer
+            | Rule::float
+            | Rule::boolean
+            | Rule::closure
+            | Rule::identifier
+            | Rule::call
+            | Rule::ordered_mathematical
+            | Rule::ordered_cast
+            | Rule::struct_value
+            | Rule::table => Type::from_parser_type(pair.clone(), reg),
+            _ => fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!("cannot infer a type from \"{}\"", pair.as_str()),
+            ),
         }
     }
-}
 
-impl From<Pair<'_, Rule>> for Variable {
-    fn from(value: Pair<'_, Rule>) -> Self {
+    /// Check a call assigned to a variable of the given type and get its source.
+    fn call_source(
+        pair: Pair<'_, Rule>,
+        r#type: &Type,
+        mutable: &MutabilityModifier,
+        reg: &Registers,
+    ) -> String {
+        let call = FunctionCall::from((pair, reg));
+        let supplied_types = call.arg_types(reg);
+        call.check_multiple(supplied_types.clone(), reg);
+
+        // check function return type
+        let function = reg.get_fn(&call.ident).instantiate(&supplied_types);
+        if function.return_type.is_empty() {
+            // there's no value to assign
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "\"{}\" returns \"{}\", which has no value",
+                    call.ident, function.return_type.ident
+                ),
+            )
+        } else if function.return_type != *r#type {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "cannot assign \"{}\" to \"{}\"",
+                    function.return_type.ident, r#type.ident
+                ),
+            )
+        }
+
+        // constant variables initialized by a `const fn` get the
+        // result of the call instead (if the arguments are constant)
+        if function.constant && (*mutable == MutabilityModifier::Constant) {
+            match ConstCall::evaluate(&call, &function, reg) {
+                Some(result) => result.transform(&reg.config),
+                None => call.transform(&reg.config),
+            }
+        } else {
+            call.transform(&reg.config)
+        }
+    }
+
+    /// Check a value assigned to a variable of the given type and get its source.
+    ///
+    /// `r#type` becomes [`TYPE_NAME_REF`] if the value is a reference to another variable.
+    fn value_source(
+        pair: Pair<'_, Rule>,
+        r#type: &mut Type,
+        mutable: &MutabilityModifier,
+        reg: &Registers,
+    ) -> String {
+        // tuples only exist to be destructured (`let a, b = f()`)
+        if r#type.ident == TYPE_NAME_TUPLE {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "cannot assign \"{TYPE_NAME_TUPLE}\" to a single variable (destructure it instead)"
+                ),
+            )
+        }
+
+        let rule = pair.as_rule();
+
+        match rule {
+            Rule::identifier => {
+                if reg.string_slice(pair.as_str()).is_some() {
+                    // slices create a new string, so they don't need to be referenced
+                    let var = reg.get_var(pair.as_str());
+                    var.check(r#type.clone(), reg);
+                    identifier_source(pair.as_str(), reg)
+                } else {
+                    let var = reg.get_var(pair.as_str());
+
+                    // since we're assigning the value of another variable to this
+                    // variable, we need to make sure we referenced the other variable
+                    if !var.is_referenced {
+                        fcompiler_general_error(CompilerError::ExpectedReference, var.ident);
+                    }
+
+                    // ...
+                    *r#type = TYPE_NAME_REF.into();
+                    var.ident
+                }
+            }
+            // process blocks before using as value
+            Rule::block => crate::process(pair.into_inner(), reg.scoped()).0,
+            Rule::nil => {
+                let t = Type::from_parser_type(pair, reg);
+
+                if t != *r#type {
+                    fcompiler_general_error(
+                        CompilerError::InvalidType,
+                        format!(
+                            "cannot assign \"{TYPE_NAME_NIL}\" to \"{}\" (not optional)",
+                            r#type.ident
+                        ),
+                    )
+                }
+
+                reg.config.nil.to_string()
+            }
+            Rule::closure => {
+                if reg.get_type(&r#type.ident).ident != TYPE_NAME_FUNCTION {
+                    fcompiler_general_error(
+                        CompilerError::InvalidType,
+                        format!(
+                            "cannot assign \"{TYPE_NAME_FUNCTION}\" to \"{}\"",
+                            r#type.ident
+                        ),
+                    )
+                }
+
+                Closure::from((pair, reg)).transform(&reg.config)
+            }
+            // everything else just needs to be stringified
+            Rule::call => Variable::call_source(pair, r#type, mutable, reg),
+            Rule::ordered_mathematical => {
+                let expression = Arithmetic::with_target(pair, reg, Some(r#type));
+
+                let t = expression.r#type();
+                if t != reg.get_type(&r#type.ident) {
+                    fcompiler_general_error(
+                        CompilerError::InvalidType,
+                        format!("cannot assign \"{}\" to \"{}\"", t.ident, r#type.ident),
+                    )
+                }
+
+                expression.transform(&reg.config)
+            }
+            Rule::ordered_cast => {
+                let cast = Cast::from((pair, reg));
+
+                if reg.get_type(&cast.r#type.ident) != reg.get_type(&r#type.ident) {
+                    fcompiler_general_error(
+                        CompilerError::InvalidType,
+                        format!(
+                            "cannot assign \"{}\" to \"{}\"",
+                            cast.r#type.ident, r#type.ident
+                        ),
+                    )
+                }
+
+                cast.transform(&reg.config)
+            }
+            Rule::ordered_comparison => {
+                let expression = Comparison::from((pair, reg));
+
+                let t = expression.r#type();
+                if t != reg.get_type(&r#type.ident) {
+                    fcompiler_general_error(
+                        CompilerError::InvalidType,
+                        format!("cannot assign \"{}\" to \"{}\"", t.ident, r#type.ident),
+                    )
+                }
+
+                expression.transform(&reg.config)
+            }
+            _ => {
+                let t = Type::from_parser_type(pair.clone(), reg);
+                let expanded_type = reg.get_type(&r#type.ident);
+
+                if (t != expanded_type) && t.ident != TYPE_NAME_TABLE {
+                    // tables can be assigned to anything since everything
+                    // in lua is *technically* a table
+                    fcompiler_general_error(
+                        CompilerError::InvalidType,
+                        format!(
+                            "cannot assign \"{}\" to \"{}\"",
+                            t.ident, expanded_type.ident
+                        ),
+                    )
+                }
+
+                if rule == Rule::struct_value {
+                    StructLiteral::from((pair, reg)).transform(&reg.config)
+                } else if (rule == Rule::table) && !expanded_type.properties.is_empty() {
+                    // constructing a struct
+                    StructLiteral::from((pair, &expanded_type)).transform(&reg.config)
+                } else if rule == Rule::table {
+                    if expanded_type.ident == TYPE_NAME_TABLE {
+                        check_table_keys(pair.clone(), r#type, reg);
+                    }
+
+                    pair.as_str().to_string()
+                } else if rule == Rule::string {
+                    interpolate(pair.as_str(), reg)
+                } else {
+                    pair.as_str().to_string()
+                }
+            }
+        }
+    }
+}
+
+impl ToSource for Variable {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        config
+            .variable
+            .replace("$visibility", self.visibility.source(config))
+            .replace("$ident", &self.ident)
+            .replace(
+                "$value",
+                if self.value.is_empty() {
+                    config.nil
+                } else {
+                    &self.value
+                },
+            )
+            .replace("$typename", &self.r#type.ident)
+    }
+}
+
+impl From<(String, Type)> for Variable {
+    fn from(value: (String, Type)) -> Self {
+        Self {
+            ident: value.0,
+            r#type: value.1,
+            value: String::new(),
+            visibility: TypeVisibility::Private,
+            mutable: MutabilityModifier::Constant,
+            is_referenced: false,
+            uninitialized: false,
+            align: None,
+            size: None,
+        }
+    }
+}
+
+impl From<(String, Type, TypeVisibility)> for Variable {
+    fn from(value: (String, Type, TypeVisibility)) -> Self {
+        Self {
+            ident: value.0,
+            r#type: value.1,
+            value: String::new(),
+            visibility: value.2,
+            mutable: MutabilityModifier::Constant,
+            is_referenced: false,
+            uninitialized: false,
+            align: None,
+            size: None,
+        }
+    }
+}
+
+impl From<Pair<'_, Rule>> for Variable {
+    fn from(value: Pair<'_, Rule>) -> Self {
         let mut inner = value.into_inner();
 
         let mut name = String::new();
@@ -318,11 +1180,13 @@ impl From<Pair<'_, Rule>> for Variable {
         let mut value: String = String::new();
         let mut visibility: TypeVisibility = TypeVisibility::Private;
         let mut mutable: MutabilityModifier = MutabilityModifier::Constant;
+        let mut attributes: Vec<Pair<'_, Rule>> = Vec::new();
 
         while let Some(pair) = inner.next() {
             let rule = pair.as_rule();
 
             match rule {
+                Rule::variable_attribute => attributes.push(pair),
                 Rule::identifier => {
                     if name.is_empty() {
                         name = pair.as_str().to_string()
@@ -342,7 +1206,8 @@ impl From<Pair<'_, Rule>> for Variable {
                             fcompiler_error!("{}", "cannot do compiler call in an enum")
                         }
                         _ => {
-                            if r#type.ident.is_empty() {
+                            // arithmetic can't be typed without registers
+                            if r#type.ident.is_empty() && (rule != Rule::ordered_mathematical) {
                                 // guess type, it wasn't provided (likely a reassignment)
                                 r#type =
                                     Type::from_parser_type(pair.clone(), &Registers::default());
@@ -355,14 +1220,25 @@ impl From<Pair<'_, Rule>> for Variable {
             }
         }
 
-        Variable {
+        let uninitialized = value.is_empty();
+
+        let mut variable = Variable {
             ident: name.clone(),
             r#type,
             value,
             visibility,
             mutable,
             is_referenced: false,
+            uninitialized,
+            align: None,
+            size: None,
+        };
+
+        for attribute in attributes {
+            variable.apply_attribute(attribute);
         }
+
+        variable
     }
 }
 
@@ -374,89 +1250,234 @@ impl From<(Pair<'_, Rule>, &Registers)> for Variable {
         let mut name = String::new();
         let mut r#type = Type::default();
         let mut value: String = String::new();
-        let mut visibility: TypeVisibility = TypeVisibility::Private;
+        let mut visibility: TypeVisibility = reg.default_visibility.clone();
         let mut mutable: MutabilityModifier = MutabilityModifier::Constant;
+        let mut attributes: Vec<Pair<'_, Rule>> = Vec::new();
+        let mut inferred: bool = false;
 
         while let Some(pair) = inner.next() {
             let rule = pair.as_rule();
 
             match rule {
-                Rule::identifier => {
-                    if name.is_empty() {
-                        name = pair.as_str().to_string()
-                    } else {
-                        let var = reg.get_var(pair.as_str());
-
-                        // since we're assigning the value of another variable to this
-                        // variable, we need to make sure we referenced the other variable
-                        if !var.is_referenced {
-                            fcompiler_general_error(CompilerError::ExpectedReference, var.ident);
-                        }
-
-                        // ...
-                        value = var.ident;
-                        r#type = TYPE_NAME_REF.into();
-                    }
-                }
+                Rule::variable_attribute => attributes.push(pair),
+                Rule::identifier if name.is_empty() => name = pair.as_str().to_string(),
                 Rule::type_modifier => visibility = pair.into(),
                 Rule::mutability_modifier => mutable = pair.into(),
                 Rule::r#type => r#type = (pair, reg).into(),
+                Rule::let_type => inferred = true,
                 _ => {
-                    value = match rule {
-                        // process blocks before using as value
-                        Rule::block => crate::process(pair.into_inner(), Registers::default()).0,
-                        // everything else just needs to be stringified
-                        Rule::call => {
-                            let call = FunctionCall::from(pair);
-                            let supplied_types = call.arg_types(reg);
-                            call.check_multiple(supplied_types, reg);
-
-                            // check function return type
-                            let function = reg.get_fn(&call.ident);
-                            if function.return_type != r#type {
-                                fcompiler_general_error(
-                                    CompilerError::InvalidType,
-                                    format!(
-                                        "cannot assign \"{}\" to \"{}\"",
-                                        function.return_type.ident, r#type.ident
-                                    ),
-                                )
-                            }
-
-                            // ...
-                            call.transform()
-                        }
-                        _ => {
-                            let t = Type::from_parser_type(pair.clone(), reg);
-                            let expanded_type = reg.get_type(&r#type.ident);
-
-                            if (t != expanded_type) && t.ident != TYPE_NAME_TABLE {
-                                // tables can be assigned to anything since everything
-                                // in lua is *technically* a table
-                                fcompiler_general_error(
-                                    CompilerError::InvalidType,
-                                    format!(
-                                        "cannot assign \"{}\" to \"{}\"",
-                                        t.ident, expanded_type.ident
-                                    ),
-                                )
-                            }
-
-                            pair.as_str().to_string()
-                        }
+                    if inferred {
+                        r#type = Variable::infer_type(&pair, reg);
                     }
+
+                    value = Variable::value_source(pair, &mut r#type, &mutable, reg)
                 }
             }
         }
 
-        Variable {
+        let uninitialized = value.is_empty();
+
+        if uninitialized && inferred {
+            // there's nothing to infer the type from
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!("\"{name}\" needs a value to infer its type from"),
+            )
+        }
+
+        if uninitialized && (mutable == MutabilityModifier::Constant) {
+            // nothing could ever assign it
+            fcompiler_general_error(CompilerError::ConstWithoutValue, name)
+        }
+
+        let mut variable = Variable {
             ident: name.clone(),
             r#type,
             value,
             visibility,
             mutable,
             is_referenced: false,
+            uninitialized,
+            align: None,
+            size: None,
+        };
+
+        if !attributes.is_empty() {
+            // there's no memory layout to control in lua
+            fcompiler_general_warning(CompilerError::IgnoredAttribute, name);
+        }
+
+        for attribute in attributes {
+            variable.apply_attribute(attribute);
+        }
+
+        variable
+    }
+}
+
+/// Several variables declared in one statement (`int a, b = 1, 2`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MultiVariable(pub Vec<Variable>);
+
+impl ToSource for MultiVariable {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let first = self
+            .0
+            .first()
+            .expect("multiple variables require a variable");
+
+        // a single statement with every variable (destructured variables only have one
+        // value, on the first variable)
+        let mut variable = first.clone();
+        variable.ident = self
+            .0
+            .iter()
+            .map(|v| v.ident.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        variable.value = self
+            .0
+            .iter()
+            .filter(|v| !v.value.is_empty())
+            .map(|v| v.value.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        variable.transform(config)
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for MultiVariable {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let reg = value.1;
+
+        let mut idents: Vec<String> = Vec::new();
+        let mut values: Vec<Pair<'_, Rule>> = Vec::new();
+        let mut r#type = Type::default();
+        let mut visibility: TypeVisibility = reg.default_visibility.clone();
+        let mut mutable: MutabilityModifier = MutabilityModifier::Constant;
+        let mut inferred: bool = false;
+
+        for pair in value.0.into_inner() {
+            match pair.as_rule() {
+                Rule::type_modifier => visibility = pair.into(),
+                Rule::mutability_modifier => mutable = pair.into(),
+                Rule::r#type => r#type = (pair, reg).into(),
+                Rule::let_type => inferred = true,
+                Rule::multi_ident => {
+                    idents = pair.into_inner().map(|p| p.as_str().to_string()).collect()
+                }
+                _ => values.push(pair),
+            }
+        }
+
+        if (values.len() == 1) && (values[0].as_rule() == Rule::call) {
+            // `let a, b = f()` destructures the tuple returned by `f`
+            let call = values.remove(0);
+            return Self::destructure(idents, call, r#type, inferred, visibility, mutable, reg);
+        }
+
+        if idents.len() != values.len() {
+            // every variable needs its own value
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "expected {} values, received {}",
+                    idents.len(),
+                    values.len()
+                ),
+            )
+        }
+
+        let mut variables: Vec<Variable> = Vec::new();
+
+        for (ident, value) in std::iter::zip(idents, values) {
+            // each variable is checked against its own value
+            let mut variable_type = if inferred {
+                Variable::infer_type(&value, reg)
+            } else {
+                r#type.clone()
+            };
+            let value = Variable::value_source(value, &mut variable_type, &mutable, reg);
+
+            variables.push(Variable {
+                ident,
+                r#type: variable_type,
+                value,
+                visibility: visibility.clone(),
+                mutable: mutable.clone(),
+                is_referenced: false,
+                uninitialized: false,
+                align: None,
+                size: None,
+            });
+        }
+
+        Self(variables)
+    }
+}
+
+impl MultiVariable {
+    /// Declare a variable for every value of the [`TYPE_NAME_TUPLE`] returned by `call`.
+    ///
+    /// Explicitly typed variables (`int a, b = f()`) must match every value.
+    fn destructure(
+        idents: Vec<String>,
+        call: Pair<'_, Rule>,
+        r#type: Type,
+        inferred: bool,
+        visibility: TypeVisibility,
+        mutable: MutabilityModifier,
+        reg: &Registers,
+    ) -> Self {
+        let tuple = Variable::infer_type(&call, reg);
+
+        if tuple.ident != TYPE_NAME_TUPLE {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "expected {} values, received \"{}\"",
+                    idents.len(),
+                    tuple.ident
+                ),
+            )
+        }
+
+        if tuple.generics.len() != idents.len() {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "expected {} values, received a tuple of {}",
+                    idents.len(),
+                    tuple.generics.len()
+                ),
+            )
+        }
+
+        let value = Variable::call_source(call, &tuple, &mutable, reg);
+        let mut variables: Vec<Variable> = Vec::new();
+
+        for (i, (ident, generic)) in std::iter::zip(idents, &tuple.generics).enumerate() {
+            let variable_type: Type = reg.get_type(generic);
+
+            if !inferred && (variable_type != r#type) {
+                fcompiler_type_error(r#type.ident.clone(), variable_type.ident.clone())
+            }
+
+            variables.push(Variable {
+                ident,
+                r#type: variable_type,
+                value: if i == 0 { value.clone() } else { String::new() },
+                visibility: visibility.clone(),
+                mutable: mutable.clone(),
+                is_referenced: false,
+                uninitialized: false,
+                align: None,
+                size: None,
+            });
         }
+
+        Self(variables)
     }
 }
 
@@ -466,6 +1487,173 @@ pub struct StructField {
     pub ident: String,
     pub r#type: Type,
     pub visibility: TypeVisibility,
+    /// The value used when the field is left out during construction.
+    pub default: Option<String>,
+}
+
+impl StructField {
+    /// Check a value given to this field and get its source.
+    pub fn value_source(&self, value: Pair<'_, Rule>, reg: &Registers) -> String {
+        // variables are copied into the field, everything else is checked like the
+        // value of a variable of the field's type
+        match value.as_rule() {
+            Rule::identifier => {
+                let t = Type::from_parser_type(value.clone(), reg);
+                if t != reg.get_type(&self.r#type.ident) {
+                    fcompiler_type_error(self.r#type.ident.clone(), t.ident);
+                }
+
+                identifier_source(value.as_str(), reg)
+            }
+            _ => Variable::value_source(
+                value,
+                &mut self.r#type.clone(),
+                &MutabilityModifier::Mutable,
+                reg,
+            ),
+        }
+    }
+}
+
+/// Check the keys of a table literal against the key generic of `table`.
+fn check_table_keys(pair: Pair<'_, Rule>, table: &Type, reg: &Registers) {
+    for item in pair.into_inner() {
+        let key = item.into_inner().next().unwrap().as_str();
+
+        // `{ [k] = v }` is computed, `{ k = v }` is a string
+        let key = match key.strip_prefix("[").and_then(|k| k.strip_suffix("]")) {
+            Some(index) => reg.key_type(index),
+            None => TYPE_NAME_STRING.into(),
+        };
+
+        reg.check_key(table, key);
+    }
+}
+
+/// A struct constructed from a table (`Point p = { x = 1 }`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructLiteral {
+    /// The fields of the struct and their values (in order of appearance).
+    pub fields: Vec<(String, String)>,
+    /// The [`crate::config::TypeTemplate`] of the struct.
+    pub template: Option<String>,
+}
+
+impl ToSource for StructLiteral {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let mut body: String = String::new();
+
+        for (ident, value) in &self.fields {
+            body.push_str(
+                &config
+                    .struct_literal_field
+                    .replace("$ident", ident)
+                    .replace("$value", value),
+            );
+        }
+
+        let Some(template) = &self.template else {
+            return config.struct_literal.replace("$body", &body);
+        };
+
+        // custom representation, fields can also be used individually (longest
+        // first so `$ab` isn't replaced by the value of `$a`)
+        let mut out = config
+            .type_template(template)
+            .literal
+            .replace("$body", &body);
+
+        let mut fields: Vec<&(String, String)> = self.fields.iter().collect();
+        fields.sort_by_key(|(ident, _)| std::cmp::Reverse(ident.len()));
+
+        for (ident, value) in fields {
+            out = out.replace(&format!("${ident}"), value);
+        }
+
+        out
+    }
+}
+
+impl StructLiteral {
+    /// The fields of the given struct which have a default value (what its `new`
+    /// constructor starts with).
+    pub fn defaults(r#type: &Type) -> Self {
+        Self {
+            fields: r#type
+                .properties
+                .iter()
+                .filter_map(|(ident, field)| Some((ident.clone(), field.default.clone()?)))
+                .collect(),
+            template: None,
+        }
+    }
+
+    /// The given fields of a struct, with the fields which were left out set to their
+    /// defaults.
+    fn with_defaults(mut fields: Vec<(String, String)>, r#type: &Type) -> Self {
+        for (ident, field) in &r#type.properties {
+            if fields.iter().any(|(key, _)| key == ident) {
+                continue;
+            }
+
+            match &field.default {
+                Some(default) => fields.push((ident.clone(), default.clone())),
+                None => fcompiler_general_error(
+                    CompilerError::MissingField,
+                    format!("{}.{ident}", r#type.ident),
+                ),
+            }
+        }
+
+        Self {
+            fields,
+            template: r#type.template.clone(),
+        }
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Type)> for StructLiteral {
+    /// Fill the fields missing from the given table with their defaults.
+    fn from(value: (Pair<'_, Rule>, &Type)) -> Self {
+        let r#type = value.1;
+        let mut fields: Vec<(String, String)> = Vec::new();
+
+        for item in value.0.into_inner() {
+            let mut inner = item.into_inner();
+            let key = inner.next().unwrap().as_str().to_string();
+            fields.push((key, inner.next().unwrap().as_str().to_string()));
+        }
+
+        Self::with_defaults(fields, r#type)
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for StructLiteral {
+    /// A struct value (`Point { x: 1 }`), checked against the fields of its type.
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let reg = value.1;
+        let mut inner = value.0.into_inner();
+        let r#type = inner.next().unwrap().into_inner().next().unwrap();
+        let r#type = reg.get_type(r#type.as_str());
+        let mut fields: Vec<(String, String)> = Vec::new();
+
+        for block in inner {
+            let mut inner = block.into_inner();
+            let key = inner.next().unwrap().as_str().to_string();
+            let value = inner.next().unwrap();
+
+            let Some(field) = r#type.properties.get(&key) else {
+                fcompiler_general_error(
+                    CompilerError::NoSuchProperty,
+                    format!("{}.{key}", r#type.ident),
+                )
+            };
+
+            fields.push((key, field.value_source(value, reg)));
+        }
+
+        Self::with_defaults(fields, &r#type)
+    }
 }
 
 /// A simple type structure.
@@ -476,28 +1664,41 @@ pub struct Type {
     /// Registered fields on a type. Empty for regular types; populated for structs.
     pub properties: BTreeMap<String, StructField>,
     pub variants: BTreeMap<String, Variable>,
+    /// Enum variants carrying values (`Circle(float)`), by the types of their values.
+    #[serde(default)]
+    pub payloads: BTreeMap<String, Vec<Type>>,
     pub visibility: TypeVisibility,
+    /// The name of the [`crate::config::TypeTemplate`] used to emit this type
+    /// (set with `@template(name)`).
+    pub template: Option<String>,
+    /// If the type also accepts `nil` (`int?`).
+    #[serde(default)]
+    pub optional: bool,
 }
 
 impl PartialEq for Type {
     fn eq(&self, other: &Self) -> bool {
-        // remove import var from identifiers
-        let mut split = self.ident.split(".");
-        let mut ident = split.next().unwrap();
+        // remove import vars from identifiers (modules can be nested, `a.b.Type`)
+        let ident = self.ident.rsplit(".").next().unwrap();
+        let other_ident = other.ident.rsplit(".").next().unwrap();
 
-        if let Some(i) = split.next() {
-            ident = i;
+        // "any" types are always equal to anything
+        if (ident == "any") | (other_ident == "any") {
+            return true;
         }
 
-        let mut split = other.ident.split(".");
-        let mut other_ident = split.next().unwrap();
-
-        if let Some(i) = split.next() {
-            other_ident = i;
+        // "number" is either an "int" or a "float"
+        let numbers = [TYPE_NAME_INT, TYPE_NAME_FLOAT, TYPE_NAME_NUMBER];
+        if ((ident == TYPE_NAME_NUMBER) && numbers.contains(&other_ident))
+            | ((other_ident == TYPE_NAME_NUMBER) && numbers.contains(&ident))
+        {
+            return true;
         }
 
-        // "any" types are always equal to anything
-        if (ident == "any") | (other_ident == "any") {
+        // "nil" is only equal to optional types
+        if ((ident == TYPE_NAME_NIL) && other.optional)
+            | ((other_ident == TYPE_NAME_NIL) && self.optional)
+        {
             return true;
         }
 
@@ -515,6 +1716,72 @@ impl Eq for Type {
 }
 
 impl Type {
+    /// If this is the empty (unit) type, which has no value.
+    pub fn is_empty(&self) -> bool {
+        (self.ident == TYPE_NAME_EMPTY) || (self.ident == TYPE_NAME_EMPTY_ALT)
+    }
+
+    /// The parameter names of an enum variant constructor taking `count` values.
+    fn payload_keys(count: usize) -> Vec<String> {
+        (1..=count).map(|i| format!("v{i}")).collect()
+    }
+
+    /// The constructor functions (`Enum.Variant(...)`) of the enum variants carrying values.
+    pub fn constructors(&self) -> Vec<Function> {
+        self.payloads
+            .iter()
+            .map(|(variant, types)| Function {
+                ident: format!("{}.{}", self.ident, variant),
+                arguments: FunctionArguments {
+                    keys: Self::payload_keys(types.len()),
+                    types: types.clone(),
+                    variadic: false,
+                    optional: 0,
+                },
+                return_type: Type {
+                    ident: self.ident.clone(),
+                    ..Default::default()
+                },
+                body: String::new(),
+                // variants are as visible as the enum itself
+                visibility: TypeVisibility::Public,
+                execution: ExecutionType::Sync,
+                association: AssociationType::Static,
+                constant: false,
+                export_name: None,
+                generics: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Check the default values of the fields of a struct against their types, and
+    /// lower them like the value of a variable.
+    pub fn lower_defaults(&mut self, pair: Pair<'_, Rule>, registers: &Registers) {
+        let block = pair
+            .into_inner()
+            .find(|p| p.as_rule() == Rule::struct_block)
+            .expect("struct requires a block");
+
+        for field in block.into_inner() {
+            let mut inner = field
+                .into_inner()
+                .skip_while(|p| p.as_rule() != Rule::r#type);
+            inner.next();
+
+            let Some(ident) = inner.next() else {
+                continue;
+            };
+
+            let Some(value) = inner.next() else {
+                continue;
+            };
+
+            if let Some(field) = self.properties.get_mut(ident.as_str()) {
+                field.default = Some(field.value_source(value, registers));
+            }
+        }
+    }
+
     /// Get a [`Type`] given a parser [`Pair`]. Resolves register references.
     pub fn from_parser_type(pair: Pair<'_, Rule>, registers: &Registers) -> Self {
         let rule = pair.as_rule();
@@ -522,7 +1789,30 @@ impl Type {
             Rule::string => (TYPE_NAME_STRING, TypeVisibility::Public).to_owned().into(),
             Rule::integer => (TYPE_NAME_INT, TypeVisibility::Public).to_owned().into(),
             Rule::float => (TYPE_NAME_FLOAT, TypeVisibility::Public).to_owned().into(),
+            Rule::boolean => (TYPE_NAME_BOOLEAN, TypeVisibility::Public)
+                .to_owned()
+                .into(),
+            Rule::nil => (TYPE_NAME_NIL, TypeVisibility::Public).to_owned().into(),
+            Rule::closure => (TYPE_NAME_FUNCTION, TypeVisibility::Public)
+                .to_owned()
+                .into(),
             Rule::identifier => {
+                // numbers can be parsed as identifiers inside of expressions
+                if pair.as_str().parse::<i64>().is_ok() {
+                    return (TYPE_NAME_INT, TypeVisibility::Public).to_owned().into();
+                } else if pair.as_str().parse::<f64>().is_ok() {
+                    return (TYPE_NAME_FLOAT, TypeVisibility::Public).to_owned().into();
+                }
+
+                // so are booleans and lengths (`#t`)
+                if (pair.as_str() == "true") | (pair.as_str() == "false") {
+                    return (TYPE_NAME_BOOLEAN, TypeVisibility::Public)
+                        .to_owned()
+                        .into();
+                } else if pair.as_str().starts_with("#") {
+                    return (TYPE_NAME_INT, TypeVisibility::Public).to_owned().into();
+                }
+
                 // since this is a variable reference, we must get the type of that
                 // variable from the registers
                 let variable = registers.get_var(pair.as_str());
@@ -531,13 +1821,33 @@ impl Type {
             Rule::call => {
                 // since this is a function call, we must get the return type of
                 // the function that is being called
-                let mut inner = pair.into_inner();
+                let mut inner = pair.clone().into_inner();
                 let ident = inner
                     .next()
                     .expect("function call requires a function ident to call");
 
                 let function = registers.get_fn(ident.as_str());
-                function.return_type.clone()
+
+                if function.generics.is_empty() {
+                    return function.return_type;
+                }
+
+                // generic functions return the types their parameters are bound to
+                let call = FunctionCall::from((pair, registers));
+                function.instantiate(&call.arg_types(registers)).return_type
+            }
+            Rule::ordered_mathematical => Arithmetic::from((pair, registers)).r#type(),
+            Rule::ordered_comparison => Comparison::from((pair, registers)).r#type(),
+            Rule::ordered_cast => Cast::from((pair, registers)).r#type,
+            Rule::struct_value => {
+                let r#type = pair
+                    .into_inner()
+                    .next()
+                    .unwrap()
+                    .into_inner()
+                    .next()
+                    .unwrap();
+                registers.get_type(r#type.as_str())
             }
             Rule::table => (
                 TYPE_NAME_TABLE,
@@ -557,7 +1867,10 @@ impl From<String> for Type {
             generics: Vec::new(),
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: TypeVisibility::Private,
+            template: None,
+            optional: false,
         }
     }
 }
@@ -569,7 +1882,10 @@ impl From<&str> for Type {
             generics: Vec::new(),
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: TypeVisibility::Private,
+            template: None,
+            optional: false,
         }
     }
 }
@@ -581,7 +1897,10 @@ impl From<(String, TypeVisibility)> for Type {
             generics: Vec::new(),
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: value.1,
+            template: None,
+            optional: false,
         }
     }
 }
@@ -593,7 +1912,10 @@ impl From<(&str, TypeVisibility)> for Type {
             generics: Vec::new(),
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: value.1,
+            template: None,
+            optional: false,
         }
     }
 }
@@ -605,7 +1927,10 @@ impl From<(String, Vec<String>, TypeVisibility)> for Type {
             generics: value.1,
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: value.2,
+            template: None,
+            optional: false,
         }
     }
 }
@@ -617,7 +1942,10 @@ impl From<(&str, Vec<String>, TypeVisibility)> for Type {
             generics: value.1,
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: value.2,
+            template: None,
+            optional: false,
         }
     }
 }
@@ -629,12 +1957,19 @@ impl From<Pair<'_, Rule>> for Type {
         let mut ident: String = String::new();
         let mut properties: BTreeMap<String, StructField> = BTreeMap::new();
         let mut variants: BTreeMap<String, Variable> = BTreeMap::new();
+        let mut payloads: BTreeMap<String, Vec<Type>> = BTreeMap::new();
         let mut visibility: TypeVisibility = TypeVisibility::Private;
+        let mut template: Option<String> = None;
+        let mut optional: bool = false;
 
         for pair in inner {
             let rule = pair.as_rule();
 
             match rule {
+                Rule::optional => optional = true,
+                Rule::type_template => {
+                    template = Some(pair.into_inner().next().unwrap().as_str().to_string())
+                }
                 Rule::generic => {
                     let inner = pair.into_inner();
 
@@ -646,8 +1981,13 @@ impl From<Pair<'_, Rule>> for Type {
                         generics.push(pair.as_str().to_string())
                     }
                 }
+                Rule::shadow_attribute => continue,
                 Rule::type_modifier => visibility = pair.into(),
                 Rule::identifier => ident = pair.as_str().to_string(),
+                Rule::tuple_type => {
+                    ident = TYPE_NAME_TUPLE.to_string();
+                    generics = pair.into_inner().map(|p| p.as_str().to_string()).collect();
+                }
                 Rule::r#type => {
                     let t: Type = pair.into();
                     ident = t.ident;
@@ -664,6 +2004,7 @@ impl From<Pair<'_, Rule>> for Type {
                                 let mut ident: String = String::new();
                                 let mut r#type: Type = Type::default();
                                 let mut visibility: TypeVisibility = TypeVisibility::Private;
+                                let mut default: Option<String> = None;
 
                                 let mut inner = pair.into_inner();
                                 while let Some(pair) = inner.next() {
@@ -672,8 +2013,11 @@ impl From<Pair<'_, Rule>> for Type {
                                     match rule {
                                         Rule::type_modifier => visibility = pair.into(),
                                         Rule::r#type => r#type = pair.into(),
-                                        Rule::identifier => ident = pair.as_str().to_string(),
-                                        _ => unreachable!("reached impossible rule in struct type"),
+                                        Rule::identifier if ident.is_empty() => {
+                                            ident = pair.as_str().to_string()
+                                        }
+                                        // anything after the identifier is the default value
+                                        _ => default = Some(pair.as_str().to_string()),
                                     }
                                 }
 
@@ -682,6 +2026,7 @@ impl From<Pair<'_, Rule>> for Type {
                                         ident,
                                         r#type,
                                         visibility,
+                                        default,
                                     });
                                 }
                             }
@@ -692,7 +2037,17 @@ impl From<Pair<'_, Rule>> for Type {
                 Rule::enum_block => {
                     let mut inner = pair.into_inner();
                     while let Some(pair) = inner.next() {
-                        let var = Variable::from(pair.into_inner().next().unwrap());
+                        let pair = pair.into_inner().next().unwrap();
+
+                        if pair.as_rule() == Rule::enum_payload {
+                            // constructed with a function, so there's no value to store
+                            let mut inner = pair.into_inner();
+                            let ident = inner.next().unwrap().as_str().to_string();
+                            payloads.insert(ident, inner.map(Type::from).collect());
+                            continue;
+                        }
+
+                        let var = Variable::from(pair);
                         variants.insert(var.ident.clone(), var);
                     }
                 }
@@ -705,7 +2060,10 @@ impl From<Pair<'_, Rule>> for Type {
             ident,
             properties,
             variants,
+            payloads,
             visibility,
+            template,
+            optional,
         }
     }
 }
@@ -739,38 +2097,58 @@ impl Default for Type {
             generics: Vec::new(),
             properties: BTreeMap::new(),
             variants: BTreeMap::new(),
+            payloads: BTreeMap::new(),
             visibility: TypeVisibility::Private,
+            template: None,
+            optional: false,
         }
     }
 }
 
 impl ToSource for Type {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
+    fn transform(&self, config: &CompilerConfig) -> String {
+        // enum, create with variants in table
+        let mut body: String = String::new();
 
-        if !self.variants.is_empty() {
-            // enum, create with variants in table
-            let mut body: String = String::new();
-
-            for variant in &self.variants {
-                body.push_str(
-                    &config
-                        .enum_field
-                        .replace("$ident", variant.0)
-                        .replace("$value", &variant.1.value),
-                );
-            }
+        for variant in &self.variants {
+            body.push_str(
+                &config
+                    .enum_field
+                    .replace("$ident", variant.0)
+                    .replace("$value", &variant.1.value),
+            );
+        }
+
+        for (ident, types) in &self.payloads {
+            body.push_str(
+                &config
+                    .enum_constructor
+                    .replace("$ident", ident)
+                    .replace("$args", &Self::payload_keys(types.len()).join(", ")),
+            );
+        }
+
+        if let Some(template) = &self.template {
+            // custom representation
+            return config
+                .type_template(template)
+                .definition
+                .replace("$visibility", self.visibility.source(config))
+                .replace("$ident", &self.ident)
+                .replace("$body", &body);
+        }
 
+        if !self.variants.is_empty() || !self.payloads.is_empty() {
             return config
                 .r#enum
-                .replace("$visibility", &self.visibility.to_string())
+                .replace("$visibility", self.visibility.source(config))
                 .replace("$ident", &self.ident)
                 .replace("$body", &body);
         }
 
         config
             .r#type
-            .replace("$visibility", &self.visibility.to_string())
+            .replace("$visibility", self.visibility.source(config))
             .replace("$ident", &self.ident)
     }
 }
@@ -783,13 +2161,11 @@ pub struct TypeAlias {
 }
 
 impl ToSource for TypeAlias {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
-
+    fn transform(&self, config: &CompilerConfig) -> String {
         config
             .type_alias
-            .replace("$visibility", &self.visibility.to_string())
-            .replace("$ident", &self.ident.transform())
+            .replace("$visibility", self.visibility.source(config))
+            .replace("$ident", &self.ident.transform(config))
             .replace("$value", &self.r#type.ident)
     }
 }
@@ -807,6 +2183,7 @@ impl From<Pair<'_, Rule>> for TypeAlias {
             let rule = pair.as_rule();
 
             match rule {
+                Rule::shadow_attribute => continue,
                 Rule::type_modifier => visibility = pair.into(),
                 Rule::r#type => {
                     if !ident_type_defined {
@@ -837,6 +2214,21 @@ pub enum TypeVisibility {
     Private,
 }
 
+impl TypeVisibility {
+    /// Get the visibility of an item from its `type_modifier`, falling back to the
+    /// module's [`Registers::default_visibility`].
+    pub fn of(pair: &Pair<'_, Rule>, registers: &Registers) -> Self {
+        match pair
+            .clone()
+            .into_inner()
+            .find(|p| p.as_rule() == Rule::type_modifier)
+        {
+            Some(modifier) => modifier.into(),
+            None => registers.default_visibility.clone(),
+        }
+    }
+}
+
 impl From<Pair<'_, Rule>> for TypeVisibility {
     fn from(value: Pair<Rule>) -> Self {
         match value.as_str() {
@@ -847,14 +2239,29 @@ impl From<Pair<'_, Rule>> for TypeVisibility {
     }
 }
 
-impl Display for TypeVisibility {
+impl Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let config = COMPILER_TEMPLATES.read().unwrap();
+        if self.generics.is_empty() {
+            write!(f, "{}", self.ident)
+        } else {
+            write!(f, "{}<{}>", self.ident, self.generics.join(", "))
+        }
+    }
+}
 
-        write!(f, "{}", match self {
+impl TypeVisibility {
+    /// Get the source of the visibility modifier in the given config.
+    pub fn source<'a>(&self, config: &CompilerConfig<'a>) -> &'a str {
+        match self {
             Self::Public => config.visibility_public,
             Self::Private => config.visibility_private,
-        })
+        }
+    }
+}
+
+impl Display for TypeVisibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source(&COMPILER_TEMPLATES.read().unwrap()))
     }
 }
 
@@ -886,6 +2293,118 @@ impl Display for MutabilityModifier {
     }
 }
 
+/// Get the source of an identifier, lowering string slices (`s[start..end]`) and
+/// indexes (`s[i]`) to the `string_slice` template. Table indexes (`t[i]`) are
+/// lowered to the `checked_index` template if [`COMPILER_BOUNDS_CHECKS`] is set.
+pub fn identifier_source(key: &str, registers: &Registers) -> String {
+    if let Some(var) = registers.variables.get(key) {
+        if var.uninitialized {
+            fcompiler_general_warning(CompilerError::Uninitialized, key.to_string());
+        }
+    }
+
+    if let Some((ident, start, end)) = registers.string_slice(key) {
+        return registers
+            .config
+            .string_slice
+            .replace("$ident", &ident)
+            .replace("$start", &start)
+            .replace("$end", &end);
+    }
+
+    match registers.int_index(key) {
+        Some((table, index)) if *COMPILER_BOUNDS_CHECKS.read().unwrap() => registers
+            .config
+            .checked_index
+            .replace("$table", &table)
+            .replace("$index", &index),
+        _ => key.to_string(),
+    }
+}
+
+/// Check if the text between the braces of `"{...}"` names a variable (`a` or `a.b`).
+fn is_interpolated_key(key: &str) -> bool {
+    key.split('.').all(|part| {
+        part.starts_with(|c: char| c.is_ascii_alphabetic() || (c == '_'))
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || (c == '_'))
+    })
+}
+
+/// Get the source of a string literal, lowering interpolated variables (`"a {b}"`)
+/// to a concatenation of the string and the variables. Variables which aren't
+/// strings are converted with the `to_string` template. `\{` is a literal `{`, and
+/// so is a `{` which isn't followed by an identifier and a `}`.
+///
+/// Strings without interpolation are returned as they are.
+pub fn interpolate(string: &str, registers: &Registers) -> String {
+    if !string.contains('{') {
+        return string.to_string();
+    }
+
+    let config = &registers.config;
+    let mut parts: Vec<String> = Vec::new();
+    let mut literal = String::new();
+    let mut chars = string[1..string.len() - 1].chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => match chars.next() {
+                Some('{') => literal.push('{'),
+                Some(escaped) => {
+                    literal.push('\\');
+                    literal.push(escaped);
+                }
+                None => literal.push('\\'),
+            },
+            '{' => {
+                let mut key = String::new();
+                let mut rest = chars.clone();
+                let mut closed = false;
+
+                for char in rest.by_ref() {
+                    if char == '}' {
+                        closed = true;
+                        break;
+                    }
+
+                    key.push(char);
+                }
+
+                // anything other than `{identifier}` is part of the string (`"{}"`)
+                let key = key.trim();
+                if !closed || !is_interpolated_key(key) {
+                    literal.push(char);
+                    continue;
+                }
+
+                chars = rest;
+                let var = registers.get_var(key);
+
+                if !literal.is_empty() {
+                    parts.push(format!("\"{literal}\""));
+                    literal.clear();
+                }
+
+                let source = identifier_source(key, registers);
+                if registers.get_type(&var.r#type.ident).ident == TYPE_NAME_STRING {
+                    parts.push(source);
+                } else {
+                    parts.push(config.to_string.replace("$value", &source));
+                }
+            }
+            _ => literal.push(char),
+        }
+    }
+
+    if !literal.is_empty() || parts.is_empty() {
+        parts.push(format!("\"{literal}\""));
+    }
+
+    parts.join(config.concat)
+}
+
 /// A call to a stored function.
 #[derive(Debug)]
 pub struct FunctionCall<'a> {
@@ -896,6 +2415,39 @@ pub struct FunctionCall<'a> {
 }
 
 impl FunctionCall<'_> {
+    /// Make sure the call supplies as many arguments as `function` has parameters.
+    ///
+    /// The last parameter of a variadic function takes any number of arguments (even none),
+    /// and optional parameters can be left out.
+    pub fn check_argument_count(&self, function: &Function) {
+        let expected = function.arguments.types.len();
+        let supplied = self.arguments.len();
+        let required = expected.saturating_sub(function.arguments.optional);
+
+        if function.arguments.variadic {
+            let expected = required.saturating_sub(1);
+
+            if supplied < expected {
+                fcompiler_general_error(
+                    CompilerError::InvalidArgumentCount,
+                    format!("expected at least {expected}, received {supplied}"),
+                )
+            }
+        } else if required == expected {
+            if supplied != expected {
+                fcompiler_general_error(
+                    CompilerError::InvalidArgumentCount,
+                    format!("expected {expected}, received {supplied}"),
+                )
+            }
+        } else if (supplied < required) | (supplied > expected) {
+            fcompiler_general_error(
+                CompilerError::InvalidArgumentCount,
+                format!("expected {required} to {expected}, received {supplied}"),
+            )
+        }
+    }
+
     /// Get the [`Type`] of all arguments passed during a [`FunctionCall`].
     pub fn arg_types(&self, registers: &Registers) -> Vec<Type> {
         let mut types: Vec<Type> = Vec::new();
@@ -908,9 +2460,16 @@ impl FunctionCall<'_> {
     }
 }
 
-impl<'a> From<Pair<'a, Rule>> for FunctionCall<'a> {
-    fn from(value: Pair<'a, Rule>) -> Self {
-        let config = COMPILER_TEMPLATES.read().unwrap();
+impl<'a> FunctionCall<'a> {
+    /// Parse a [`FunctionCall`], resolving compiler builtins (like `typeof`) if
+    /// `registers` are available.
+    fn parse(value: Pair<'a, Rule>, registers: Option<&Registers>) -> Self {
+        // calls parsed without registers use the default config
+        let default = COMPILER_TEMPLATES.read().unwrap();
+        let config: &CompilerConfig = match registers {
+            Some(registers) => &registers.config,
+            None => &default,
+        };
 
         let mut src_out: String = String::new();
         let mut inner = value.into_inner();
@@ -932,31 +2491,96 @@ impl<'a> From<Pair<'a, Rule>> for FunctionCall<'a> {
                     } else {
                         // ident as argument
                         args_vec.push(pair.clone());
+
+                        let arg = pair.as_str().replace(",", "");
+                        let arg = match registers {
+                            Some(registers) => identifier_source(&arg, registers),
+                            None => arg,
+                        };
+
                         if args.is_empty() {
                             // first argument
-                            args.push_str(&pair.as_str().replace(",", ""))
+                            args.push_str(&arg)
                         } else {
                             // nth argument
-                            args.push_str(&(", ".to_string() + &pair.as_str().replace(",", "")))
+                            args.push_str(&(", ".to_string() + &arg))
                         }
                     }
                 }
                 Rule::block => {
-                    args.push_str(&crate::process(pair.into_inner(), Registers::default()).0)
+                    let block_registers = match registers {
+                        Some(registers) => registers.fresh(),
+                        None => Registers::default(),
+                    };
+
+                    args.push_str(&crate::process(pair.into_inner(), block_registers).0)
                 }
                 _ => {
                     args_vec.push(pair.clone());
+
+                    let arg = match (rule, registers) {
+                        // nested calls may contain builtins as well
+                        (Rule::call, Some(registers)) => {
+                            let call = FunctionCall::parse(pair, Some(registers));
+                            call.check_argument_count(&registers.get_fn(&call.ident));
+                            call.src_out
+                        }
+                        (Rule::string, Some(registers)) => interpolate(pair.as_str(), registers),
+                        (Rule::struct_value, Some(registers)) => {
+                            StructLiteral::from((pair, registers)).transform(&registers.config)
+                        }
+                        (Rule::closure, Some(registers)) => {
+                            Closure::from((pair, registers)).transform(&registers.config)
+                        }
+                        (Rule::ordered_cast, Some(registers)) => {
+                            Cast::from((pair, registers)).transform(&registers.config)
+                        }
+                        _ => pair.as_str().to_string(),
+                    };
+
                     if args.is_empty() {
                         // first argument
-                        args.push_str(pair.as_str())
+                        args.push_str(&arg)
                     } else {
                         // nth argument
-                        args.push_str(&(", ".to_string() + &pair.as_str().replace(",", "")))
+                        args.push_str(&(", ".to_string() + &arg.replace(",", "")))
                     }
                 }
             }
         }
 
+        if let Some(registers) = registers {
+            let function = registers.get_fn(&ident);
+            registers.check_access(&ident, &function);
+
+            // make sure async functions are awaited, and only async functions
+            match (function.execution, is_async) {
+                (ExecutionType::Async, false) => {
+                    fcompiler_general_error(CompilerError::AsyncNotAwaited, ident.clone())
+                }
+                (ExecutionType::Sync, true) => {
+                    fcompiler_general_error(CompilerError::CannotAwaitSync, ident.clone())
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(registers), "typeof") = (registers, ident.as_str()) {
+            // resolve to the static type of the argument
+            let arg = match args_vec.as_slice() {
+                [arg] => arg.clone(),
+                _ => fcompiler_error!("typeof requires exactly one argument"),
+            };
+
+            let r#type = Type::from_parser_type(arg, registers);
+
+            return Self {
+                ident,
+                src_out: format!("\"{type}\""),
+                arguments: args_vec,
+            };
+        }
+
         if is_async {
             src_out.push_str(
                 &config
@@ -981,12 +2605,413 @@ impl<'a> From<Pair<'a, Rule>> for FunctionCall<'a> {
     }
 }
 
+impl<'a> From<Pair<'a, Rule>> for FunctionCall<'a> {
+    fn from(value: Pair<'a, Rule>) -> Self {
+        Self::parse(value, None)
+    }
+}
+
+impl<'a> From<(Pair<'a, Rule>, &Registers)> for FunctionCall<'a> {
+    fn from(value: (Pair<'a, Rule>, &Registers)) -> Self {
+        Self::parse(value.0, Some(value.1))
+    }
+}
+
 impl ToSource for FunctionCall<'_> {
-    fn transform(&self) -> String {
+    fn transform(&self, _config: &CompilerConfig) -> String {
         self.src_out.to_owned()
     }
 }
 
+/// An arithmetic expression (`(lhs operator rhs)`).
+pub struct Arithmetic {
+    pub lhs: String,
+    pub lhs_type: Type,
+    pub operator: String,
+    pub rhs: String,
+    pub rhs_type: Type,
+}
+
+impl Arithmetic {
+    /// If this is a division of two integers.
+    pub fn is_integer_division(&self) -> bool {
+        (self.operator == "/")
+            && (self.lhs_type.ident == TYPE_NAME_INT)
+            && (self.rhs_type.ident == TYPE_NAME_INT)
+    }
+
+    /// Get the [`Type`] of the result of this expression.
+    ///
+    /// `/` is always float division, `//` is integer division.
+    pub fn r#type(&self) -> Type {
+        let types = [self.lhs_type.ident.as_str(), self.rhs_type.ident.as_str()];
+
+        let ident = if types.contains(&TYPE_NAME_ANY) {
+            TYPE_NAME_ANY
+        } else if self.operator == "//" {
+            TYPE_NAME_INT
+        } else if (self.operator == "/") | types.contains(&TYPE_NAME_FLOAT) {
+            TYPE_NAME_FLOAT
+        } else if types == [TYPE_NAME_INT, TYPE_NAME_INT] {
+            TYPE_NAME_INT
+        } else {
+            TYPE_NAME_NUMBER
+        };
+
+        (ident, TypeVisibility::Public).to_owned().into()
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Arithmetic {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        Self::with_target(value.0, value.1, None)
+    }
+}
+
+impl Arithmetic {
+    /// Get an expression whose result is assigned to a value of the `target` type.
+    ///
+    /// Divisions of two integers (nested ones included) are integer divisions if the
+    /// target is an `int`.
+    pub fn with_target(pair: Pair<'_, Rule>, reg: &Registers, target: Option<&Type>) -> Self {
+        let integer = target.is_some_and(|t| reg.get_type(&t.ident).ident == TYPE_NAME_INT);
+
+        // ordered_mathematical -> mathematical
+        let expression = pair.into_inner().next().unwrap();
+        let mut inner = expression.clone().into_inner();
+
+        let lhs = inner.next().unwrap();
+        let rhs = inner.next().unwrap();
+        let operator = crate::ast::operator(&expression, &lhs, &rhs);
+
+        // nested expressions have the same target
+        let operand = |pair: Pair<'_, Rule>| match pair.as_rule() {
+            Rule::ordered_mathematical => {
+                let nested = Arithmetic::with_target(pair, reg, target);
+                (nested.transform(&reg.config), nested.r#type())
+            }
+            Rule::ordered_cast => {
+                let cast = Cast::from((pair, reg));
+                (cast.transform(&reg.config), cast.r#type)
+            }
+            Rule::identifier => (
+                identifier_source(pair.as_str(), reg),
+                Type::from_parser_type(pair, reg),
+            ),
+            _ => (pair.as_str().to_string(), Type::from_parser_type(pair, reg)),
+        };
+
+        let (lhs, lhs_type) = operand(lhs);
+        let (rhs, rhs_type) = operand(rhs);
+
+        if !is_numeric(&lhs_type, reg) || !is_numeric(&rhs_type, reg) {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "cannot apply \"{operator}\" to \"{}\" and \"{}\"",
+                    lhs_type.ident, rhs_type.ident
+                ),
+            )
+        }
+
+        let mut expression = Self {
+            lhs_type,
+            lhs,
+            operator,
+            rhs_type,
+            rhs,
+        };
+
+        if integer && expression.is_integer_division() {
+            // int target, keep the result an int
+            expression.operator = "//".to_string();
+        }
+
+        expression
+    }
+}
+
+impl ToSource for Arithmetic {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        if self.operator == "//" {
+            return config
+                .integer_division
+                .replace("$lhs", &self.lhs)
+                .replace("$rhs", &self.rhs);
+        }
+
+        format!("({} {} {})", self.lhs, self.operator, self.rhs)
+    }
+}
+
+/// If values of the given [`Type`] can be used in arithmetic (and ordered comparisons).
+fn is_numeric(r#type: &Type, reg: &Registers) -> bool {
+    matches!(
+        reg.get_type(&r#type.ident).ident.as_str(),
+        TYPE_NAME_INT | TYPE_NAME_FLOAT | TYPE_NAME_NUMBER | TYPE_NAME_ANY
+    )
+}
+
+/// A comparison (`(lhs operator rhs)`), which is always a `bool`.
+///
+/// `==` and `!=` need operands of the same type (or two numbers, or a table), `<`,
+/// `<=`, `>` and `>=` need two numbers or two strings. `and` and `or` take anything.
+pub struct Comparison {
+    pub lhs: String,
+    pub lhs_type: Type,
+    pub operator: String,
+    pub rhs: String,
+    pub rhs_type: Type,
+}
+
+impl Comparison {
+    /// Get the [`Type`] of the result of this expression.
+    pub fn r#type(&self) -> Type {
+        (TYPE_NAME_BOOLEAN, TypeVisibility::Public)
+            .to_owned()
+            .into()
+    }
+
+    /// If the operands of this expression can be compared with its operator.
+    fn is_valid(&self, reg: &Registers) -> bool {
+        let numeric = is_numeric(&self.lhs_type, reg) && is_numeric(&self.rhs_type, reg);
+
+        match self.operator.as_str() {
+            "and" | "or" => true,
+            "==" | "!=" => {
+                // tables can hold anything (enums are tables of their variants' values)
+                let table = [&self.lhs_type, &self.rhs_type]
+                    .iter()
+                    .any(|t| t.ident == TYPE_NAME_TABLE);
+
+                numeric || table || (self.lhs_type == self.rhs_type)
+            }
+            _ => {
+                let strings = [&self.lhs_type, &self.rhs_type]
+                    .iter()
+                    .all(|t| matches!(t.ident.as_str(), TYPE_NAME_STRING | TYPE_NAME_ANY));
+
+                numeric || strings
+            }
+        }
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Comparison {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let reg = value.1;
+
+        // ordered_comparison -> comparison
+        let expression = value.0.into_inner().next().unwrap();
+        let mut inner = expression.clone().into_inner();
+
+        let lhs = inner.next().unwrap();
+        let rhs = inner.next().unwrap();
+        let operator = crate::ast::operator(&expression, &lhs, &rhs);
+
+        let source = |pair: Pair<'_, Rule>| match pair.as_rule() {
+            Rule::ordered_comparison => Comparison::from((pair, reg)).transform(&reg.config),
+            Rule::ordered_mathematical => Arithmetic::from((pair, reg)).transform(&reg.config),
+            Rule::ordered_cast => Cast::from((pair, reg)).transform(&reg.config),
+            Rule::identifier => identifier_source(pair.as_str(), reg),
+            _ => pair.as_str().to_string(),
+        };
+
+        let comparison = Self {
+            lhs_type: Type::from_parser_type(lhs.clone(), reg),
+            lhs: source(lhs),
+            operator,
+            rhs_type: Type::from_parser_type(rhs.clone(), reg),
+            rhs: source(rhs),
+        };
+
+        if !comparison.is_valid(reg) {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "cannot compare \"{}\" to \"{}\" with \"{}\"",
+                    comparison.lhs_type.ident, comparison.rhs_type.ident, comparison.operator
+                ),
+            )
+        }
+
+        comparison
+    }
+}
+
+impl ToSource for Comparison {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let operator = match self.operator.as_str() {
+            "and" => config.and,
+            "or" => config.or,
+            "!=" => config.not_equal,
+            operator => operator,
+        };
+
+        format!("({} {operator} {})", self.lhs, self.rhs)
+    }
+}
+
+/// A cast (`(value as Type)`), which changes the [`Type`] the value is checked as.
+///
+/// Values cast to a `String` are converted with the `to_string` template, and
+/// `String`s cast to a number with the `to_number` template. Every other cast
+/// keeps the value as it is.
+pub struct Cast {
+    pub value: String,
+    pub value_type: Type,
+    pub r#type: Type,
+}
+
+impl Cast {
+    /// If a value of the given [`Type`] is a table (`Table`, a struct, or an enum).
+    fn is_table(r#type: &Type) -> bool {
+        !matches!(
+            r#type.ident.as_str(),
+            TYPE_NAME_INT
+                | TYPE_NAME_FLOAT
+                | TYPE_NAME_NUMBER
+                | TYPE_NAME_BOOLEAN
+                | TYPE_NAME_STRING
+                | TYPE_NAME_FUNCTION
+                | TYPE_NAME_NIL
+                | TYPE_NAME_EMPTY
+                | TYPE_NAME_EMPTY_ALT
+                | TYPE_NAME_TUPLE
+                | TYPE_NAME_REF
+        )
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Cast {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let reg = value.1;
+        let mut inner = value.0.into_inner();
+
+        let pair = inner.next().unwrap();
+        let value_type = Type::from_parser_type(pair.clone(), reg);
+        let r#type: Type = inner.next().unwrap().into();
+
+        let source = match pair.as_rule() {
+            Rule::ordered_comparison => Comparison::from((pair, reg)).transform(&reg.config),
+            Rule::ordered_mathematical => Arithmetic::from((pair, reg)).transform(&reg.config),
+            Rule::ordered_cast => Cast::from((pair, reg)).transform(&reg.config),
+            Rule::identifier => identifier_source(pair.as_str(), reg),
+            Rule::string => interpolate(pair.as_str(), reg),
+            _ => pair.as_str().to_string(),
+        };
+
+        let from = reg.get_type(&value_type.ident);
+        let to = reg.get_type(&r#type.ident);
+
+        let unchanged = (from.ident == TYPE_NAME_ANY)
+            || (to.ident == TYPE_NAME_ANY)
+            || (from == to)
+            || (is_numeric(&from, reg) && is_numeric(&to, reg))
+            // tables can be anything
+            || (Cast::is_table(&from) && Cast::is_table(&to));
+
+        let value = if unchanged {
+            source
+        } else if to.ident == TYPE_NAME_STRING {
+            reg.config.to_string.replace("$value", &source)
+        } else if (from.ident == TYPE_NAME_STRING) && is_numeric(&to, reg) {
+            reg.config.to_number.replace("$value", &source)
+        } else {
+            fcompiler_general_error(
+                CompilerError::InvalidType,
+                format!(
+                    "cannot cast \"{}\" to \"{}\"",
+                    value_type.ident, r#type.ident
+                ),
+            )
+        };
+
+        Self {
+            value,
+            value_type,
+            r#type,
+        }
+    }
+}
+
+impl ToSource for Cast {
+    fn transform(&self, _config: &CompilerConfig) -> String {
+        self.value.to_owned()
+    }
+}
+
+/// A set of methods which a type has to implement (`trait Name { fn method() -> T }`).
+///
+/// Traits only exist for checking: an `impl Name for Type` must have every method
+/// of the trait (with the same signature), and nothing is emitted for the trait.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trait {
+    pub ident: String,
+    /// The signatures of the required methods (not associated with any type).
+    /// [`TYPE_NAME_SELF`] is the type implementing the trait.
+    pub methods: Vec<Function>,
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Trait {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let regs = Function::generic_registers(value.1, &[TYPE_NAME_SELF.to_string()]);
+        let mut ident: String = String::new();
+        let mut methods: Vec<Function> = Vec::new();
+
+        for pair in value.0.into_inner() {
+            match pair.as_rule() {
+                Rule::identifier => ident = pair.as_str().to_string(),
+                Rule::trait_method => methods.push(Function::signature(pair, &regs)),
+                _ => unreachable!("reached impossible rule in trait processing"),
+            }
+        }
+
+        Self { ident, methods }
+    }
+}
+
+impl Trait {
+    /// Check that the given methods (of an impl for `type`) include every method of
+    /// this trait, with the same signature.
+    pub fn check(&self, r#type: &Type, functions: &[Function]) {
+        // `Self` is the same as the type itself
+        let ident = |t: &Type| match t.ident.as_str() {
+            TYPE_NAME_SELF => r#type.ident.clone(),
+            _ => t.ident.clone(),
+        };
+
+        for required in &self.methods {
+            let mut expected = required.clone();
+            Impl::associate(r#type, &mut expected);
+
+            let Some(function) = functions.iter().find(|f| f.ident == expected.ident) else {
+                fcompiler_general_error(
+                    CompilerError::MissingMethod,
+                    format!("{} (required by {})", expected.ident, self.ident),
+                )
+            };
+
+            let same_arguments = (function.arguments.types.len() == expected.arguments.types.len())
+                && std::iter::zip(&function.arguments.types, &expected.arguments.types)
+                    .all(|(a, b)| ident(a) == ident(b));
+
+            if !same_arguments
+                || (ident(&function.return_type) != ident(&expected.return_type))
+                || (function.execution != expected.execution)
+            {
+                fcompiler_general_error(
+                    CompilerError::MissingMethod,
+                    format!(
+                        "{} doesn't have the signature required by {}",
+                        function.ident, self.ident
+                    ),
+                )
+            }
+        }
+    }
+}
+
 /// An implementation definition of a struct.
 #[derive(Debug, Clone)]
 pub struct Impl {
@@ -999,16 +3024,17 @@ impl From<(Pair<'_, Rule>, &Registers)> for Impl {
         let regs = value.1;
         let mut inner = value.0.into_inner();
 
-        let mut ident: String = String::new();
+        let mut r#type: Type = Type::default();
+        let mut r#trait: Option<(Trait, Pair<'_, Rule>)> = None;
         let mut functions: Vec<Function> = Vec::new();
 
         while let Some(pair) = inner.next() {
             let rule = pair.as_rule();
             match rule {
+                Rule::impl_trait => r#trait = Some((regs.get_trait(pair.as_str()), pair)),
                 Rule::identifier => {
                     // make sure type exists
-                    let r#type = regs.get_type(pair.as_str());
-                    ident = r#type.ident
+                    r#type = regs.get_type(pair.as_str());
                 }
                 Rule::impl_block => {
                     let mut inner = pair.into_inner();
@@ -1022,252 +3048,1009 @@ impl From<(Pair<'_, Rule>, &Registers)> for Impl {
                             span.end_pos().line_col(),
                         );
 
-                        match rule {
-                            Rule::method => {
-                                let mut function: Function = (pair, regs).into();
-                                // TODO: add config translations
+                        match rule {
+                            Rule::method => {
+                                let visibility = Impl::visibility(&pair);
+                                let mut function: Function =
+                                    (pair, &Impl::method_registers(&r#type, regs)).into();
+                                function.visibility = visibility;
+                                // TODO: add config translations
+
+                                if (function.ident == "new")
+                                    && (function.association == AssociationType::Static)
+                                    && r#type.variants.is_empty()
+                                {
+                                    // imitate class (starting with the field defaults)
+                                    let defaults = StructLiteral::defaults(&r#type);
+                                    let fields = if defaults.fields.is_empty() {
+                                        "{}".to_string()
+                                    } else {
+                                        defaults.transform(&regs.config)
+                                    };
+
+                                    function.body = format!(
+                                        "{ident}.__index = {ident}
+local self = {fields}
+setmetatable(self, {ident})
+{body}
+return self",
+                                        ident = r#type.ident,
+                                        body = function.body
+                                    )
+                                }
+
+                                Impl::associate(&r#type, &mut function);
+                                functions.push(function)
+                            }
+                            _ => unreachable!("reached impossible block in impl block processing"),
+                        }
+                    }
+                }
+                _ => unreachable!("reached impossible block in impl processing"),
+            }
+        }
+
+        if let Some((r#trait, pair)) = r#trait {
+            // point errors at the trait, not the last method
+            crate::set_marker(&pair, regs);
+            r#trait.check(&r#type, &functions);
+        }
+
+        Self {
+            ident: r#type.ident,
+            functions,
+        }
+    }
+}
+
+impl Impl {
+    /// Attach a method to the type it's implemented on.
+    ///
+    /// Static methods are called with a period (`T.f()`), other struct methods with
+    /// a colon (`t:f()`). Enum variants are plain values which can't have methods of
+    /// their own, so non-static enum methods take the variant as an explicit `self`
+    /// argument instead (`T.f(T.Variant)`).
+    pub fn associate(r#type: &Type, function: &mut Function) {
+        if function.return_type.ident == TYPE_NAME_SELF {
+            function.return_type = r#type.ident.clone().into();
+        }
+
+        if function.association == AssociationType::None {
+            // methods are `assoc` unless they're marked `static`
+            function.association = AssociationType::Assoc;
+        }
+
+        if function.association == AssociationType::Static {
+            // period
+            function.ident = format!("{}.{}", r#type.ident, function.ident);
+        } else if !r#type.variants.is_empty() {
+            // period, with the variant as the first argument
+            function.ident = format!("{}.{}", r#type.ident, function.ident);
+            function.arguments.keys.insert(0, "self".to_string());
+            function.arguments.types.insert(0, r#type.clone());
+        } else {
+            // colon
+            function.ident = format!("{}:{}", r#type.ident, function.ident);
+        }
+    }
+
+    /// Get the visibility of a method, which is public unless it's marked `prv`.
+    ///
+    /// Private methods can only be called from the type's own impl blocks, and
+    /// aren't available to files which `use` the type.
+    fn visibility(pair: &Pair<'_, Rule>) -> TypeVisibility {
+        match pair.clone().into_inner().next() {
+            Some(pair) if pair.as_rule() == Rule::type_modifier => pair.into(),
+            _ => TypeVisibility::Public,
+        }
+    }
+
+    /// Get the registers for the body of a method, where [`TYPE_NAME_SELF`] is the
+    /// type the method is implemented on (and `self` is an instance of it).
+    fn method_registers(r#type: &Type, regs: &Registers) -> Registers {
+        let mut regs = regs.clone();
+        regs.types
+            .insert(TYPE_NAME_SELF.to_string(), r#type.clone());
+
+        if r#type.variants.is_empty() {
+            regs.variables.insert(
+                "self".to_string(),
+                ("self".to_string(), Type::from(r#type.ident.clone())).into(),
+            );
+        }
+
+        regs
+    }
+
+    /// Get the signatures of all methods in an impl without processing their bodies.
+    ///
+    /// Methods which use a type that isn't registered yet are left out.
+    pub fn signatures(pair: Pair<'_, Rule>, regs: &Registers) -> Vec<Function> {
+        let mut r#type: Type = Type::default();
+        let mut functions: Vec<Function> = Vec::new();
+
+        for pair in pair.into_inner() {
+            match pair.as_rule() {
+                Rule::impl_trait => continue,
+                Rule::identifier => match regs.find_type(pair.as_str()) {
+                    Some(t) => r#type = t,
+                    None => return functions,
+                },
+                Rule::impl_block => {
+                    let method_regs = Impl::method_registers(&r#type, regs);
+
+                    for pair in pair.into_inner() {
+                        let visibility = Impl::visibility(&pair);
+                        let Some(mut function) = Function::try_signature(pair, &method_regs) else {
+                            continue;
+                        };
+
+                        function.visibility = visibility;
+                        Impl::associate(&r#type, &mut function);
+                        functions.push(function);
+                    }
+                }
+                _ => unreachable!("reached impossible block in impl processing"),
+            }
+        }
+
+        functions
+    }
+}
+
+impl ToSource for Impl {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let mut src_out = String::new();
+
+        for function in &self.functions {
+            // private methods are still fields of the type, lua has no private fields
+            let mut function = function.clone();
+            function.visibility = TypeVisibility::Public;
+            src_out.push_str(&function.transform(config));
+        }
+
+        src_out
+    }
+}
+
+/// Get the body of a loop, followed by its `continue` label (if it has one).
+fn loop_body(block: &str, continue_label: &Option<String>, config: &CompilerConfig) -> String {
+    match continue_label {
+        Some(label) => format!("{block}{}", config.continue_label.replace("$label", label)),
+        None => block.to_string(),
+    }
+}
+
+/// A standard for loop.
+///
+/// <https://www.lua.org/pil/4.3.5.html>
+///
+/// Numeric for loops (<https://www.lua.org/pil/4.3.4.html>) are [`NumericForLoop`]s.
+pub struct ForLoop {
+    pub label: Option<String>,
+    pub idents: Vec<String>,
+    /// The type of each identifier in `idents` (annotated or inferred).
+    pub types: Vec<Type>,
+    pub iterator: String,
+    pub block: String,
+    /// The label `continue` jumps to (if the loop has a `continue`).
+    pub continue_label: Option<String>,
+}
+
+impl ForLoop {
+    /// Get the element types produced by the given iterator (if they can be inferred).
+    ///
+    /// Only `ipairs`/`pairs` calls over a `Table<K, V>` variable are understood,
+    /// which produce `K, V`.
+    pub fn element_types(pair: Pair<'_, Rule>, registers: &Registers) -> Vec<Type> {
+        if pair.as_rule() != Rule::call {
+            return Vec::new();
+        }
+
+        let mut inner = pair.into_inner();
+        let ident = inner.next().unwrap().as_str();
+
+        if (ident != "ipairs") && (ident != "pairs") {
+            return Vec::new();
+        }
+
+        match inner.next() {
+            Some(arg) if arg.as_rule() == Rule::identifier => {
+                let table = registers.get_var(arg.as_str());
+
+                if table.r#type.ident != TYPE_NAME_TABLE {
+                    return Vec::new();
+                }
+
+                table
+                    .r#type
+                    .generics
+                    .iter()
+                    .map(|t| Type::from(t.as_str()))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for ForLoop {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let regs = value.1;
+        let inner = value.0.into_inner();
+
+        let mut label: Option<String> = None;
+        let mut idents: Vec<String> = Vec::new();
+        let mut annotations: Vec<Option<Type>> = Vec::new();
+        let mut types: Vec<Type> = Vec::new();
+        let mut element_types: Vec<Type> = Vec::new();
+        let mut iterator: String = String::new();
+        let mut block: String = String::new();
+        let mut continue_label: Option<String> = None;
+
+        for pair in inner {
+            let rule = pair.as_rule();
+
+            match rule {
+                Rule::loop_label => label = Some(pair.as_str()[1..].to_string()),
+                Rule::identifier => {
+                    idents.push(pair.as_str().to_string());
+                    annotations.push(None);
+                }
+                Rule::typed_parameter => {
+                    let mut inner = pair.into_inner();
+                    let r#type: Type = (inner.next().unwrap(), regs).into();
+                    idents.push(inner.next().unwrap().as_str().to_string());
+                    annotations.push(Some(r#type));
+                }
+                Rule::block => {
+                    // annotations can't conflict with the inferred element types;
+                    // bindings without an annotation stay `any`
+                    for (i, annotation) in annotations.iter().enumerate() {
+                        let inferred = element_types.get(i);
+
+                        types.push(match (annotation, inferred) {
+                            (Some(annotation), Some(inferred)) => {
+                                if annotation != inferred {
+                                    fcompiler_type_error(
+                                        inferred.ident.clone(),
+                                        annotation.ident.clone(),
+                                    );
+                                }
+
+                                annotation.to_owned()
+                            }
+                            (Some(annotation), None) => annotation.to_owned(),
+                            (None, _) => Type::from(TYPE_NAME_ANY),
+                        });
+                    }
+
+                    let processed = crate::process(pair.into_inner(), {
+                        let mut regs = regs.looped(label.clone());
+
+                        for (identifier, r#type) in std::iter::zip(&idents, &types) {
+                            regs.variables.insert(
+                                identifier.clone(),
+                                (identifier.clone(), r#type.to_owned()).into(),
+                            );
+                        }
+
+                        regs
+                    });
+
+                    block = processed.0;
+                    continue_label = processed
+                        .1
+                        .continue_label
+                        .filter(|label| block.contains(label.as_str()));
+                }
+                _ => {
+                    element_types = ForLoop::element_types(pair.clone(), regs);
+                    iterator = pair.as_str().to_string()
+                }
+            }
+        }
+
+        Self {
+            label,
+            idents,
+            types,
+            iterator,
+            block,
+            continue_label,
+        }
+    }
+}
+
+impl ToSource for ForLoop {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let out = config
+            .r#for
+            .replace("$idents", &{
+                let mut out = String::new();
+
+                for (i, ident) in self.idents.iter().enumerate() {
+                    if i == self.idents.len() - 1 {
+                        out.push_str(&ident);
+                    } else {
+                        out.push_str(&format!("{ident}, "));
+                    }
+                }
+
+                out
+            })
+            .replace("$iter", &self.iterator)
+            .replace(
+                "$body",
+                &loop_body(&self.block, &self.continue_label, config),
+            );
+
+        match self.label {
+            Some(ref label) => config
+                .labeled_loop
+                .replace("$loop", &out)
+                .replace("$label", label),
+            None => out,
+        }
+    }
+}
+
+/// A numeric for loop (`for (i) in 1..10 {}` or `for (i) in 1, 10, 2 {}`).
+///
+/// <https://www.lua.org/pil/4.3.4.html>
+pub struct NumericForLoop {
+    pub label: Option<String>,
+    pub ident: String,
+    pub start: String,
+    /// The last value of `ident` (inclusive).
+    pub stop: String,
+    pub step: String,
+    pub block: String,
+    /// The label `continue` jumps to (if the loop has a `continue`).
+    pub continue_label: Option<String>,
+}
+
+impl NumericForLoop {
+    /// Get the source of a bound (or step), which must be an `int`.
+    fn bound(pair: Pair<'_, Rule>, regs: &Registers) -> String {
+        let r#type = match pair.as_rule() {
+            Rule::range_bound => regs.key_type(pair.as_str()),
+            _ => Type::from_parser_type(pair.clone(), regs),
+        };
+
+        if regs.get_type(&r#type.ident).ident != TYPE_NAME_INT {
+            fcompiler_type_error(TYPE_NAME_INT.to_string(), r#type.ident);
+        }
+
+        match pair.as_rule() {
+            Rule::call => FunctionCall::from((pair, regs)).transform(&regs.config),
+            Rule::ordered_mathematical => Arithmetic::from((pair, regs)).transform(&regs.config),
+            Rule::ordered_cast => Cast::from((pair, regs)).transform(&regs.config),
+            Rule::identifier => identifier_source(pair.as_str(), regs),
+            _ => pair.as_str().to_string(),
+        }
+    }
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for NumericForLoop {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let regs = value.1;
+        let inner = value.0.into_inner();
+
+        let mut label: Option<String> = None;
+        let mut ident: String = String::new();
+        let mut bounds: Vec<String> = Vec::new();
+        let mut block: String = String::new();
+        let mut continue_label: Option<String> = None;
+
+        for pair in inner {
+            match pair.as_rule() {
+                Rule::loop_label => label = Some(pair.as_str()[1..].to_string()),
+                Rule::identifier => ident = pair.as_str().to_string(),
+                Rule::typed_parameter => {
+                    let mut inner = pair.into_inner();
+                    let r#type: Type = (inner.next().unwrap(), regs).into();
 
-                                if function.association == AssociationType::Static {
-                                    // period
-                                    function.ident = format!("{}.{}", ident, function.ident);
-                                } else {
-                                    // colon
-                                    function.ident = format!("{}:{}", ident, function.ident);
-                                }
+                    if regs.get_type(&r#type.ident).ident != TYPE_NAME_INT {
+                        fcompiler_type_error(TYPE_NAME_INT.to_string(), r#type.ident);
+                    }
 
-                                function.body = function.body.replace("__data_struct", &ident);
-                                function.visibility = TypeVisibility::Public;
-                                functions.push(function)
-                            }
-                            _ => unreachable!("reached impossible block in impl block processing"),
-                        }
+                    ident = inner.next().unwrap().as_str().to_string();
+                }
+                Rule::numeric_range | Rule::numeric_steps => {
+                    for pair in pair.into_inner() {
+                        bounds.push(NumericForLoop::bound(pair, regs));
                     }
                 }
-                _ => unreachable!("reached impossible block in impl processing"),
+                Rule::block => {
+                    let processed = crate::process(pair.into_inner(), {
+                        let mut regs = regs.looped(label.clone());
+                        regs.variables.insert(
+                            ident.clone(),
+                            (ident.clone(), Type::from(TYPE_NAME_INT)).into(),
+                        );
+
+                        regs
+                    });
+
+                    block = processed.0;
+                    continue_label = processed
+                        .1
+                        .continue_label
+                        .filter(|label| block.contains(label.as_str()));
+                }
+                _ => unreachable!("reached impossible rule in numeric for loop processing"),
             }
         }
 
-        Self { ident, functions }
+        let mut bounds = bounds.into_iter();
+
+        Self {
+            label,
+            ident,
+            start: bounds.next().unwrap(),
+            stop: bounds.next().unwrap(),
+            step: bounds.next().unwrap_or("1".to_string()),
+            block,
+            continue_label,
+        }
     }
 }
 
-impl ToSource for Impl {
-    fn transform(&self) -> String {
-        let mut src_out = String::new();
+impl ToSource for NumericForLoop {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let out = config
+            .numeric_for
+            .replace("$ident", &self.ident)
+            .replace("$start", &self.start)
+            .replace("$stop", &self.stop)
+            .replace("$step", &self.step)
+            .replace(
+                "$body",
+                &loop_body(&self.block, &self.continue_label, config),
+            );
 
-        for function in &self.functions {
-            src_out.push_str(&function.transform());
+        match self.label {
+            Some(ref label) => config
+                .labeled_loop
+                .replace("$loop", &out)
+                .replace("$label", label),
+            None => out,
         }
-
-        src_out
     }
 }
 
-/// A standard for loop.
-///
-/// <https://www.lua.org/pil/4.3.5.html>
+/// A standard while loop.
 ///
-/// We do not support <https://www.lua.org/pil/4.3.4.html> (numeric for) at this time.
-pub struct ForLoop {
-    pub idents: Vec<String>,
-    pub iterator: String,
+/// <https://www.lua.org/pil/4.3.2.html>
+pub struct WhileLoop {
+    pub label: Option<String>,
+    pub condition: String,
     pub block: String,
+    /// The label `continue` jumps to (if the loop has a `continue`).
+    pub continue_label: Option<String>,
 }
 
-impl From<(Pair<'_, Rule>, &Registers)> for ForLoop {
+impl From<(Pair<'_, Rule>, &Registers)> for WhileLoop {
     fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
         let regs = value.1;
         let mut inner = value.0.into_inner();
 
-        let mut idents: Vec<String> = Vec::new();
-        let mut iterator: String = String::new();
+        let mut label: Option<String> = None;
+        let mut condition: String = String::new();
         let mut block: String = String::new();
+        let mut continue_label: Option<String> = None;
 
         while let Some(pair) = inner.next() {
             let rule = pair.as_rule();
 
             match rule {
-                Rule::identifier => idents.push(pair.as_str().to_string()),
+                Rule::loop_label => label = Some(pair.as_str()[1..].to_string()),
                 Rule::block => {
-                    block = crate::process(pair.into_inner(), {
-                        let mut regs = regs.clone();
-
-                        for identifier in &idents {
-                            regs.variables.insert(
-                                identifier.clone(),
-                                (identifier.clone(), Type::from(TYPE_NAME_ANY)).into(),
-                            );
-                        }
-
-                        regs
-                    })
-                    .0
+                    let processed = crate::process(pair.into_inner(), regs.looped(label.clone()));
+                    block = processed.0;
+                    continue_label = processed
+                        .1
+                        .continue_label
+                        .filter(|label| block.contains(label.as_str()));
                 }
-                _ => iterator = pair.as_str().to_string(),
+                Rule::ordered_comparison => {
+                    condition = Comparison::from((pair, regs)).transform(&regs.config)
+                }
+                _ => condition = pair.as_str().to_string(),
             }
         }
 
         Self {
-            idents,
-            iterator,
+            label,
+            condition,
             block,
+            continue_label,
         }
     }
 }
 
-impl ToSource for ForLoop {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
-
-        config
-            .r#for
-            .replace("$idents", &{
-                let mut out = String::new();
-
-                for (i, ident) in self.idents.iter().enumerate() {
-                    if i == self.idents.len() - 1 {
-                        out.push_str(&ident);
-                    } else {
-                        out.push_str(&format!("{ident}, "));
-                    }
-                }
+impl ToSource for WhileLoop {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let out = config
+            .r#while
+            .replace("$condition", &self.condition)
+            .replace(
+                "$body",
+                &loop_body(&self.block, &self.continue_label, config),
+            );
 
-                out
-            })
-            .replace("$iter", &self.iterator)
-            .replace("$body", &self.block)
+        match self.label {
+            Some(ref label) => config
+                .labeled_loop
+                .replace("$loop", &out)
+                .replace("$label", label),
+            None => out,
+        }
     }
 }
 
-/// A standard while loop.
+/// A loop which checks its condition after the body (so the body always runs at
+/// least once), and stops once the condition is true.
 ///
-/// <https://www.lua.org/pil/4.3.2.html>
-pub struct WhileLoop {
+/// <https://www.lua.org/pil/4.3.3.html>
+pub struct RepeatLoop {
+    pub label: Option<String>,
     pub condition: String,
     pub block: String,
+    /// The label `continue` jumps to (if the loop has a `continue`).
+    pub continue_label: Option<String>,
 }
 
-impl From<(Pair<'_, Rule>, &Registers)> for WhileLoop {
+impl From<(Pair<'_, Rule>, &Registers)> for RepeatLoop {
     fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
         let regs = value.1;
-        let mut inner = value.0.into_inner();
+        let inner = value.0.into_inner();
 
+        let mut label: Option<String> = None;
         let mut condition: String = String::new();
         let mut block: String = String::new();
+        let mut continue_label: Option<String> = None;
+        // the condition can use the variables declared in the body (unless it has a `continue`)
+        let mut body_regs: Registers = regs.to_owned();
 
-        while let Some(pair) = inner.next() {
+        for pair in inner {
             let rule = pair.as_rule();
 
             match rule {
-                Rule::block => block = crate::process(pair.into_inner(), regs.clone()).0,
+                Rule::loop_label => label = Some(pair.as_str()[1..].to_string()),
+                Rule::block => {
+                    let processed = crate::process(pair.into_inner(), regs.looped(label.clone()));
+                    block = processed.0;
+                    body_regs = processed.1;
+                    continue_label = body_regs
+                        .continue_label
+                        .clone()
+                        .filter(|label| block.contains(label.as_str()));
+                }
+                Rule::ordered_comparison => {
+                    // the body's variables are out of scope if the body has its own block
+                    let condition_regs = match continue_label {
+                        Some(_) => regs,
+                        None => &body_regs,
+                    };
+
+                    condition = Comparison::from((pair, condition_regs)).transform(&regs.config)
+                }
                 _ => condition = pair.as_str().to_string(),
             }
         }
 
-        Self { condition, block }
+        Self {
+            label,
+            condition,
+            block,
+            continue_label,
+        }
     }
 }
 
-impl ToSource for WhileLoop {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
+impl ToSource for RepeatLoop {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        // `continue` can't jump past the body's locals while they're still in scope
+        // (they are until after the condition), so they get their own block
+        let block = match self.continue_label {
+            Some(_) => config.block.replace("$body", &self.block),
+            None => self.block.clone(),
+        };
 
-        config
-            .r#while
+        let out = config
+            .repeat
             .replace("$condition", &self.condition)
-            .replace("$body", &self.block)
+            .replace("$body", &loop_body(&block, &self.continue_label, config));
+
+        match self.label {
+            Some(ref label) => config
+                .labeled_loop
+                .replace("$loop", &out)
+                .replace("$label", label),
+            None => out,
+        }
+    }
+}
+
+/// A `break` out of the innermost loop, or out of the enclosing loop with the given label.
+///
+/// Lua has no labeled `break`, so labeled breaks are a `goto` to a label placed
+/// right after the loop.
+pub struct Break {
+    pub label: Option<String>,
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Break {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let label = value
+            .0
+            .into_inner()
+            .next()
+            .map(|pair| pair.as_str()[1..].to_string());
+
+        if value.1.loops.is_empty() {
+            fcompiler_error!("cannot break outside of a loop")
+        }
+
+        if let Some(ref label) = label {
+            if !value.1.loops.contains(&Some(label.to_owned())) {
+                fcompiler_general_error(CompilerError::NoSuchLabel, label.to_owned())
+            }
+        }
+
+        Self { label }
+    }
+}
+
+impl ToSource for Break {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        match self.label {
+            Some(ref label) => config.break_label.replace("$label", label),
+            None => config.r#break.to_string(),
+        }
+    }
+}
+
+/// A `continue` to the next iteration of the innermost loop.
+///
+/// Lua has no `continue`, so it's a `goto` to a label placed at the end of the
+/// loop's body ([`Registers::continue_label`]).
+pub struct Continue {
+    pub label: String,
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Continue {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        match value.1.continue_label {
+            Some(ref label) => Self {
+                label: label.to_owned(),
+            },
+            None => fcompiler_error!("cannot continue outside of a loop"),
+        }
+    }
+}
+
+impl ToSource for Continue {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        config.r#continue.replace("$label", &self.label)
     }
 }
 
+/// Keep the variables of `assigned` which were also assigned in the next branch (`block`).
+fn assigned_in_all(assigned: &mut Option<BTreeSet<String>>, block: BTreeSet<String>) {
+    *assigned = Some(match assigned.take() {
+        Some(assigned) => assigned.intersection(&block).cloned().collect(),
+        None => block,
+    });
+}
+
 /// A standard conditional (if, else, else if).
 ///
 /// <https://www.lua.org/pil/4.3.1.html>
+///
+/// Branches with conditions which are known at compile time are folded: branches
+/// which can never run are removed, and a branch which always runs ends the chain.
 pub struct Conditional {
-    pub keyword: String,
-    pub condition: String,
-    pub block: String,
+    /// Every branch which may run, in order. The condition is `None` for `else`.
+    pub branches: Vec<(Option<String>, String)>,
+    /// Uninitialized variables of the enclosing scope assigned in every branch (if one of
+    /// the branches always runs).
+    pub assigned: BTreeSet<String>,
 }
 
 impl From<(Pair<'_, Rule>, &Registers)> for Conditional {
     fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
         let regs = value.1;
 
-        let keyword = match value.0.as_rule() {
-            Rule::conditional_else => "else",
-            Rule::conditional_elseif => "elseif",
-            _ => "if",
+        // flatten `if`, `else if`, and `else` into a list of branches
+        let mut pairs = vec![value.0.clone()];
+        pairs.extend(value.0.into_inner().filter(|pair| {
+            matches!(
+                pair.as_rule(),
+                Rule::conditional_elseif | Rule::conditional_else
+            )
+        }));
+
+        let mut branches: Vec<(Option<String>, String)> = Vec::new();
+        let mut assigned: Option<BTreeSet<String>> = None;
+        let mut exhaustive: bool = false;
+
+        for pair in pairs {
+            let mut condition: Option<(Pair<'_, Rule>, String)> = None;
+            let mut block: String = String::new();
+            let mut block_assigned: BTreeSet<String> = BTreeSet::new();
+
+            for pair in pair.into_inner() {
+                match pair.as_rule() {
+                    Rule::block => {
+                        let processed = crate::process(pair.into_inner(), regs.nested());
+                        block = processed.0;
+                        block_assigned = regs.assigned_in(&processed.1);
+                    }
+                    Rule::conditional_elseif | Rule::conditional_else => continue,
+                    _ => {
+                        // checked before the block, so errors point at the condition
+                        crate::set_marker(&pair, regs);
+
+                        let source = match pair.as_rule() {
+                            Rule::ordered_comparison => {
+                                Comparison::from((pair.clone(), regs)).transform(&regs.config)
+                            }
+                            _ => pair.as_str().to_string(),
+                        };
+
+                        condition = Some((pair, source));
+                    }
+                }
+            }
+
+            let Some((condition, source)) = condition else {
+                // else
+                branches.push((None, block));
+                assigned_in_all(&mut assigned, block_assigned);
+                exhaustive = true;
+                break;
+            };
+
+            match crate::fold::fold(condition, regs) {
+                // never runs
+                Some(Constant::Bool(false)) => continue,
+                // always runs, nothing after this can
+                Some(Constant::Bool(true)) => {
+                    branches.push((None, block));
+                    assigned_in_all(&mut assigned, block_assigned);
+                    exhaustive = true;
+                    break;
+                }
+                _ => {
+                    branches.push((Some(source), block));
+                    assigned_in_all(&mut assigned, block_assigned);
+                }
+            }
+        }
+
+        // without an `else`, none of the branches may run
+        let assigned = match assigned {
+            Some(assigned) if exhaustive => assigned,
+            _ => BTreeSet::new(),
+        };
+
+        Self { branches, assigned }
+    }
+}
+
+impl ToSource for Conditional {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        let mut out = String::new();
+
+        for (i, (condition, body)) in self.branches.iter().enumerate() {
+            let (keyword, opening) = match (i, condition) {
+                // the first branch always runs, so there's no conditional at all
+                (0, None) => return config.block.replace("$body", body),
+                (0, Some(_)) => (
+                    config.conditional_keyword_if,
+                    config.conditional_opening_no_else,
+                ),
+                (_, Some(_)) => (
+                    config.conditional_keyword_else_if,
+                    config.conditional_opening_no_else,
+                ),
+                (_, None) => (
+                    config.conditional_keyword_else,
+                    config.conditional_opening_else,
+                ),
+            };
+
+            out.push_str(
+                &config
+                    .conditional
+                    .replace("$keyword", keyword)
+                    .replace("$condition", condition.as_deref().unwrap_or_default())
+                    .replace("$opening", opening)
+                    .replace("$body", body)
+                    .replace(
+                        "$closing",
+                        if i == self.branches.len() - 1 {
+                            config.conditional_closing
+                        } else {
+                            ""
+                        },
+                    ),
+            );
         }
-        .to_string();
 
+        out
+    }
+}
+
+/// A `match` over a value, lowered to a [`Conditional`] which compares the value
+/// to each pattern in order. The `_` pattern is the `else` branch.
+///
+/// The value is only evaluated once, it's stored in a local which the patterns are
+/// compared to.
+///
+/// Patterns of a `match` over an enum must name one of its variants (`Enum.Variant`
+/// or just `Variant`).
+pub struct Match {
+    /// The source of the value being matched.
+    pub scrutinee: String,
+    /// The name of the local the value is stored in.
+    pub binding: String,
+    /// The pattern and block of each arm. The pattern is `_` for the wildcard arm.
+    pub arms: Vec<(String, String)>,
+    /// Uninitialized variables of the enclosing scope assigned in every arm (if the match
+    /// has a `_` arm).
+    pub assigned: BTreeSet<String>,
+}
+
+impl From<(Pair<'_, Rule>, &Registers)> for Match {
+    fn from(value: (Pair<'_, Rule>, &Registers)) -> Self {
+        let regs = value.1;
         let mut inner = value.0.into_inner();
 
-        let mut condition: String = String::new();
-        let mut block: String = String::new();
+        let scrutinee = inner.next().unwrap();
+        let r#enum = match scrutinee.as_rule() {
+            Rule::identifier | Rule::call => {
+                let r#type = Type::from_parser_type(scrutinee.clone(), regs);
+                Some(regs.get_type(&r#type.ident)).filter(|t| !t.variants.is_empty())
+            }
+            _ => None,
+        };
 
-        while let Some(pair) = inner.next() {
-            let rule = pair.as_rule();
+        let mut arms: Vec<(String, String)> = Vec::new();
+        let mut assigned: Option<BTreeSet<String>> = None;
 
-            match rule {
-                Rule::block => block = crate::process(pair.into_inner(), regs.clone()).0,
-                Rule::conditional_else => {
-                    if block.ends_with("end\n") {
-                        // reopen block
-                        block = block[..block.len() - 4].to_string();
-                    }
+        for arm in inner {
+            if arms.last().is_some_and(|(pattern, _)| pattern == "_") {
+                fcompiler_error!("the `_` arm must be the last arm of a match")
+            }
 
-                    block.push_str(&Conditional::from((pair, regs)).transform())
-                }
-                Rule::conditional_elseif => {
-                    if block.ends_with("end\n") {
-                        block = block[..block.len() - 4].to_string();
+            let mut arm = arm.into_inner();
+            let pattern = arm.next().unwrap();
+
+            let pattern = match (&r#enum, pattern.as_rule()) {
+                (_, Rule::match_wildcard) => "_".to_string(),
+                (Some(r#enum), _) => {
+                    let variant = pattern
+                        .as_str()
+                        .strip_prefix(&format!("{}.", r#enum.ident))
+                        .unwrap_or(pattern.as_str());
+
+                    if !r#enum.variants.contains_key(variant) {
+                        fcompiler_general_error(
+                            CompilerError::NoSuchVariant,
+                            format!("{}.{variant}", r#enum.ident),
+                        )
                     }
 
-                    block.push_str(&Conditional::from((pair, regs)).transform())
+                    format!("{}.{variant}", r#enum.ident)
                 }
-                _ => condition = pair.as_str().to_string(),
-            }
+                (None, _) => pattern.as_str().to_string(),
+            };
+
+            let processed = crate::process(arm.next().unwrap().into_inner(), regs.nested());
+            assigned_in_all(&mut assigned, regs.assigned_in(&processed.1));
+            arms.push((pattern, processed.0));
         }
 
+        // without a `_` arm, none of the arms may run
+        let assigned = match assigned {
+            Some(assigned) if arms.last().is_some_and(|(pattern, _)| pattern == "_") => assigned,
+            _ => BTreeSet::new(),
+        };
+
+        let source = match scrutinee.as_rule() {
+            Rule::identifier => identifier_source(scrutinee.as_str(), regs),
+            Rule::call => {
+                let call = FunctionCall::from((scrutinee, regs));
+                call.check_multiple(call.arg_types(regs), regs);
+                call.transform(&regs.config)
+            }
+            Rule::string => interpolate(scrutinee.as_str(), regs),
+            Rule::ordered_comparison => Comparison::from((scrutinee, regs)).transform(&regs.config),
+            Rule::ordered_mathematical => {
+                Arithmetic::from((scrutinee, regs)).transform(&regs.config)
+            }
+            Rule::ordered_cast => Cast::from((scrutinee, regs)).transform(&regs.config),
+            _ => scrutinee.as_str().to_string(),
+        };
+
         Self {
-            keyword,
-            condition,
-            block,
+            scrutinee: source,
+            binding: format!("match_{}", crate::tempfile::unique()),
+            arms,
+            assigned,
         }
     }
 }
 
-impl ToSource for Conditional {
-    fn transform(&self) -> String {
-        let config = COMPILER_TEMPLATES.read().unwrap();
+impl ToSource for Match {
+    fn transform(&self, config: &CompilerConfig) -> String {
+        if self.arms.is_empty() {
+            return String::new();
+        }
 
+        let binding = config
+            .variable
+            .replace("$visibility", TypeVisibility::Private.source(config))
+            .replace("$ident", &self.binding)
+            .replace("$value", &self.scrutinee);
+
+        let conditional = Conditional {
+            branches: self
+                .arms
+                .iter()
+                .map(|(pattern, block)| {
+                    let condition = match pattern.as_str() {
+                        "_" => None,
+                        _ => Some(format!("({} == {pattern})", self.binding)),
+                    };
+
+                    (condition, block.to_owned())
+                })
+                .collect(),
+            assigned: self.assigned.clone(),
+        }
+        .transform(config);
+
+        // the local is only in scope for the match
         config
-            .conditional
-            .replace("$keyword", &self.keyword)
-            .replace("$condition", &self.condition)
-            .replace(
-                "$opening",
-                if self.keyword == "else" {
-                    config.conditional_opening_else
-                } else {
-                    config.conditional_opening_no_else
-                },
-            )
-            .replace("$body", &self.block)
-            .replace(
-                "$closing",
-                if !self.block.ends_with(config.conditional_closing) {
-                    config.conditional_closing
-                } else {
-                    ""
-                },
-            )
+            .block
+            .replace("$body", &format!("{binding}{conditional}"))
     }
 }
 
-/// Map containing a tuple with a function value and the path to the temp file it is mapped to.
-pub static COMPILER_EXPRESSIONS: LazyLock<Mutex<BTreeMap<String, (Function, pathbufd::PathBufD)>>> =
+/// A function value, the (bare) module it was declared in, and the temp file it
+/// is mapped to.
+pub type Expression = (Function, String, crate::tempfile::TempFile);
+
+/// Map containing the [`Expression`] of each `expr_use`d file.
+pub static COMPILER_EXPRESSIONS: LazyLock<Mutex<BTreeMap<String, Expression>>> =
     LazyLock::new(|| Mutex::new(BTreeMap::default()));
 
+/// Forget every [`Expression`] in [`COMPILER_EXPRESSIONS`], removing their temp files.
+pub fn clear_expressions() {
+    match COMPILER_EXPRESSIONS.lock() {
+        Ok(mut w) => w.clear(),
+        Err(e) => e.into_inner().clear(),
+    }
+}
+
+/// Run [`clear_expressions`] unless a file is still being compiled (its [`ImportGuard`]
+/// clears them once it's done).
+pub fn clear_finished_expressions() {
+    let compiling = match COMPILER_IMPORT_STACK.lock() {
+        Ok(w) => !w.is_empty(),
+        Err(e) => !e.into_inner().is_empty(),
+    };
+
+    if !compiling {
+        clear_expressions();
+    }
+}
+
 /// An invocation of the `expr_use` macro "function".
 pub struct ExprUse(pub String);
 
@@ -1291,7 +4074,7 @@ impl<'a> From<(FunctionCall<'a>, &Registers)> for ExprUse {
         };
 
         // use file
-        let mut registers = Registers::default();
+        let mut registers = regs.scoped();
 
         let stem = path
             .as_path()
@@ -1302,11 +4085,13 @@ impl<'a> From<(FunctionCall<'a>, &Registers)> for ExprUse {
             .to_string()
             .replace(".", "_");
 
-        use_file(
+        // the module is run as a script, so it shouldn't return its exports
+        let module = use_file(
             path,
             relative_file_path,
             "expr".to_string(),
             true,
+            true,
             &mut registers,
         );
 
@@ -1317,7 +4102,10 @@ impl<'a> From<(FunctionCall<'a>, &Registers)> for ExprUse {
         };
 
         let fun = registers.get_fn(&format!("expr.{stem}"));
-        lock.insert(stem.clone(), (fun, crate::tempfile::create()));
+        lock.insert(
+            stem.clone(),
+            (fun, module, crate::tempfile::TempFile::create()),
+        );
 
         // return
         Self(stem)
@@ -1340,53 +4128,129 @@ impl<'a> From<FunctionCall<'a>> for ExprCall {
             Err(_) => fcompiler_error!("poisoned mutex on COMPILER_EXPRESSIONS"),
         };
 
-        let (fun, temp_path) = match reader.get(&expr_name) {
+        let (_, module, temp_path) = match reader.get(&expr_name) {
             Some(f) => f,
             None => fcompiler_general_error(CompilerError::NoSuchFunction, expr_name),
         };
 
-        // build arguments
-        let mut arguments_string: String = String::new();
+        // build arguments (the name was already taken from the iterator)
+        let arguments_string = arguments
+            .map(|arg| arg.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
 
-        let mut arg_count: usize = 0;
-        while let Some(arg) = arguments.next() {
-            arg_count += 1;
-            if arg_count == value.arguments.len() - 1 {
-                arguments_string.push_str(&format!("{}", arg.as_str()));
-            } else {
-                arguments_string.push_str(&format!("{}, ", arg.as_str()));
-            }
+        // build return (the whole module is included so the expression can use
+        // everything else declared in its file)
+        let src_out: String = format!("{module}\n\nprint({expr_name}({arguments_string}))");
+
+        // return
+        match luajit(temp_path, src_out) {
+            Ok(stdout) => Self(stdout),
+            Err(stderr) => fcompiler_error!("expression \"{expr_name}\" failed: {stderr}"),
+        }
+    }
+}
+
+impl ToSource for ExprCall {
+    fn transform(&self, _config: &CompilerConfig) -> String {
+        self.0.to_owned()
+    }
+}
+
+/// Write `src` to the given (temporary) file and run it with `luajit`.
+///
+/// # Returns
+/// The stdout of `luajit`, or its stderr if the script failed.
+fn luajit(path: &pathbufd::PathBufD, src: String) -> Result<String, String> {
+    if let Err(e) = write(path, src) {
+        fcompiler_error!("{e}");
+    }
+
+    let mut pre_cmd = Command::new("luajit");
+
+    let cmd = pre_cmd
+        .arg(path.to_string())
+        .current_dir(std::env::temp_dir())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output();
+
+    let cmd = match cmd {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            fcompiler_error!("luajit not found: install LuaJIT to use expr_call and const fn")
         }
+        Err(e) => fcompiler_error!("failed to run luajit: {e}"),
+    };
 
-        // build return
-        let src_out: String = format!(
-            "{}\n\nprint({expr_name}({arguments_string}))",
-            fun.transform()
-        );
+    if !cmd.status.success() {
+        return Err(String::from_utf8_lossy(&cmd.stderr).trim().to_string());
+    }
 
-        // run
-        if let Err(e) = write(temp_path, src_out) {
-            panic!("{e}");
+    Ok(String::from_utf8_lossy(&cmd.stdout).to_string())
+}
+
+/// A call to a `const fn` evaluated at compile time (the same way as [`ExprCall`]).
+///
+/// The literal result of the call is used in place of the call.
+pub struct ConstCall(pub String);
+
+impl ConstCall {
+    /// Evaluate the given call to a `const fn`.
+    ///
+    /// Returns `None` if any of the arguments aren't constant.
+    pub fn evaluate(
+        call: &FunctionCall,
+        function: &Function,
+        registers: &Registers,
+    ) -> Option<Self> {
+        let mut arguments: Vec<String> = Vec::new();
+
+        for arg in &call.arguments {
+            arguments.push(crate::fold::fold(arg.clone(), registers)?.to_source());
+        }
+
+        // every const fn is available to the function being called
+        let mut src_out: String = String::new();
+
+        for other in registers.functions.values() {
+            if other.constant && (other.ident != function.ident) {
+                src_out.push_str(&other.transform(&registers.config));
+            }
         }
 
-        let mut pre_cmd = Command::new("luajit");
+        src_out.push_str(&function.transform(&registers.config));
 
-        let cmd = pre_cmd
-            .arg(&temp_path.to_string())
-            .current_dir(std::env::temp_dir())
-            .stdout(Stdio::piped())
-            .output()
-            .unwrap();
+        // print the result as a lua literal
+        let format = match function.return_type.ident.as_str() {
+            TYPE_NAME_STRING => "%q",
+            TYPE_NAME_INT => "%d",
+            TYPE_NAME_BOOLEAN => "%s",
+            _ => "%.17g",
+        };
 
-        let stdout = String::from_utf8_lossy(&cmd.stdout).to_string();
+        src_out.push_str(&format!(
+            "\n\nio.write(string.format(\"{format}\", tostring({}({}))))",
+            function.ident,
+            arguments.join(", ")
+        ));
 
-        // return
-        Self(stdout)
+        // run
+        match luajit(&crate::tempfile::TempFile::create(), src_out) {
+            Ok(stdout) => Some(Self(stdout)),
+            Err(stderr) => fcompiler_general_error(
+                CompilerError::NotConstant,
+                format!(
+                    "failed to evaluate const function \"{}\": {stderr}",
+                    call.ident
+                ),
+            ),
+        }
     }
 }
 
-impl ToSource for ExprCall {
-    fn transform(&self) -> String {
+impl ToSource for ConstCall {
+    fn transform(&self, _config: &CompilerConfig) -> String {
         self.0.to_owned()
     }
 }
@@ -0,0 +1,148 @@
+//! Compile-time evaluation of constant expressions.
+use crate::checking::Registers;
+use crate::data::MutabilityModifier;
+use parser::{Pair, Rule};
+
+/// A value known at compile time.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Constant {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Constant {
+    /// Parse a literal (as it appears in source).
+    pub fn parse(literal: &str) -> Option<Self> {
+        match literal {
+            "true" => Some(Self::Bool(true)),
+            "false" => Some(Self::Bool(false)),
+            _ if literal.starts_with("\"") && literal.ends_with("\"") && (literal.len() > 1) => {
+                Some(Self::String(literal[1..literal.len() - 1].to_string()))
+            }
+            _ => {
+                if let Ok(i) = literal.parse::<i64>() {
+                    Some(Self::Int(i))
+                } else if let Ok(f) = literal.parse::<f64>() {
+                    Some(Self::Float(f))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Get the value as a literal (as it would appear in source).
+    pub fn to_source(&self) -> String {
+        match self {
+            Self::Bool(b) => b.to_string(),
+            Self::Int(i) => i.to_string(),
+            Self::Float(f) => format!("{f:?}"),
+            Self::String(s) => format!("\"{s}\""),
+        }
+    }
+
+    /// Get the value as an `f64` (for comparing mixed numbers).
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(i) => Some(*i as f64),
+            Self::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Compare two constants with the given operator.
+    fn compare(&self, operator: &str, other: &Self) -> Option<Self> {
+        use std::cmp::Ordering;
+
+        let ordering = match (self.as_f64(), other.as_f64()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b)?,
+            _ if std::mem::discriminant(self) != std::mem::discriminant(other) => {
+                // values of different types are never equal, and can't be ordered
+                return match operator {
+                    "==" => Some(Self::Bool(false)),
+                    "!=" => Some(Self::Bool(true)),
+                    _ => None,
+                };
+            }
+            _ => self.partial_cmp(other)?,
+        };
+
+        Some(Self::Bool(match operator {
+            "==" => ordering == Ordering::Equal,
+            "!=" => ordering != Ordering::Equal,
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            ">=" => ordering != Ordering::Less,
+            _ => return None,
+        }))
+    }
+
+    /// Apply an arithmetic operator to two constants.
+    fn arithmetic(&self, operator: &str, other: &Self) -> Option<Self> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => match operator {
+                "+" => a.checked_add(*b).map(Self::Int),
+                "-" => a.checked_sub(*b).map(Self::Int),
+                "*" => a.checked_mul(*b).map(Self::Int),
+                // `/` is always float division
+                "/" if *b != 0 => Some(Self::Float(*a as f64 / *b as f64)),
+                _ => None,
+            },
+            _ => {
+                let (a, b) = (self.as_f64()?, other.as_f64()?);
+
+                Some(Self::Float(match operator {
+                    "+" => a + b,
+                    "-" => a - b,
+                    "*" => a * b,
+                    "/" => a / b,
+                    _ => return None,
+                }))
+            }
+        }
+    }
+}
+
+/// Evaluate the given expression at compile time.
+///
+/// Returns `None` if the expression isn't constant (it depends on a mutable
+/// variable, a function call, etc.).
+pub fn fold(pair: Pair<'_, Rule>, registers: &Registers) -> Option<Constant> {
+    match pair.as_rule() {
+        Rule::boolean | Rule::integer | Rule::float | Rule::string => {
+            Constant::parse(pair.as_str())
+        }
+        Rule::identifier => Constant::parse(pair.as_str()).or_else(|| {
+            // constant variables with literal values
+            let variable = registers.variables.get(pair.as_str())?;
+
+            if variable.mutable != MutabilityModifier::Constant {
+                return None;
+            }
+
+            Constant::parse(&variable.value)
+        }),
+        Rule::ordered_comparison | Rule::ordered_mathematical => {
+            let expression = pair.into_inner().next().unwrap();
+            let mut inner = expression.clone().into_inner();
+
+            let lhs = inner.next().unwrap();
+            let rhs = inner.next().unwrap();
+            let operator = crate::ast::operator(&expression, &lhs, &rhs);
+
+            let lhs = fold(lhs, registers)?;
+            let rhs = fold(rhs, registers)?;
+
+            match (operator.as_str(), &lhs, &rhs) {
+                ("and", Constant::Bool(a), Constant::Bool(b)) => Some(Constant::Bool(*a && *b)),
+                ("or", Constant::Bool(a), Constant::Bool(b)) => Some(Constant::Bool(*a || *b)),
+                ("+" | "-" | "*" | "/", _, _) => lhs.arithmetic(&operator, &rhs),
+                _ => lhs.compare(&operator, &rhs),
+            }
+        }
+        _ => None,
+    }
+}
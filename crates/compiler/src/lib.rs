@@ -1,119 +1,216 @@
-use bindings::{TYPE_NAME_ANY, TYPE_NAME_TABLE};
-use parser::{FaradayParser, Pairs, Parser, Rule};
+use bindings::{
+    FUNCTION_BINDINGS, TYPE_BINDINGS, TYPE_NAME_ANY, TYPE_NAME_TABLE, VARIABLE_BINDINGS,
+};
+use parser::{FaradayParser, Pair, Pairs, Parser, Rule};
 use pathbufd::PathBufD as PathBuf;
 use std::{
+    collections::BTreeSet,
     fs::read_to_string,
     sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
 };
 
+pub mod ast;
 pub mod bindings;
 pub mod checking;
 pub mod config;
 pub mod data;
+pub mod fold;
+pub mod scope;
+pub mod sourcemap;
 pub mod tempfile;
 
 use checking::{
-    CompilerError, MultipleTypeChecking, Registers, ToSource, fcompiler_general_error,
+    COMPILER_DIAGNOSTICS, CompilerError, Diagnostic, MultipleTypeChecking, Registers, Severity,
+    ToSource, fcompiler_general_error, fcompiler_general_warning, fcompiler_recover,
     fcompiler_type_error,
 };
+use config::{
+    COMPILER_POST_PROCESSORS, COMPILER_SOURCE_COMMENTS, COMPILER_SOURCEMAP, COMPILER_STRIP_COMMENTS,
+};
 use data::{
-    Conditional, ExprCall, ExprUse, ForLoop, Function, FunctionCall, Impl, MutabilityModifier,
-    Type, TypeAlias, TypeVisibility, Variable, WhileLoop, use_file,
+    Break, Cast, Conditional, Continue, ExprCall, ExprUse, ForLoop, Function, FunctionCall, Impl,
+    ImportGuard, Match, MultiVariable, MutabilityModifier, NumericForLoop, RepeatLoop,
+    StructLiteral, Trait, Type, TypeAlias, TypeVisibility, Variable, WhileLoop,
+    clear_finished_expressions, identifier_source, interpolate, use_file,
 };
+use fold::Constant;
+use sourcemap::{COMPILER_SOURCEMAPS, SOURCEMAP_MARKER, SourceMap};
 
 pub type ParserPairs<'a> = Pairs<'a, Rule>;
 
 pub static COMPILER_MARKER: LazyLock<Mutex<(String, String)>> =
     LazyLock::new(|| Mutex::new((String::default(), String::default())));
 
+/// Point [`COMPILER_MARKER`] at the span of the given `pair`.
+fn set_marker(pair: &Pair<'_, Rule>, registers: &Registers) {
+    let span = pair.as_span();
+
+    let start = span.start_pos().line_col();
+    let end = span.end_pos().line_col();
+
+    let marker = format!(
+        "{}:{}:{}",
+        registers.get_var("@@FARADAY_PATH").value,
+        start.0,
+        start.1
+    );
+
+    let marker_end = format!(
+        "{}:{}:{}",
+        registers.get_var("@@FARADAY_PATH").value,
+        end.0,
+        end.1
+    );
+
+    match COMPILER_MARKER.lock() {
+        Ok(mut w) => *w = (marker.replace("./", ""), marker_end.replace("./", "")),
+        Err(_) => COMPILER_MARKER.clear_poison(),
+    }
+}
+
 /// Generate a Lua output from the given parser output
-pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registers) {
-    fcompiler_marker!("{}", registers.get_var("@@FARADAY_PATH").value);
+pub fn process(input: ParserPairs, registers: Registers) -> (String, Registers) {
+    process_items(input, registers, false)
+}
+
+/// [`process`], but keep going after an error in one of the items (the error is
+/// still reported, and can be found in [`COMPILER_DIAGNOSTICS`]).
+///
+/// Recovery is coarse: the rest of the failed item is skipped, and anything it
+/// registered before failing is kept.
+pub fn process_recovering(input: ParserPairs, registers: Registers) -> (String, Registers) {
+    process_items(input, registers, true)
+}
+
+fn process_items(
+    input: ParserPairs,
+    mut registers: Registers,
+    recover: bool,
+) -> (String, Registers) {
+    let path = registers.get_var("@@FARADAY_PATH").value;
+
+    if !path.is_empty() {
+        fcompiler_marker!("{path}");
+    }
+
     let do_compile = registers.get_var("@@FARADAY_NO_COMPILE").value == "false";
+    let source_comments = do_compile
+        && !path.is_empty()
+        && *COMPILER_SOURCE_COMMENTS.read().unwrap()
+        && !*COMPILER_STRIP_COMMENTS.read().unwrap();
+    let sourcemap = do_compile && !path.is_empty() && *COMPILER_SOURCEMAP.read().unwrap();
 
     let mut src_out = String::new();
+    let mut skip_next: bool = false;
 
     for pair in input {
         let rule = pair.as_rule();
 
-        // marker
-        let span = pair.as_span();
+        if skip_next {
+            // previous pair was a disabled `#[cfg(...)]` pragma
+            skip_next = false;
+            continue;
+        }
 
-        let start = span.start_pos().line_col();
-        let end = span.end_pos().line_col();
+        // marker
+        set_marker(&pair, &registers);
 
-        let marker = format!(
-            "{}:{}:{}",
-            registers.get_var("@@FARADAY_PATH").value,
-            start.0,
-            start.1
-        );
+        if !matches!(rule, Rule::EOI | Rule::module_macro | Rule::r#macro) {
+            let marker = &COMPILER_MARKER.lock().unwrap().0;
 
-        let marker_end = format!(
-            "{}:{}:{}",
-            registers.get_var("@@FARADAY_PATH").value,
-            end.0,
-            end.1
-        );
+            if source_comments {
+                src_out.push_str(&format!("-- {marker}\n"));
+            }
 
-        match COMPILER_MARKER.lock() {
-            Ok(mut w) => {
-                *w = (
-                    marker.clone().replace("./", ""),
-                    marker_end.clone().replace("./", ""),
-                )
+            if sourcemap {
+                src_out.push_str(&format!("{SOURCEMAP_MARKER}{marker}\n"));
             }
-            Err(_) => COMPILER_MARKER.clear_poison(),
         }
 
         // ...
-        match rule {
+        let item = || match rule {
             Rule::function => {
-                let function: Function = (pair, &registers).into();
+                let function: Function = (pair.clone(), &registers).into();
 
                 if do_compile {
-                    src_out.push_str(&function.transform());
+                    src_out.push_str(&function.transform(&registers.config));
                 }
 
+                check_shadowing(&pair, &function.ident, &registers);
                 registers.functions.insert(function.ident.clone(), function);
             }
             Rule::block => {
-                src_out.push_str(&process(pair.into_inner(), Registers::default()).0);
+                src_out.push_str(&process(pair.into_inner(), registers.scoped()).0);
             }
             Rule::r#return => {
-                let return_value = pair.into_inner().next().unwrap();
+                // `return a, b` returns several values (typed as a tuple)
+                let mut values: Vec<String> = Vec::new();
+
+                for return_value in pair.into_inner() {
+                    let value = match return_value.as_rule() {
+                        Rule::identifier => {
+                            let var = registers.get_var(return_value.as_str());
+
+                            if var.is_referenced {
+                                fcompiler_general_error(
+                                    CompilerError::NoReturnReference,
+                                    var.ident,
+                                );
+                            }
 
-                match return_value.as_rule() {
-                    Rule::identifier => {
-                        let var = registers.get_var(return_value.as_str());
+                            if !do_compile {
+                                continue;
+                            }
 
-                        if var.is_referenced {
-                            fcompiler_general_error(CompilerError::NoReturnReference, var.ident);
+                            // use the full path, `var.ident` is only the last segment of
+                            // a path like `Enum.Variant`
+                            identifier_source(return_value.as_str(), &registers)
                         }
-
-                        if do_compile {
-                            src_out.push_str(&format!("return {}", var.ident));
+                        // literals have no inner pairs to process
+                        Rule::string if do_compile => {
+                            interpolate(return_value.as_str(), &registers)
                         }
-                    }
-                    _ => {
-                        if do_compile {
-                            src_out.push_str(&format!(
-                                "return {}",
-                                process(return_value.into_inner(), registers.clone()).0
-                            ));
+                        Rule::integer | Rule::float | Rule::boolean => {
+                            return_value.as_str().to_string()
                         }
-                    }
+                        Rule::nil => registers.config.nil.to_string(),
+                        Rule::struct_value => StructLiteral::from((return_value, &registers))
+                            .transform(&registers.config),
+                        Rule::ordered_cast => {
+                            Cast::from((return_value, &registers)).transform(&registers.config)
+                        }
+                        _ if do_compile => process(return_value.into_inner(), registers.clone()).0,
+                        _ => continue,
+                    };
+
+                    values.push(value);
+                }
+
+                if do_compile {
+                    src_out.push_str(&format!("return {}", values.join(", ")));
                 }
             }
             Rule::pair => {
                 let variable: Variable = (pair, &registers).into();
 
                 if do_compile {
-                    src_out.push_str(&variable.transform());
+                    src_out.push_str(&variable.transform(&registers.config));
                 }
 
                 registers.variables.insert(variable.ident.clone(), variable);
             }
+            Rule::multi_pair => {
+                let variables = MultiVariable::from((pair, &registers));
+
+                if do_compile {
+                    src_out.push_str(&variables.transform(&registers.config));
+                }
+
+                for variable in variables.0 {
+                    registers.variables.insert(variable.ident.clone(), variable);
+                }
+            }
             Rule::reassignment => {
                 let mut variable: Variable = pair.clone().into();
                 variable.visibility = TypeVisibility::Public; // must be public or reassignment isn't valid in lua
@@ -133,38 +230,52 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                     }
                 }
 
+                if let Some(var) = registers.variables.get_mut(&variable.ident) {
+                    var.uninitialized = false;
+                }
+
                 if do_compile && !variable.r#type.ident.is_empty() {
-                    src_out.push_str(&variable.transform());
-                } else if variable.r#type.ident.is_empty() {
-                    src_out.push_str(pair.as_str());
+                    src_out.push_str(&variable.transform(&registers.config));
+                } else if do_compile && variable.r#type.ident.is_empty() {
+                    src_out.push_str(&(pair.as_str().to_string() + "\n"));
                 }
             }
             Rule::call => {
-                let call = FunctionCall::from(pair);
+                let call = FunctionCall::from((pair, &registers));
                 let supplied_types = call.arg_types(&registers);
                 call.check_multiple(supplied_types, &registers);
 
                 if do_compile {
-                    src_out.push_str(&call.transform());
+                    src_out.push_str(&call.transform(&registers.config));
                 }
             }
             Rule::r#struct => {
-                let t = Type::from(pair);
+                let mut t = Type::from(pair.clone());
+                t.visibility = TypeVisibility::of(&pair, &registers);
+                t.lower_defaults(pair.clone(), &registers);
 
                 if do_compile {
-                    src_out.push_str(&t.transform());
+                    src_out.push_str(&t.transform(&registers.config));
                 }
 
+                check_shadowing(&pair, &t.ident, &registers);
                 registers.types.insert(t.ident.clone(), t.clone());
                 registers
                     .variables
                     .insert(t.ident.clone(), (t.ident.clone(), t).into());
             }
             Rule::r#enum => {
-                let t = Type::from(pair);
+                let mut t = Type::from(pair.clone());
+                t.visibility = TypeVisibility::of(&pair, &registers);
 
                 if do_compile {
-                    src_out.push_str(&t.transform());
+                    src_out.push_str(&t.transform(&registers.config));
+                }
+
+                check_shadowing(&pair, &t.ident, &registers);
+
+                for function in t.constructors() {
+                    registers.functions.insert(function.ident.clone(), function);
                 }
 
                 registers.types.insert(t.ident.clone(), t.clone());
@@ -173,34 +284,80 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                     .insert(t.ident.clone(), (t.ident.clone(), t).into());
             }
             Rule::type_alias => {
-                let t = TypeAlias::from(pair);
+                let mut t = TypeAlias::from(pair.clone());
+                t.visibility = TypeVisibility::of(&pair, &registers);
 
                 if do_compile {
-                    src_out.push_str(&t.transform());
+                    src_out.push_str(&t.transform(&registers.config));
                 }
 
                 let mut ty = registers.get_type(&t.r#type.ident);
                 ty.generics = t.r#type.generics;
+                check_shadowing(&pair, &t.ident.ident, &registers);
                 registers.types.insert(t.ident.ident.clone(), ty.clone());
                 registers
                     .variables
                     .insert(t.ident.ident.clone(), (t.ident.ident.clone(), ty).into());
             }
             Rule::for_loop => {
+                let for_loop = ForLoop::from((pair, &registers));
+
                 if do_compile {
-                    src_out.push_str(&ForLoop::from((pair, &registers)).transform())
+                    src_out.push_str(&for_loop.transform(&registers.config))
+                }
+            }
+            Rule::numeric_for => {
+                let for_loop = NumericForLoop::from((pair, &registers));
+
+                if do_compile {
+                    src_out.push_str(&for_loop.transform(&registers.config))
+                }
+            }
+            Rule::r#break => {
+                if do_compile {
+                    src_out.push_str(&Break::from((pair, &registers)).transform(&registers.config))
+                }
+            }
+            Rule::r#continue => {
+                if do_compile {
+                    src_out
+                        .push_str(&Continue::from((pair, &registers)).transform(&registers.config))
                 }
             }
             Rule::while_loop => {
+                let while_loop = WhileLoop::from((pair, &registers));
+
                 if do_compile {
-                    src_out.push_str(&WhileLoop::from((pair, &registers)).transform())
+                    src_out.push_str(&while_loop.transform(&registers.config))
+                }
+            }
+            Rule::repeat_loop => {
+                let repeat_loop = RepeatLoop::from((pair, &registers));
+
+                if do_compile {
+                    src_out.push_str(&repeat_loop.transform(&registers.config))
                 }
             }
             Rule::conditional => {
+                let conditional = Conditional::from((pair, &registers));
+                registers.mark_assigned(&conditional.assigned);
+
+                if do_compile {
+                    src_out.push_str(&conditional.transform(&registers.config))
+                }
+            }
+            Rule::r#match => {
+                let r#match = Match::from((pair, &registers));
+                registers.mark_assigned(&r#match.assigned);
+
                 if do_compile {
-                    src_out.push_str(&Conditional::from((pair, &registers)).transform())
+                    src_out.push_str(&r#match.transform(&registers.config))
                 }
             }
+            Rule::r#trait => {
+                let t = Trait::from((pair, &registers));
+                registers.traits.insert(t.ident.clone(), t);
+            }
             Rule::r#impl => {
                 let i = Impl::from((pair, &registers));
 
@@ -212,7 +369,7 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                 }
 
                 if do_compile {
-                    src_out.push_str(&i.transform());
+                    src_out.push_str(&i.transform(&registers.config));
                 }
             }
             Rule::r#use => {
@@ -246,9 +403,16 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                 }
 
                 if do_compile {
-                    src_out.push_str(&format!(
-                        "local {ident} = require \"{relative_file_path}\"\n"
-                    ));
+                    let config = &registers.config;
+                    let module_name = config::COMPILER_MODULE_NAME.read().unwrap();
+                    let module = module_name(&relative_file_path);
+
+                    src_out.push_str(
+                        &config
+                            .require
+                            .replace("$ident", &ident)
+                            .replace("$module", &module),
+                    );
                 }
 
                 // register module
@@ -276,7 +440,18 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                 );
 
                 // process file and merge registers
-                use_file(path, relative_file_path, ident, do_compile, &mut registers);
+                use_file(
+                    path,
+                    relative_file_path,
+                    ident,
+                    do_compile,
+                    false,
+                    &mut registers,
+                );
+            }
+            Rule::module_macro => {
+                let call = FunctionCall::from(pair.into_inner().next().unwrap());
+                module_pragma(&call, &mut registers);
             }
             Rule::r#macro => {
                 let call = FunctionCall::from(pair.into_inner().next().unwrap());
@@ -287,9 +462,14 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                     }
                     "expr_call" => {
                         if do_compile {
-                            src_out.push_str(&ExprCall::from(call).transform())
+                            src_out.push_str(&ExprCall::from(call).transform(&registers.config))
                         }
                     }
+                    "cfg" => {
+                        // skip the next item entirely if the feature isn't enabled
+                        skip_next = !cfg_enabled(&call, &registers);
+                    }
+                    "static_assert" => static_assert(&call, &registers),
                     _ => fcompiler_general_error(CompilerError::NoSuchFunction, call.ident),
                 };
             }
@@ -298,28 +478,214 @@ pub fn process(input: ParserPairs, mut registers: Registers) -> (String, Registe
                     src_out.push_str(&(pair.as_str().to_string() + "\n"))
                 }
             }
+        };
+
+        if recover {
+            // the error was already reported, move on to the next item
+            let _ = fcompiler_recover(item);
+        } else {
+            item();
         }
     }
 
     (src_out, registers)
 }
 
+/// Warn if the given declaration replaces a built-in function or type, unless
+/// it's marked with `@shadow`.
+fn check_shadowing(pair: &Pair<'_, Rule>, ident: &str, registers: &Registers) {
+    let builtin = match pair.as_rule() {
+        Rule::function => FUNCTION_BINDINGS.contains_key(ident),
+        _ => TYPE_BINDINGS.contains_key(ident),
+    };
+
+    if builtin
+        && !pair
+            .clone()
+            .into_inner()
+            .any(|p| p.as_rule() == Rule::shadow_attribute)
+    {
+        // the marker may have moved into the body of the declaration
+        set_marker(pair, registers);
+        fcompiler_general_warning(CompilerError::ShadowedBuiltin, ident.to_string());
+    }
+}
+
+/// Check if the feature named by a `#[cfg(feature)]` pragma is enabled.
+fn cfg_enabled(call: &FunctionCall, registers: &Registers) -> bool {
+    let feature = match call.arguments.first() {
+        Some(f) => f.as_str().replace("\"", ""),
+        None => fcompiler_error!("cfg pragma requires a feature name"),
+    };
+
+    registers.features.contains(&feature)
+}
+
+/// Evaluate a `#[static_assert(condition, "message")]` pragma, stopping compilation
+/// with the given message if the condition is false.
+fn static_assert(call: &FunctionCall, registers: &Registers) {
+    let condition = match call.arguments.first() {
+        Some(c) => c,
+        None => fcompiler_error!("static_assert pragma requires a condition"),
+    };
+
+    match fold::fold(condition.clone(), registers) {
+        Some(Constant::Bool(true)) => {}
+        Some(Constant::Bool(false)) => fcompiler_general_error(
+            CompilerError::AssertionFailed,
+            match call.arguments.get(1) {
+                Some(message) => message.as_str().trim_matches('"').to_string(),
+                None => condition.as_str().to_string(),
+            },
+        ),
+        _ => fcompiler_general_error(CompilerError::NotConstant, condition.as_str().to_string()),
+    }
+}
+
+/// Apply a module-level `#![pragma(...)]` to the given registers.
+fn module_pragma(call: &FunctionCall, registers: &mut Registers) {
+    match call.ident.as_str() {
+        "default_visibility" => {
+            registers.default_visibility = match call.arguments.first().map(|a| a.as_str()) {
+                Some("pub") => TypeVisibility::Public,
+                Some("prv") => TypeVisibility::Private,
+                _ => fcompiler_error!("default_visibility pragma requires `pub` or `prv`"),
+            }
+        }
+        _ => fcompiler_general_error(CompilerError::NoSuchFunction, call.ident.clone()),
+    }
+}
+
+/// Register the signatures of all types, functions, and impl methods in the given
+/// parser output without processing any bodies.
+///
+/// This allows items to be used before they're declared in the file.
+pub fn register_signatures(input: ParserPairs, registers: &mut Registers) {
+    let mut skip_next: bool = false;
+    let mut aliases = Vec::new();
+    let mut deferred = Vec::new();
+    let mut traits: BTreeSet<String> = BTreeSet::new();
+
+    // types first, since function signatures reference them
+    for pair in input {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+
+        set_marker(&pair, registers);
+
+        match pair.as_rule() {
+            Rule::r#struct | Rule::r#enum => {
+                let mut t = Type::from(pair.clone());
+                t.visibility = TypeVisibility::of(&pair, registers);
+
+                for function in t.constructors() {
+                    registers.functions.insert(function.ident.clone(), function);
+                }
+
+                registers.types.insert(t.ident.clone(), t.clone());
+                registers
+                    .variables
+                    .insert(t.ident.clone(), (t.ident.clone(), t).into());
+            }
+            Rule::type_alias => aliases.push(pair),
+            Rule::function | Rule::r#impl | Rule::r#trait => deferred.push(pair),
+            Rule::module_macro => {
+                let call = FunctionCall::from(pair.into_inner().next().unwrap());
+                module_pragma(&call, registers);
+            }
+            Rule::r#macro => {
+                let call = FunctionCall::from(pair.into_inner().next().unwrap());
+
+                if call.ident == "cfg" {
+                    skip_next = !cfg_enabled(&call, registers);
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    // then aliases of those types (an alias of a type from a `use`d module is
+    // registered once it's processed, since modules aren't imported yet)
+    for pair in aliases {
+        let t = TypeAlias::from(pair);
+
+        if let Some(mut ty) = registers.find_type(&t.r#type.ident) {
+            ty.generics = t.r#type.generics;
+            registers.types.insert(t.ident.ident.clone(), ty.clone());
+            registers
+                .variables
+                .insert(t.ident.ident.clone(), (t.ident.ident.clone(), ty).into());
+        }
+    }
+
+    // functions and methods (the same goes for signatures using those types)
+    for pair in deferred {
+        set_marker(&pair, registers);
+
+        match pair.as_rule() {
+            Rule::function => {
+                if let Some(function) = Function::try_signature(pair, registers) {
+                    registers.functions.insert(function.ident.clone(), function);
+                }
+            }
+            Rule::r#impl => {
+                for function in Impl::signatures(pair, registers) {
+                    registers.functions.insert(function.ident.clone(), function);
+                }
+            }
+            Rule::r#trait => {
+                let t = Trait::from((pair, &*registers));
+
+                if !traits.insert(t.ident.clone()) {
+                    fcompiler_general_error(CompilerError::DuplicateTrait, t.ident);
+                }
+
+                registers.traits.insert(t.ident.clone(), t);
+            }
+            _ => unreachable!("only functions, impls and traits are deferred"),
+        }
+    }
+}
+
 macro_rules! publish_register {
-    ($registers:ident.$sub:ident >> $src_out:ident) => {
+    ($registers:ident.$sub:ident >> $src_out:ident, $exported:ident, $strip_comments:ident) => {
+        publish_register!(
+            $registers.$sub >> $src_out,
+            $exported,
+            $strip_comments,
+            |ident: &String, _| ident.clone()
+        );
+    };
+    // `$name` gets the key an item is exported as
+    (
+        $registers:ident.$sub:ident >> $src_out:ident,
+        $exported:ident,
+        $strip_comments:ident,
+        $name:expr
+    ) => {
         let reg_name_for_label = stringify!($sub);
+        let name_of = $name;
         let reg = &$registers.$sub;
-        $src_out.push_str(&format!("    -- faraday.registers:{reg_name_for_label}\n"));
+
+        if !$strip_comments {
+            $src_out.push_str(&format!("    -- faraday.registers:{reg_name_for_label}\n"));
+        }
 
         for (ident, item) in reg {
+            let name: String = name_of(ident, item);
+
             if (item.visibility != $crate::data::TypeVisibility::Public)
                 | ident.contains(".")
                 | ident.contains(":")
                 | ident.contains("[")
+                | !$exported.insert(name.clone())
             {
                 continue;
             }
 
-            $src_out.push_str(&format!("    {} = {},\n", ident, ident));
+            $src_out.push_str(&format!("    {} = {},\n", name, ident));
         }
     };
 }
@@ -333,6 +699,9 @@ macro_rules! define {
             visibility: $crate::data::TypeVisibility::Private,
             mutable: $crate::data::MutabilityModifier::Constant,
             is_referenced: false,
+            uninitialized: false,
+            align: None,
+            size: None,
         });
     };
 
@@ -344,51 +713,243 @@ macro_rules! define {
             visibility: $crate::data::TypeVisibility::Private,
             mutable: $crate::data::MutabilityModifier::Constant,
             is_referenced: false,
+            uninitialized: false,
+            align: None,
+            size: None,
         });
     };
 }
 
+/// Process source which isn't in a file (like input to a REPL).
+///
+/// Unlike [`process_file`], no export table is added to the output.
+///
+/// # Returns
+/// `(lua output, registers)`
+pub fn process_string(input: &str, mut registers: Registers) -> (String, Registers) {
+    if !registers.variables.contains_key("@@FARADAY_PATH_PARENT") {
+        // `use` paths are relative to the current directory
+        let parent = ".";
+        define!("@@FARADAY_PATH_PARENT" = parent >> registers);
+    }
+
+    define!("@@FARADAY_NO_COMPILE" = false >> registers);
+
+    let parsed = match FaradayParser::parse(parser::Rule::document, input) {
+        Ok(mut p) => p.next().unwrap().into_inner(),
+        Err(e) => fcompiler_error!("{e}"),
+    };
+
+    register_signatures(parsed.clone(), &mut registers);
+    let processed = process(parsed, registers);
+
+    clear_finished_expressions();
+    processed
+}
+
 /// Process an individual file given its `path`.
+///
+/// An error in one top-level item doesn't stop the rest of the file from being
+/// checked, so the output should only be used if none of the diagnostics are errors.
+///
+/// # Returns
+/// `(lua output, registers, diagnostics reported while compiling this file and its imports)`
 pub fn process_file(
     path: PathBuf,
-    mut registers: Registers,
+    registers: Registers,
     check_only: bool,
-) -> (String, Registers) {
-    // define some compiler variables
-    define!(
-        "@@FARADAY_PATH_PARENT" = (path.as_path().parent().unwrap().to_str().unwrap()) >> registers
-    );
+) -> (String, Registers, Vec<Diagnostic>) {
+    let (src_out, registers, diagnostics, _) = process_file_with_stats(path, registers, check_only);
+    (src_out, registers, diagnostics)
+}
 
-    define!("@@FARADAY_PATH" = (path.as_path().to_str().unwrap()) >> registers);
-    define!("@@FARADAY_NO_COMPILE" = check_only >> registers);
+/// [`process_file`], but return the (first) error instead of exiting the process.
+pub fn try_process_file(
+    path: PathBuf,
+    registers: Registers,
+    check_only: bool,
+) -> Result<(String, Registers, Vec<Diagnostic>), Box<Diagnostic>> {
+    let output = fcompiler_recover(|| process_file(path, registers, check_only))?;
 
-    // ...
-    let mut src_out: String = String::new();
+    match output.2.iter().find(|d| d.severity == Severity::Error) {
+        Some(error) => Err(Box::new(error.clone())),
+        None => Ok(output),
+    }
+}
 
-    let file_string = match read_to_string(path) {
+/// Statistics about the compilation of a file (and the files it imports).
+#[derive(Debug, Clone, Default)]
+pub struct CompileStats {
+    /// Functions (and methods) registered, not counting built-ins.
+    pub functions: usize,
+    /// Types registered, not counting built-ins.
+    pub types: usize,
+    /// Variables registered, not counting compiler variables.
+    pub variables: usize,
+    /// Lines of Lua emitted.
+    pub lines: usize,
+    /// Time spent parsing.
+    pub parse: Duration,
+    /// Time spent registering signatures.
+    pub signatures: Duration,
+    /// Time spent checking and generating Lua.
+    pub process: Duration,
+    /// Time spent building exports and running post-processing hooks.
+    pub export: Duration,
+}
+
+impl CompileStats {
+    /// Count the items in the given registers.
+    fn count(&mut self, registers: &Registers) {
+        self.functions = registers
+            .functions
+            .keys()
+            .filter(|k| !FUNCTION_BINDINGS.contains_key(*k))
+            .count();
+
+        self.types = registers
+            .types
+            .keys()
+            .filter(|k| !TYPE_BINDINGS.contains_key(*k))
+            .count();
+
+        self.variables = registers
+            .variables
+            .keys()
+            .filter(|k| !k.starts_with("@@") && (*k != "self"))
+            .filter(|k| !VARIABLE_BINDINGS.contains_key(*k))
+            .count();
+    }
+}
+
+/// [`process_file`], but also return [`CompileStats`].
+pub fn process_file_with_stats(
+    path: PathBuf,
+    registers: Registers,
+    check_only: bool,
+) -> (String, Registers, Vec<Diagnostic>, CompileStats) {
+    let first_diagnostic = COMPILER_DIAGNOSTICS.lock().unwrap().len();
+    let _guard = ImportGuard::push(path.as_path());
+
+    let file_string = match read_to_string(&path) {
         Ok(f) => f,
         Err(e) => fcompiler_error!("{e}"),
     };
 
-    let parsed = match FaradayParser::parse(parser::Rule::document, &file_string) {
+    let (src_out, registers, stats) =
+        compile_str_with_stats(&file_string, &path.to_string(), registers, check_only);
+
+    // return
+    let diagnostics = COMPILER_DIAGNOSTICS.lock().unwrap()[first_diagnostic..].to_vec();
+    (src_out, registers, diagnostics, stats)
+}
+
+/// Compile `source` as if it was the file at `virtual_path` (which doesn't have to
+/// exist). `use` paths are relative to the parent of `virtual_path`.
+///
+/// Unlike [`process_string`], the output is a full module (with an export table).
+///
+/// # Returns
+/// `(lua output, registers)`
+pub fn compile_str(
+    source: &str,
+    virtual_path: &str,
+    registers: Registers,
+    check_only: bool,
+) -> (String, Registers) {
+    let (src_out, registers, _) =
+        compile_str_with_stats(source, virtual_path, registers, check_only);
+    (src_out, registers)
+}
+
+/// [`compile_str`], but also return [`CompileStats`].
+pub fn compile_str_with_stats(
+    source: &str,
+    virtual_path: &str,
+    mut registers: Registers,
+    check_only: bool,
+) -> (String, Registers, CompileStats) {
+    let mut stats = CompileStats::default();
+
+    // define some compiler variables
+    let parent = std::path::Path::new(virtual_path)
+        .parent()
+        .and_then(|p| p.to_str())
+        .unwrap_or(".");
+    define!("@@FARADAY_PATH_PARENT" = parent >> registers);
+
+    let path_string = virtual_path.to_string();
+    define!("@@FARADAY_PATH" = path_string >> registers);
+    define!("@@FARADAY_NO_COMPILE" = check_only >> registers);
+
+    // ...
+    let mut src_out: String = String::new();
+
+    let start = Instant::now();
+    let parsed = match FaradayParser::parse(parser::Rule::document, source) {
         Ok(mut p) => p.next().unwrap().into_inner(),
         Err(e) => fcompiler_error!("{e}"),
     };
+    stats.parse = start.elapsed();
+
+    let start = Instant::now();
+    register_signatures(parsed.clone(), &mut registers);
+    stats.signatures = start.elapsed();
 
-    let compiled = process(parsed, registers);
-    src_out.push_str(&compiled.0);
+    let start = Instant::now();
+    let compiled = process_recovering(parsed, registers);
     registers = compiled.1;
 
-    // build export list
-    let mut export = format!("\n-- faraday.module\nreturn {{\n");
+    if !check_only && *COMPILER_SOURCEMAP.read().unwrap() {
+        // the export table isn't mapped, so markers can be taken out now
+        let (src, map) = SourceMap::extract(&compiled.0);
+        src_out.push_str(&src);
+        COMPILER_SOURCEMAPS.lock().unwrap().insert(path_string, map);
+    } else {
+        src_out.push_str(&compiled.0);
+    }
+
+    stats.process = start.elapsed();
+
+    // build export list (bare modules are run as scripts, so they don't need one)
+    let start = Instant::now();
+    let bare = registers.get_var("@@FARADAY_BARE_MODULE").value == "true";
+
+    if !bare {
+        let strip_comments = *COMPILER_STRIP_COMMENTS.read().unwrap();
+        let mut export = if strip_comments {
+            "\nreturn {\n".to_string()
+        } else {
+            format!("\n-- faraday.module\nreturn {{\n")
+        };
+
+        // an identifier can be in more than one register (structs are both a type and
+        // a variable), but it can only be exported once. registers published first win
+        let mut exported: BTreeSet<String> = BTreeSet::new();
+        publish_register!(registers.types >> export, exported, strip_comments);
+        publish_register!(
+            registers.functions >> export,
+            exported,
+            strip_comments,
+            |ident: &String, function: &Function| {
+                function.export_name.clone().unwrap_or(ident.clone())
+            }
+        );
+        publish_register!(registers.variables >> export, exported, strip_comments);
+
+        export.push_str("}");
+        src_out.push_str(&export);
+    }
 
-    publish_register!(registers.types >> export);
-    publish_register!(registers.functions >> export);
-    publish_register!(registers.variables >> export);
+    // post-processing hooks
+    for hook in COMPILER_POST_PROCESSORS.read().unwrap().iter() {
+        src_out = hook(&src_out);
+    }
 
-    export.push_str("}");
-    src_out.push_str(&export);
+    stats.export = start.elapsed();
+    stats.lines = src_out.lines().count();
+    stats.count(&registers);
 
-    // return
-    (src_out, registers)
+    clear_finished_expressions();
+    (src_out, registers, stats)
 }
@@ -0,0 +1,221 @@
+//! Copy-on-write maps for [`crate::checking::Registers`].
+//!
+//! Registers are cloned for every nested block, so cloning them has to be cheap. A
+//! [`Scope`] is a chain of layers: cloning freezes the current layer (sharing it with
+//! the clone), and every scope only writes to its own (newest) layer. Lookups walk the
+//! chain from the newest layer to the oldest.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, btree_map},
+    sync::Arc,
+};
+
+/// The number of layers a [`Scope`] can have before they're flattened into one.
+const MAX_DEPTH: usize = 16;
+
+/// A single layer of a [`Scope`]. Removed keys are `None`, so they hide the
+/// entries of older layers.
+type Layer<V> = BTreeMap<String, Option<V>>;
+
+/// A map of identifiers to items, which shares its entries with its clones.
+#[derive(Debug)]
+pub struct Scope<V> {
+    /// The frozen layers below `layer`.
+    parent: Option<Arc<Scope<V>>>,
+    layer: Arc<Layer<V>>,
+    depth: usize,
+}
+
+impl<V> Default for Scope<V> {
+    fn default() -> Self {
+        Self {
+            parent: None,
+            layer: Arc::new(BTreeMap::new()),
+            depth: 0,
+        }
+    }
+}
+
+impl<V: Clone> Clone for Scope<V> {
+    fn clone(&self) -> Self {
+        // both scopes share the current layer, and freeze it before they write to it
+        Self {
+            parent: self.parent.clone(),
+            layer: self.layer.clone(),
+            depth: self.depth,
+        }
+    }
+}
+
+impl<V: Clone> Scope<V> {
+    /// Get the entry for `key` in the newest layer which has one.
+    fn entry(&self, key: &str) -> Option<&Option<V>> {
+        let mut scope = self;
+
+        loop {
+            if let Some(entry) = scope.layer.get(key) {
+                return Some(entry);
+            }
+
+            scope = scope.parent.as_deref()?;
+        }
+    }
+
+    /// Get the layer of `self` which can be written to.
+    ///
+    /// A layer shared with a clone is frozen into the parent (so the clone keeps
+    /// seeing it as it was), and a new layer is started.
+    fn layer_mut(&mut self) -> &mut Layer<V> {
+        if Arc::strong_count(&self.layer) > 1 {
+            let frozen = Self {
+                parent: self.parent.take(),
+                layer: std::mem::take(&mut self.layer),
+                depth: self.depth,
+            };
+
+            if frozen.depth >= MAX_DEPTH {
+                // lookups walk every layer, so the chain can't get too long
+                self.layer = Arc::new(frozen.flatten());
+                self.depth = 0;
+            } else {
+                self.parent = Some(Arc::new(frozen));
+                self.depth += 1;
+            }
+        }
+
+        Arc::get_mut(&mut self.layer).unwrap()
+    }
+
+    /// Merge every layer of `self` into one (without any removed entries).
+    fn flatten(&self) -> Layer<V> {
+        self.iter()
+            .map(|(key, value)| (key.to_owned(), Some(value.to_owned())))
+            .collect()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entry(key)?.as_ref()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Get a mutable reference to the entry for `key`. Entries of frozen layers are
+    /// copied into the newest layer first.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let value = self.get(key)?.to_owned();
+        let layer = self.layer_mut();
+
+        layer.entry(key.to_owned()).or_insert(Some(value)).as_mut()
+    }
+
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        let previous = self.get(&key).cloned();
+        self.layer_mut().insert(key, Some(value));
+        previous
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let previous = self.get(key).cloned();
+
+        if previous.is_some() {
+            self.layer_mut().insert(key.to_owned(), None);
+        }
+
+        previous
+    }
+
+    /// Iterate over every entry (in key order).
+    pub fn iter(&self) -> btree_map::IntoIter<&String, &V> {
+        let mut layers = vec![&self.layer];
+        let mut scope = self;
+
+        while let Some(parent) = scope.parent.as_deref() {
+            layers.push(&parent.layer);
+            scope = parent;
+        }
+
+        // newer layers overwrite (or remove) the entries of older ones
+        let mut entries: BTreeMap<&String, &V> = BTreeMap::new();
+
+        for layer in layers.into_iter().rev() {
+            for (key, value) in layer.iter() {
+                match value {
+                    Some(value) => entries.insert(key, value),
+                    None => entries.remove(key),
+                };
+            }
+        }
+
+        entries.into_iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<V: Clone> From<BTreeMap<String, V>> for Scope<V> {
+    fn from(value: BTreeMap<String, V>) -> Self {
+        Self {
+            parent: None,
+            layer: Arc::new(value.into_iter().map(|(k, v)| (k, Some(v))).collect()),
+            depth: 0,
+        }
+    }
+}
+
+impl<V: Clone> FromIterator<(String, V)> for Scope<V> {
+    fn from_iter<T: IntoIterator<Item = (String, V)>>(iter: T) -> Self {
+        Self::from(iter.into_iter().collect::<BTreeMap<_, _>>())
+    }
+}
+
+impl<'a, V: Clone> IntoIterator for &'a Scope<V> {
+    type Item = (&'a String, &'a V);
+    type IntoIter = btree_map::IntoIter<&'a String, &'a V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<V: Clone> IntoIterator for Scope<V> {
+    type Item = (String, V);
+    type IntoIter = btree_map::IntoIter<String, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+    }
+}
+
+// scopes are (de)serialized as a single map
+impl<V: Clone + Serialize> Serialize for Scope<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, V: Clone + Deserialize<'de>> Deserialize<'de> for Scope<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::from(BTreeMap::<String, V>::deserialize(
+            deserializer,
+        )?))
+    }
+}
@@ -0,0 +1,108 @@
+//! Source maps from generated Lua back to faraday source.
+//!
+//! When [`crate::config::COMPILER_SOURCEMAP`] is set, [`crate::process`] emits a
+//! [`SOURCEMAP_MARKER`] comment (with the [`crate::COMPILER_MARKER`] of the item)
+//! before every item it compiles. Once a file is processed, the markers are taken
+//! back out of the output and each line is mapped to the last marker before it.
+use crate::checking::split_marker;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, Mutex},
+};
+
+/// The comment written before every compiled item while a source map is built.
+pub const SOURCEMAP_MARKER: &str = "--@@faraday.source ";
+
+/// A range of generated lines which came from the same item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mapping {
+    /// The first and last generated line (starting at 1, inclusive).
+    pub lines: (usize, usize),
+    /// The path of the source file.
+    pub source: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A map of generated Lua lines to the source they were compiled from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub version: usize,
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Remove every [`SOURCEMAP_MARKER`] from `src`, and map the lines after each one.
+    ///
+    /// # Returns
+    /// `(src without markers, source map)`
+    pub fn extract(src: &str) -> (String, Self) {
+        let mut out = String::new();
+        let mut map = Self {
+            version: 1,
+            mappings: Vec::new(),
+        };
+
+        let mut current: Option<(String, usize, usize)> = None;
+        let mut indent = String::new();
+        let mut line_number: usize = 0;
+
+        for line in src.split_inclusive('\n') {
+            if let Some(index) = line.find(SOURCEMAP_MARKER) {
+                // anything before the marker (indentation) belongs to the next line
+                indent.push_str(&line[..index]);
+                current = Some(split_marker(
+                    line[index + SOURCEMAP_MARKER.len()..].trim_end(),
+                ));
+                continue;
+            }
+
+            out.push_str(&indent);
+            out.push_str(line);
+            indent.clear();
+            line_number += 1;
+
+            let Some((source, source_line, column)) = &current else {
+                continue;
+            };
+
+            match map.mappings.last_mut() {
+                Some(last)
+                    if (last.lines.1 == line_number - 1)
+                        && (&last.source == source)
+                        && (last.line == *source_line)
+                        && (last.column == *column) =>
+                {
+                    last.lines.1 = line_number
+                }
+                _ => map.mappings.push(Mapping {
+                    lines: (line_number, line_number),
+                    source: source.to_owned(),
+                    line: *source_line,
+                    column: *column,
+                }),
+            }
+        }
+
+        (out, map)
+    }
+
+    /// Serialize the map as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// The source map of every file compiled with [`crate::config::COMPILER_SOURCEMAP`]
+/// set, by the path of the source file.
+pub static COMPILER_SOURCEMAPS: LazyLock<Mutex<BTreeMap<String, SourceMap>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// Remove the source map of the file at `path` from [`COMPILER_SOURCEMAPS`].
+pub fn take_sourcemap(path: &str) -> Option<SourceMap> {
+    match COMPILER_SOURCEMAPS.lock() {
+        Ok(mut maps) => maps.remove(path),
+        Err(_) => None,
+    }
+}
@@ -1,19 +1,31 @@
 use pathbufd::PathBufD;
-use rand::{Rng, distributions::Alphanumeric, thread_rng};
-use std::{env::temp_dir, fs::write};
-
-pub fn random() -> String {
-    thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect()
+use std::{
+    env::temp_dir,
+    fs::{remove_file, write},
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The number of the next name returned by [`unique`].
+static UNIQUE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Get a name which hasn't been used yet (`u0`, `u1`, ...), for generated labels.
+/// Names are given out in order, so the same input always produces the same output.
+pub fn unique() -> String {
+    format!("u{}", UNIQUE_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Continue [`unique`] names from `seed`.
+pub fn reset_unique(seed: usize) {
+    UNIQUE_COUNTER.store(seed, Ordering::Relaxed)
 }
 
 /// Create a temporary file and return the path.
 pub fn create() -> PathBufD {
+    // the temp dir is shared with other compiler processes
     let tempdir = temp_dir();
-    let path = PathBufD::from(tempdir.into()).join(random());
+    let path =
+        PathBufD::from(tempdir.into()).join(format!("faraday_{}_{}", std::process::id(), unique()));
 
     if let Err(e) = write(&path, "") {
         panic!("{e}");
@@ -21,3 +33,29 @@ pub fn create() -> PathBufD {
 
     path
 }
+
+/// A temporary file which is removed once it's dropped.
+#[derive(Debug)]
+pub struct TempFile(PathBufD);
+
+impl TempFile {
+    /// Create a temporary file (see [`create`]).
+    pub fn create() -> Self {
+        Self(create())
+    }
+}
+
+impl Deref for TempFile {
+    type Target = PathBufD;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // the file might already be gone
+        let _ = remove_file(&self.0);
+    }
+}
@@ -1,74 +1,365 @@
-use compiler::checking::Registers;
-use compiler::process_file;
+use compiler::checking::{
+    COMPILER_MESSAGE_FORMAT, MessageFormat, Registers, Severity, fcompiler_print_json,
+    fcompiler_recover,
+};
+use compiler::config::{
+    COMPILER_BOUNDS_CHECKS, COMPILER_OUT_DIR, COMPILER_SOURCE_COMMENTS, COMPILER_SOURCEMAP,
+    COMPILER_STRIP_COMMENTS, COMPILER_TEMPLATES, CompilerConfig, lua_module_name,
+    set_module_name_mapper,
+};
+use compiler::data::clear_module_cache;
+use compiler::sourcemap::take_sourcemap;
+use compiler::{process_file_with_stats, process_string};
+use parser::{FaradayParser, Pair, Parser, Rule};
 use pathbufd::PathBufD as PathBuf;
+use std::collections::BTreeSet;
 use std::env::args;
 use std::fs::write;
+use std::io::{BufRead, Write};
 use std::process::Command;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
 
 fn main() {
+    // project config, replacing the default templates (before any registers copy them)
+    let config_path = PathBuf::current().join("faraday.json");
+    if config_path.as_path().exists() {
+        *COMPILER_TEMPLATES.write().unwrap() = CompilerConfig::from_file(config_path);
+    }
+
+    let mut input = "main.fd".to_string();
+    let mut exec = "-nr".to_string();
+    let mut registers = Registers::default();
+    let mut entry: Option<String> = None;
+    let mut stats = false;
+    let mut out: Option<String> = None;
+    let mut target = "lua".to_string();
+    let mut backend = "lua".to_string();
+    let mut emit: Option<String> = None;
+    let mut watch = false;
+
     let mut args = args().skip(1);
-    let input = args.next().unwrap_or("main.fd".to_string());
+    while let Some(arg) = args.next() {
+        if arg.starts_with("-r=") | (arg == "-nr") | (arg == "--repl") {
+            exec = arg;
+        } else if (arg == "-o") | (arg == "--out") {
+            // path of the output file (imports are written next to it)
+            match args.next() {
+                Some(path) => out = Some(path),
+                None => {
+                    println!("\x1b[31;1merror:\x1b[0m \x1b[1m{arg} requires a path\x1b[0m");
+                    std::process::exit(1);
+                }
+            }
+        } else if arg == "--target" {
+            // language to compile to
+            match args.next() {
+                Some(language) => target = language,
+                None => {
+                    println!("\x1b[31;1merror:\x1b[0m \x1b[1m{arg} requires a language\x1b[0m");
+                    std::process::exit(1);
+                }
+            }
+        } else if let Some(name) = arg.strip_prefix("--backend=") {
+            // compiler to use (`llvm` compiles `.rr` files with rir)
+            backend = name.to_string();
+        } else if let Some(mode) = arg.strip_prefix("--emit=") {
+            // print the parse tree (or tokens) instead of compiling
+            emit = Some(mode.to_string());
+        } else if arg == "--message-format=json" {
+            // print diagnostics as json instead of colored text
+            *COMPILER_MESSAGE_FORMAT.write().unwrap() = MessageFormat::Json;
+        } else if arg == "--strip-comments" {
+            // leave generated comments out of the output
+            *COMPILER_STRIP_COMMENTS.write().unwrap() = true;
+        } else if arg == "--source-comments" {
+            // point every emitted item back at its source
+            *COMPILER_SOURCE_COMMENTS.write().unwrap() = true;
+        } else if arg == "--bounds-checks" {
+            // assert that table indexes are in bounds at runtime
+            *COMPILER_BOUNDS_CHECKS.write().unwrap() = true;
+        } else if arg == "--sourcemap" {
+            // write a source map next to the output
+            *COMPILER_SOURCEMAP.write().unwrap() = true;
+        } else if arg == "--watch" {
+            // rebuild whenever a compiled file changes
+            watch = true;
+        } else if arg == "--stats" {
+            // print a breakdown of what was compiled
+            stats = true;
+        } else if arg == "--require=module" {
+            // require imported files by module name instead of by path
+            set_module_name_mapper(lua_module_name);
+        } else if let Some(section) = arg.strip_prefix("--entry=") {
+            // rir section to start execution at
+            entry = Some(section.to_string());
+        } else if let Some(feature) = arg.strip_prefix("--feature=") {
+            // enable feature for `#[cfg(feature)]` pragmas
+            registers.features.insert(feature.to_string());
+        } else {
+            input = arg;
+        }
+    }
+
+    let extension = match target.as_str() {
+        "lua" => "lua",
+        "js" => {
+            // javascript can't return the module export table, so files are compiled
+            // as scripts
+            let config = CompilerConfig::javascript();
+            *COMPILER_TEMPLATES.write().unwrap() = config.clone();
+            registers.config = Arc::new(config);
+
+            if let Some(var) = registers.variables.get_mut("@@FARADAY_BARE_MODULE") {
+                var.value = "true".to_string();
+            }
+
+            "js"
+        }
+        _ => {
+            println!("\x1b[31;1merror:\x1b[0m \x1b[1munknown target {target} (lua, js)\x1b[0m");
+            std::process::exit(1);
+        }
+    };
 
-    let exec = args.next().unwrap_or("-nr".to_string());
     let check_only = exec == "-r=check";
-    let run = exec.starts_with("-r=");
+    let json = *COMPILER_MESSAGE_FORMAT.read().unwrap() == MessageFormat::Json;
+
+    if exec == "--repl" {
+        repl(registers);
+        return;
+    }
 
     if exec == "-r=rir" {
         // run vm file instead
         println!(
             "{}",
-            rir::process_file_with_bindings(PathBuf::new().join(input)).1
+            rir::process_file_with_bindings(PathBuf::new().join(input), entry.as_deref()).1
         );
         return;
     }
 
+    if exec == "-r=rir-run" {
+        // compile vm file with clang and run the binary
+        let ir = rir::process_file_with_bindings(PathBuf::new().join(input), entry.as_deref()).1;
+        run_ir(ir);
+        return;
+    }
+
+    match backend.as_str() {
+        "lua" => {}
+        "llvm" => {
+            let ir =
+                rir::process_file_with_bindings(PathBuf::new().join(&input), entry.as_deref()).1;
+
+            if check_only {
+                return;
+            } else if exec.starts_with("-r=") {
+                // also writes the ir to build/main.ll
+                run_ir(ir);
+                return;
+            }
+
+            let out_path = match out {
+                Some(ref out) => PathBuf::current().join(out),
+                None => PathBuf::current().extend(&["build", "main.ll"]),
+            };
+
+            let parent = out_path.as_path().parent().unwrap();
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                let parent = parent.display();
+                println!("\x1b[31;1merror:\x1b[0m \x1b[1mfailed to create {parent}: {e}\x1b[0m");
+                std::process::exit(1);
+            }
+
+            write(&out_path, ir).unwrap();
+            return;
+        }
+        _ => {
+            println!("\x1b[31;1merror:\x1b[0m \x1b[1munknown backend {backend} (lua, llvm)\x1b[0m");
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(mode) = emit {
+        if mode == "json" {
+            emit_registers(PathBuf::current().join(&input), registers);
+        } else {
+            emit_parsed(PathBuf::current().join(&input), &mode);
+        }
+
+        return;
+    }
+
     // create build dir
-    let out_path = PathBuf::current().extend(&["build", "main.lua"]);
+    let out_path = match out {
+        Some(ref out) => PathBuf::current().join(out),
+        None => PathBuf::current().extend(&["build", &format!("main.{extension}")]),
+    };
+
     let parent = out_path.as_path().parent().unwrap();
+    *COMPILER_OUT_DIR.write().unwrap() = PathBuf::new().join(parent);
 
-    // if !parent.exists() {
-    // std::fs::create_dir_all(parent).unwrap();
-    // }
-    if parent.exists() {
-        std::fs::remove_dir_all(parent).unwrap();
+    // clear the previous build (there's none on the first run). a custom output can
+    // be anywhere, so its directory is left alone
+    if out.is_none() {
+        match std::fs::remove_dir_all(parent) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                let parent = parent.display();
+                println!("\x1b[31;1merror:\x1b[0m \x1b[1mfailed to clear {parent}: {e}\x1b[0m");
+                std::process::exit(1);
+            }
+        }
     }
 
-    std::fs::create_dir_all(parent).unwrap();
+    if let Err(e) = std::fs::create_dir_all(parent) {
+        let parent = parent.display();
+        println!("\x1b[31;1merror:\x1b[0m \x1b[1mfailed to create {parent}: {e}\x1b[0m");
+        std::process::exit(1);
+    }
+
+    let input_path = PathBuf::current().join(&input);
+
+    if !watch {
+        if build(&input, &out_path, registers, check_only, json, stats, &exec).is_err() {
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    // rebuild whenever the input or one of its imports changes
+    let mut files: Vec<std::path::PathBuf> = vec![input_path.as_path().to_path_buf()];
+
+    loop {
+        // imports have to be compiled again too
+        clear_module_cache();
+
+        let built = build(
+            &input,
+            &out_path,
+            registers.clone(),
+            check_only,
+            json,
+            stats,
+            &exec,
+        );
+
+        // keep watching the previous imports if the build failed
+        if let Ok(dependencies) = built {
+            files = std::iter::once(input_path.as_path().to_path_buf())
+                .chain(dependencies)
+                .collect();
+        }
+
+        if !json {
+            println!(
+                "    \x1b[36;1mWatching\x1b[0m \x1b[2m{} file(s) for changes\x1b[0m",
+                files.len()
+            );
+        }
+
+        wait_for_change(&files);
+    }
+}
+
+/// Compile `input` to `out_path` (and run it with `exec`).
+///
+/// # Returns
+/// The files imported by `input`, or `Err` if compiling failed (every error was
+/// already reported).
+fn build(
+    input: &str,
+    out_path: &PathBuf,
+    registers: Registers,
+    check_only: bool,
+    json: bool,
+    stats: bool,
+    exec: &str,
+) -> Result<BTreeSet<std::path::PathBuf>, ()> {
+    let run = exec.starts_with("-r=");
 
     // process
     let start = SystemTime::now();
-    let output = process_file(
-        PathBuf::current().join(&input),
-        Registers::default(),
-        check_only,
-    );
+    let input_path = PathBuf::current().join(input);
+    let output = match fcompiler_recover(|| {
+        process_file_with_stats(input_path.clone(), registers, check_only)
+    }) {
+        Ok(output) => output,
+        // the error was already reported
+        Err(_) => {
+            if json {
+                fcompiler_print_json();
+            }
+
+            return Err(());
+        }
+    };
 
     // finished
     let micros = start.elapsed().unwrap().as_micros();
     let gap = "-".repeat(((micros / 100) as usize) / 2);
+    let failed = output.2.iter().any(|d| d.severity == Severity::Error);
 
-    println!("🦇 \x1b[91m{} end {}\x1b[0m 🦖", gap, gap);
+    if json {
+        // diagnostics are the only output in json mode
+        println!("{}", serde_json::to_string(&output.2).unwrap());
+    } else if !failed {
+        println!("🦇 \x1b[91m{} end {}\x1b[0m 🦖", gap, gap);
+    }
+
+    if failed {
+        // every error was already reported
+        return Err(());
+    }
 
     if check_only {
         // we're not meant to save since we only checked types!
-        std::process::exit(0);
+        return Ok(output.1.dependencies);
     }
 
-    println!(
-        "    \x1b[32;1mFinished\x1b[0m \x1b[2m{input}\x1b[0m in \x1b[1m{}μs ({:.4}s)\x1b[0m",
-        micros,
-        start.elapsed().unwrap().as_secs_f32()
-    );
+    if !json {
+        println!(
+            "    \x1b[32;1mFinished\x1b[0m \x1b[2m{input}\x1b[0m in \x1b[1m{}μs ({:.4}s)\x1b[0m",
+            micros,
+            start.elapsed().unwrap().as_secs_f32()
+        );
+    }
+
+    if stats && !json {
+        let s = &output.3;
+        println!(
+            "       \x1b[32;1mStats\x1b[0m \x1b[2m{} functions, {} types, {} variables, {} lines\x1b[0m",
+            s.functions, s.types, s.variables, s.lines
+        );
+        println!(
+            "             \x1b[2mparse {}μs, signatures {}μs, process {}μs, export {}μs\x1b[0m",
+            s.parse.as_micros(),
+            s.signatures.as_micros(),
+            s.process.as_micros(),
+            s.export.as_micros()
+        );
+    }
 
     // write file
-    write(&out_path, output.0).unwrap();
-    println!("       \x1b[32;1mSaved\x1b[0m \x1b[2m{out_path}\x1b[0m");
+    write(out_path, output.0).unwrap();
+
+    if let Some(map) = take_sourcemap(&input_path.to_string()) {
+        write(format!("{out_path}.map"), map.to_json()).unwrap();
+    }
+
+    if !json {
+        println!("       \x1b[32;1mSaved\x1b[0m \x1b[2m{out_path}\x1b[0m");
+    }
 
     // run
     if run {
         let mut pre_cmd = Command::new(exec.replace("-r=", ""));
-        let cmd = pre_cmd.arg(&out_path.to_string()).current_dir("build");
+        let parent = out_path.as_path().parent().unwrap();
+        let cmd = pre_cmd.arg(&out_path.to_string()).current_dir(parent);
 
         // pretty print cmd
         let mut args: String = String::new();
@@ -86,4 +377,241 @@ fn main() {
         println!("🦇 \x1b[92m{} run {}\x1b[0m 🌑", gap, gap);
         cmd.spawn().unwrap().wait().unwrap();
     }
+
+    Ok(output.1.dependencies)
+}
+
+/// Block until one of `files` is modified (by polling their modification times).
+///
+/// Saving a file can take more than one write, so this only returns once the files
+/// stop changing. Files which can't be read count as changed once they can be.
+fn wait_for_change(files: &[std::path::PathBuf]) {
+    let modified = || -> Vec<Option<SystemTime>> {
+        files
+            .iter()
+            .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
+    };
+
+    let before = modified();
+    while modified() == before {
+        sleep(Duration::from_millis(100));
+    }
+
+    // debounce
+    let mut last = modified();
+    loop {
+        sleep(Duration::from_millis(50));
+        let now = modified();
+
+        if now == last {
+            break;
+        }
+
+        last = now;
+    }
+}
+
+/// Print the parse tree of the file at `path` (`--emit=ast`), or only its tokens
+/// (`--emit=tokens`), without compiling it.
+fn emit_parsed(path: PathBuf, mode: &str) {
+    let source = match std::fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("\x1b[31;1merror:\x1b[0m \x1b[1mfailed to read {path}: {e}\x1b[0m");
+            std::process::exit(1);
+        }
+    };
+
+    let pairs = match FaradayParser::parse(Rule::document, &source) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("\x1b[31;1merror:\x1b[0m \x1b[1m{e}\x1b[0m");
+            std::process::exit(1);
+        }
+    };
+
+    match mode {
+        "ast" => {
+            for pair in pairs {
+                print_pair(&pair, 0);
+            }
+        }
+        "tokens" => {
+            // tokens are the pairs without any inner pairs
+            for pair in pairs.flatten() {
+                if pair.clone().into_inner().next().is_none() {
+                    print_pair(&pair, 0);
+                }
+            }
+        }
+        _ => {
+            println!(
+                "\x1b[31;1merror:\x1b[0m \x1b[1munknown emit mode {mode} (ast, tokens, json)\x1b[0m"
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Check the file at `path` and print its [`Registers`] (every type, function, and
+/// variable) as JSON.
+///
+/// The output is always JSON, so diagnostics are printed as JSON instead if
+/// checking fails.
+fn emit_registers(path: PathBuf, registers: Registers) {
+    *COMPILER_MESSAGE_FORMAT.write().unwrap() = MessageFormat::Json;
+
+    let output = match fcompiler_recover(|| process_file_with_stats(path, registers, true)) {
+        Ok(output) => output,
+        Err(_) => {
+            fcompiler_print_json();
+            std::process::exit(1);
+        }
+    };
+
+    if output.2.iter().any(|d| d.severity == Severity::Error) {
+        println!("{}", serde_json::to_string(&output.2).unwrap());
+        std::process::exit(1);
+    }
+
+    println!("{}", output.1.to_json());
+}
+
+/// Print a pair (and its inner pairs) with its rule and span, indented by `depth`.
+fn print_pair(pair: &Pair<'_, Rule>, depth: usize) {
+    let span = pair.as_span();
+    let (line, col) = span.start_pos().line_col();
+    let (end_line, end_col) = span.end_pos().line_col();
+    let indent = "  ".repeat(depth);
+
+    let inner: Vec<Pair<'_, Rule>> = pair.clone().into_inner().collect();
+
+    if inner.is_empty() {
+        println!(
+            "{indent}{:?} \x1b[2m{line}:{col} to {end_line}:{end_col}\x1b[0m {:?}",
+            pair.as_rule(),
+            pair.as_str()
+        );
+        return;
+    }
+
+    println!(
+        "{indent}{:?} \x1b[2m{line}:{col} to {end_line}:{end_col}\x1b[0m",
+        pair.as_rule()
+    );
+
+    for pair in inner {
+        print_pair(&pair, depth + 1);
+    }
+}
+
+/// Compile the given LLVM IR to a binary (`build/main`) with clang and run it.
+///
+/// Clang can be set with the `FARADAY_CLANG` environment variable.
+fn run_ir(ir: String) {
+    let clang = std::env::var("FARADAY_CLANG").unwrap_or("clang".to_string());
+
+    let build = PathBuf::current().join("build");
+    std::fs::create_dir_all(&build).unwrap();
+
+    let ir_path = build.join("main.ll");
+    let bin_path = build.join("main");
+    write(&ir_path, ir).unwrap();
+
+    // compile (clang also links against libc, which is where the bindings come from)
+    println!("   \x1b[32;1mCompiling\x1b[0m \x1b[2m{clang} {ir_path} -o {bin_path}\x1b[0m");
+
+    let status = match Command::new(&clang)
+        .arg(ir_path.to_string())
+        .arg("-o")
+        .arg(bin_path.to_string())
+        .status()
+    {
+        Ok(s) => s,
+        Err(e) => {
+            println!("\x1b[31;1merror:\x1b[0m \x1b[1mfailed to run {clang}: {e}\x1b[0m");
+            std::process::exit(1);
+        }
+    };
+
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+
+    // run
+    println!("     \x1b[32;1mRunning\x1b[0m \x1b[2m{bin_path}\x1b[0m");
+
+    let status = Command::new(bin_path.to_string()).status().unwrap();
+
+    println!(
+        "      \x1b[32;1mExited\x1b[0m \x1b[2mwith code {}\x1b[0m",
+        status.code().unwrap_or(-1)
+    );
+}
+
+/// Read statements from stdin, compile them, and run everything compiled so far
+/// with `luajit` (printing only the new output).
+///
+/// Errors are reported without ending the session.
+fn repl(mut registers: Registers) {
+    // removed once the session ends
+    let temp_path = compiler::tempfile::TempFile::create();
+    let mut source: String = String::new();
+    let mut seen_output: usize = 0;
+
+    let mut input: String = String::new();
+    let mut lines = std::io::stdin().lock().lines();
+
+    loop {
+        print!("{}", if input.is_empty() { "fd> " } else { "..> " });
+        std::io::stdout().flush().unwrap();
+
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+
+        input.push_str(&line);
+        input.push('\n');
+
+        if input.matches('{').count() > input.matches('}').count() {
+            // keep reading until the block is closed
+            continue;
+        }
+
+        let statement = std::mem::take(&mut input);
+        let compiled = fcompiler_recover(|| process_string(&statement, registers.clone()));
+
+        // the error was already reported, keep the previous state
+        let Ok((lua, new_registers)) = compiled else {
+            continue;
+        };
+
+        let previous = std::mem::replace(&mut registers, new_registers);
+        source.push_str(&lua);
+        source.push('\n');
+
+        // run
+        write(&*temp_path, &source).unwrap();
+
+        let output = match Command::new("luajit").arg(temp_path.to_string()).output() {
+            Ok(o) => o,
+            Err(e) => {
+                println!("\x1b[31;1merror:\x1b[0m \x1b[1mfailed to run luajit: {e}\x1b[0m");
+                continue;
+            }
+        };
+
+        if !output.status.success() {
+            // the statement fails at runtime, forget it
+            print!("{}", String::from_utf8_lossy(&output.stderr));
+            source.truncate(source.len() - lua.len() - 1);
+            registers = previous;
+            continue;
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        print!("{}", stdout.get(seen_output..).unwrap_or_default());
+        seen_output = stdout.len();
+    }
 }
@@ -1,4 +1,4 @@
-pub use pest::{Parser, iterators::Pair, iterators::Pairs};
+pub use pest::{Parser, error::Error, iterators::Pair, iterators::Pairs};
 use pest_derive::Parser;
 
 #[derive(Parser)]
@@ -15,6 +15,8 @@ pub struct Registers {
     pub sections: HashMap<String, Section>,
     pub functions: HashMap<String, Function>,
     pub extra_header_ir: String,
+    /// The label and end block of each loop enclosing the current block (innermost last).
+    pub loops: Vec<(Option<String>, String)>,
 }
 
 macro_rules! llvm_function {
@@ -49,6 +51,7 @@ impl Default for Registers {
                 out
             },
             extra_header_ir: String::new(),
+            loops: Vec::new(),
         }
     }
 }
@@ -172,104 +175,123 @@ pub enum Operation {
 impl ToIr for Operation {
     fn transform(&self, registers: &mut Registers) -> (String, String) {
         use Operation::*;
+        // every variant must be listed here, don't add a wildcard arm
         match self {
-            Assign(ident) => {
-                let var = registers.get_var(&ident);
-                if var.r#type == "string" {
-                    return (
-                        format!(
-                            "@.s_{}_{} = constant [{} x i8] c\"{}\\00\\00\", align 1",
-                            var.label,
-                            var.key,
-                            var.size,
-                            {
-                                let mut val = var.value.clone();
-                                val.remove(0);
-                                val.remove(val.len() - 1);
-                                val
-                            }
-                        ),
-                        format!(
-                            "%{}.addr = getelementptr [{} x i8],[{} x i8]* @.s_{}_{}, i64 0, i64 0",
-                            var.label, var.size, var.size, var.label, var.key
-                        ),
-                    );
-                } else if var.r#type == "faraday::no_alloca" {
-                    return (String::new(), format!("%{} = {}", var.label, var.value));
-                }
-
-                // read: %{ident} = load {type}, ptr %p_ident, align 4
-                (
-                    String::new(),
-                    format!(
-                        "%{}.addr = alloca [{} x {}], align {}",
-                        var.label, var.size, var.r#type, var.align
-                    ),
-                )
-            }
+            Assign(ident) => Operation::assign_ir(ident, registers),
             Section(ident) => registers
-                .get_section(&ident)
+                .get_section(ident)
                 .transform(&mut clone_registers!(registers; Registers)),
             Function(ident) => registers
-                .get_function(&ident)
+                .get_function(ident)
                 .transform(&mut clone_registers!(registers; Registers)),
             Jump(ident) => (String::new(), format!("br label %{ident}")),
-            Pipe((label, ident, value)) => {
-                let var = registers.get_var_mut(label);
-                var.value = value.to_owned();
-
-                let mut val: String = String::new();
-                (
-                    if var.r#type == "string" {
-                        icompiler_error!("cannot reassign string values (constant)")
-                    } else {
-                        if val.is_empty() {
-                            val = var.value.clone();
-                        }
-
-                        String::new()
-                    },
-                    if !var.prefix.is_empty() {
-                        // call
-                        format!(
-                            "{}store {} %k_{}, ptr %{ident}.addr, align 4",
-                            // store ptr %{ident}, ptr %k_{}, align 4",
-                            var.prefix,
-                            var.r#type,
-                            var.key,
-                            // var.key
-                        )
-                        .replace("__VALUE_INSTEAD", &val)
-                    } else {
-                        // simple expression
-                        format!("store {} {val}, ptr %{ident}.addr, align 4", var.r#type)
-                    },
-                )
-            }
-            Call((ident, args_string)) => {
-                let fun = registers.get_function(&ident);
-                (
-                    String::new(),
-                    format!("call {} @{ident}({args_string})", fun.ret_type),
-                )
-            }
+            Pipe((label, ident, value)) => Operation::pipe_ir(label, ident, value, registers),
+            Call((ident, args_string)) => Operation::call_ir(ident, args_string, registers),
             Ir(data) => (String::new(), data.trim().to_owned()),
             HeadIr(data) => (data.trim().to_owned(), String::new()),
-            Read(ident) => {
-                let var = registers.get_var_mut(ident);
-
-                (
-                    String::new(),
-                    format!(
-                        "%{} = load {}, ptr %{}.addr, align 4",
-                        var.label, var.r#type, var.label
-                    ),
-                )
-            }
+            Read(ident) => Operation::read_ir(ident, registers),
         }
     }
 }
 
+impl Operation {
+    /// IR for [`Operation::Assign`].
+    pub fn assign_ir(ident: &str, registers: &Registers) -> (String, String) {
+        let var = registers.get_var(ident);
+        if var.r#type == "string" {
+            return (
+                format!(
+                    "@.s_{}_{} = constant [{} x i8] c\"{}\\00\\00\", align 1",
+                    var.label,
+                    var.key,
+                    var.size,
+                    {
+                        let mut val = var.value.clone();
+                        val.remove(0);
+                        val.remove(val.len() - 1);
+                        val
+                    }
+                ),
+                format!(
+                    "%{}.addr = getelementptr [{} x i8],[{} x i8]* @.s_{}_{}, i64 0, i64 0",
+                    var.label, var.size, var.size, var.label, var.key
+                ),
+            );
+        } else if var.r#type == "faraday::no_alloca" {
+            return (String::new(), format!("%{} = {}", var.label, var.value));
+        }
+
+        // read: %{ident} = load {type}, ptr %p_ident, align 4
+        (
+            String::new(),
+            format!(
+                "%{}.addr = alloca [{} x {}], align {}",
+                var.label, var.size, var.r#type, var.align
+            ),
+        )
+    }
+
+    /// IR for [`Operation::Pipe`].
+    pub fn pipe_ir(
+        label: &str,
+        ident: &str,
+        value: &str,
+        registers: &mut Registers,
+    ) -> (String, String) {
+        let var = registers.get_var_mut(label);
+        var.value = value.to_owned();
+
+        let mut val: String = String::new();
+        (
+            if var.r#type == "string" {
+                icompiler_error!("cannot reassign string values (constant)")
+            } else {
+                if val.is_empty() {
+                    val = var.value.clone();
+                }
+
+                String::new()
+            },
+            if !var.prefix.is_empty() {
+                // call
+                format!(
+                    "{}store {} %k_{}, ptr %{ident}.addr, align {}",
+                    var.prefix, var.r#type, var.key, var.align
+                )
+                .replace("__VALUE_INSTEAD", &val)
+            } else {
+                // simple expression
+                format!(
+                    "store {} {val}, ptr %{ident}.addr, align {}",
+                    var.r#type, var.align
+                )
+            },
+        )
+    }
+
+    /// IR for [`Operation::Call`].
+    pub fn call_ir(ident: &str, args_string: &str, registers: &Registers) -> (String, String) {
+        let fun = registers.get_function(ident);
+        (
+            String::new(),
+            format!("call {} @{ident}({args_string})", fun.ret_type),
+        )
+    }
+
+    /// IR for [`Operation::Read`].
+    pub fn read_ir(ident: &str, registers: &mut Registers) -> (String, String) {
+        let var = registers.get_var_mut(ident);
+
+        (
+            String::new(),
+            format!(
+                "%{} = load {}, ptr %{}.addr, align {}",
+                var.label, var.r#type, var.label, var.align
+            ),
+        )
+    }
+}
+
 /// A section is a grouping of execution steps.
 #[derive(Clone)]
 pub struct Section {
@@ -367,13 +389,13 @@ impl From<&str> for Variable {
     fn from(value: &str) -> Self {
         Self {
             prefix: String::new(),
-            label: crate::random(),
+            label: crate::unique(),
             ident: value.to_string(),
             size: 0,
             align: 4,
             value: value.to_string(),
             r#type: "void".to_string(),
-            key: crate::random(),
+            key: crate::unique(),
         }
     }
 }
@@ -6,18 +6,123 @@ use crate::{
     data::{Function, Operation, Registers, Variable},
     icompiler_error,
     parser::{Pair, Rule},
-    random,
+    unique,
 };
+use std::iter::Peekable;
 
 /// Get a LLVM IR type from the given [`Rule`].
 pub fn rule_to_type<'a>(rule: Rule) -> &'a str {
     match rule {
         Rule::integer => "i32",
+        Rule::hex => "i32",
+        Rule::float => "double",
         _ => "void",
     }
 }
 
+/// Check if the given LLVM IR type is a floating-point type.
+pub fn is_float_type(r#type: &str) -> bool {
+    matches!(r#type, "half" | "float" | "double")
+}
+
+/// Check if the given LLVM IR type is an integer type (`i1` to `i64`).
+pub fn is_int_type(r#type: &str) -> bool {
+    matches!(r#type, "i1" | "i8" | "i16" | "i32" | "i64")
+}
+
+/// Get the natural alignment (in bytes) of the given LLVM IR type.
+pub fn type_align(r#type: &str) -> Option<i32> {
+    match r#type {
+        "i1" | "i8" => Some(1),
+        "i16" | "half" => Some(2),
+        "i32" | "float" => Some(4),
+        "i64" | "double" | "ptr" => Some(8),
+        _ => None,
+    }
+}
+
+/// Translate a float (or integer) literal into its LLVM IR form, as a constant of the
+/// given float type.
+pub fn float_literal(raw: &str, r#type: &str) -> String {
+    let raw = raw.replace("_", "");
+
+    // literals which were already translated (as a `double`) are read back from their bits
+    let value = match raw.strip_prefix("0x") {
+        Some(bits) => match u64::from_str_radix(bits, 16) {
+            Ok(v) => f64::from_bits(v),
+            Err(e) => icompiler_error!("invalid float literal {raw}: {e}"),
+        },
+        None => match raw.parse::<f64>() {
+            Ok(v) => v,
+            Err(e) => icompiler_error!("invalid float literal {raw}: {e}"),
+        },
+    };
+
+    // LLVM only accepts decimal floats which are exactly representable, so we always
+    // use the hexadecimal form (which is a double, even for smaller types, so the value
+    // has to be rounded to the precision of the type first)
+    let value = match r#type {
+        "float" => (value as f32) as f64,
+        "half" => round_to_half(value as f32) as f64,
+        _ => value,
+    };
+
+    format!("0x{:016X}", value.to_bits())
+}
+
+/// Round the mantissa of the given float to the 10 bits of a `half` (ties to even).
+///
+/// Values outside of the range of a `half` are left for LLVM to reject.
+fn round_to_half(value: f32) -> f32 {
+    let bits = value.to_bits();
+    let lowest_kept = (bits >> 13) & 1;
+    f32::from_bits(bits.wrapping_add(0x0FFF + lowest_kept) & !0x1FFF)
+}
+
+/// Translate a numeric literal into its LLVM IR form.
+///
+/// # Returns
+/// `(value, type, size)`, or `None` if the pair isn't a float or hex literal.
+pub fn numeric_literal<'a>(pair: &Pair<'_, Rule>) -> Option<(String, &'a str, usize)> {
+    let raw = pair.as_str().replace("_", "");
+
+    match pair.as_rule() {
+        Rule::float => Some((float_literal(&raw, "double"), "double", 8)),
+        Rule::hex => {
+            // LLVM integers must be decimal
+            let negative = raw.starts_with("-");
+            let digits = raw.trim_start_matches(['+', '-']).trim_start_matches("0x");
+
+            let value = match i64::from_str_radix(digits, 16) {
+                Ok(v) => v,
+                Err(e) => icompiler_error!("invalid hex literal {raw}: {e}"),
+            };
+
+            let value = if negative { -value } else { value };
+
+            if i32::try_from(value).is_ok() {
+                Some((value.to_string(), "i32", 4))
+            } else {
+                Some((value.to_string(), "i64", 8))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Get the source of an argument of a built-in call (numeric literals are translated).
+fn argument_source(pair: Pair<'_, Rule>) -> String {
+    let value = pair.clone().into_inner().next().unwrap_or(pair);
+
+    match numeric_literal(&value) {
+        Some((value, _, _)) => value,
+        None => value.as_str().to_string(),
+    }
+}
+
 /// Get a LLVM IR operator for [`icmp`](https://llvm.org/docs/LangRef.html#icmp-instruction) from the given [`Rule`].
+///
+/// `&&` and `||` aren't `icmp` predicates, see [`comparison`].
 pub fn rule_to_operator<'a>(rule: Rule) -> &'a str {
     match rule {
         Rule::GREATER_THAN => "sgt",
@@ -26,12 +131,147 @@ pub fn rule_to_operator<'a>(rule: Rule) -> &'a str {
         Rule::LESS_THAN_EQUAL_TO => "sle",
         Rule::NOT_EQUAL => "ne",
         Rule::EQUAL => "eq",
-        Rule::OR => "or",
-        Rule::AND => "and",
         _ => "void",
     }
 }
 
+/// Get a LLVM IR operator for [`fcmp`](https://llvm.org/docs/LangRef.html#fcmp-instruction) from the given [`Rule`].
+pub fn rule_to_float_operator<'a>(rule: Rule) -> &'a str {
+    match rule {
+        Rule::GREATER_THAN => "ogt",
+        Rule::LESS_THAN => "olt",
+        Rule::GREATER_THAN_EQUAL_TO => "oge",
+        Rule::LESS_THAN_EQUAL_TO => "ole",
+        Rule::NOT_EQUAL => "one",
+        Rule::EQUAL => "oeq",
+        _ => "void",
+    }
+}
+
+/// Get an operand of a comparison (variables are loaded from their address).
+///
+/// # Returns
+/// `(value, type)`, where `type` is the type of the variable (or `i32`/`double` for literals).
+fn comparison_operand(
+    pair: Pair<'_, Rule>,
+    registers: &Registers,
+    operations: &mut Vec<Operation>,
+) -> (String, String) {
+    match pair.as_rule() {
+        Rule::identifier => {
+            let r = unique();
+            let var = registers.get_var(pair.as_str());
+            let r#type = if is_float_type(&var.r#type) || is_int_type(&var.r#type) {
+                var.r#type.clone()
+            } else {
+                "i32".to_string()
+            };
+
+            operations.push(Operation::Ir(format!(
+                "%k_{r} = load {type}, ptr %{}.addr, align {}",
+                var.label, var.align
+            )));
+            (format!("%k_{r}"), r#type)
+        }
+        Rule::float | Rule::hex => {
+            let (value, r#type, _) = numeric_literal(&pair).unwrap();
+            (value, r#type.to_string())
+        }
+        Rule::ordered_comparison => {
+            icompiler_error!("comparisons can only be combined with && and ||")
+        }
+        _ => (pair.as_str().to_string(), "i32".to_string()),
+    }
+}
+
+/// Get an operand of a comparison as the given float type (integer literals are converted).
+fn float_operand((value, r#type): (String, String), float_type: &str) -> String {
+    if r#type == float_type {
+        value
+    } else if !value.starts_with("%") {
+        float_literal(&value, float_type)
+    } else {
+        icompiler_error!("cannot compare {type} with {float_type}")
+    }
+}
+
+/// [`Operation`] generation for a comparison.
+///
+/// `&&` and `||` combine the `i1` results of their sides with `and`/`or` (both sides
+/// are always evaluated). Sides which aren't comparisons are true if they aren't 0.
+///
+/// # Returns
+/// The name of the `i1` result.
+pub fn comparison(
+    pair: Pair<'_, Rule>,
+    registers: &Registers,
+    operations: &mut Vec<Operation>,
+) -> String {
+    let mut inner = pair.into_inner();
+    let lhs = inner.next().unwrap();
+    let op = inner.next().unwrap().as_rule();
+    let rhs = inner.next().unwrap();
+
+    let result = format!("%k_cmp_{}", unique());
+
+    if matches!(op, Rule::AND | Rule::OR) {
+        let mut side = |pair: Pair<'_, Rule>| match pair.as_rule() {
+            Rule::ordered_comparison => {
+                comparison(pair.into_inner().next().unwrap(), registers, operations)
+            }
+            _ => {
+                let (value, r#type) = comparison_operand(pair, registers, operations);
+                let r = unique();
+                operations.push(Operation::Ir(if is_float_type(&r#type) {
+                    format!("%k_cmp_{r} = fcmp one {type} {value}, 0.0")
+                } else {
+                    format!("%k_cmp_{r} = icmp ne {type} {value}, 0")
+                }));
+                format!("%k_cmp_{r}")
+            }
+        };
+
+        let lhs = side(lhs);
+        let rhs = side(rhs);
+        let op = if op == Rule::AND { "and" } else { "or" };
+        operations.push(Operation::Ir(format!("{result} = {op} i1 {lhs}, {rhs}")));
+    } else {
+        let lhs = comparison_operand(lhs, registers, operations);
+        let rhs = comparison_operand(rhs, registers, operations);
+
+        let float_type = [&lhs.1, &rhs.1]
+            .into_iter()
+            .find(|t| is_float_type(t))
+            .cloned();
+
+        if let Some(r#type) = float_type {
+            let lhs = float_operand(lhs, &r#type);
+            let rhs = float_operand(rhs, &r#type);
+            let op = rule_to_float_operator(op);
+            operations.push(Operation::Ir(format!(
+                "{result} = fcmp {op} {type} {lhs}, {rhs}"
+            )));
+        } else {
+            // literals take the type of the variable they're compared with
+            let r#type = match (lhs.0.starts_with("%"), rhs.0.starts_with("%")) {
+                (true, true) if lhs.1 != rhs.1 => {
+                    icompiler_error!("cannot compare {} with {}", lhs.1, rhs.1)
+                }
+                (_, true) => &rhs.1,
+                _ => &lhs.1,
+            };
+
+            let op = rule_to_operator(op);
+            operations.push(Operation::Ir(format!(
+                "{result} = icmp {op} {type} {}, {}",
+                lhs.0, rhs.0
+            )));
+        }
+    }
+
+    result
+}
+
 /// [`Operation`] generation for raw LLVM IR blocks.
 pub fn llvm_ir<'a>(mut input: ParserPairs<'a>) -> Operation {
     let mut raw = input.next().unwrap().as_str().to_string();
@@ -42,6 +282,45 @@ pub fn llvm_ir<'a>(mut input: ParserPairs<'a>) -> Operation {
     Operation::Ir(raw)
 }
 
+/// [`Operation`] generation for external function declarations.
+///
+/// The function is registered (so it can be called) and a `declare` line is added to the head.
+pub fn extern_function<'a>(
+    pair: Pair<'a, Rule>,
+    operations: &mut Vec<Operation>,
+    registers: &mut Registers,
+) {
+    let mut inner = pair.into_inner();
+
+    let ret_type = inner.next().unwrap().as_str().to_string();
+    let ident = inner.next().unwrap().as_str().to_string();
+
+    if registers.functions.contains_key(&ident) {
+        icompiler_error!("function is already declared: {ident}")
+    }
+
+    // variable names are their arg index
+    let args: Vec<(String, String, String)> = inner
+        .enumerate()
+        .map(|(i, pair)| (pair.as_str().to_string(), i.to_string(), unique()))
+        .collect();
+
+    operations.push(Operation::HeadIr(format!(
+        "declare {ret_type} @{ident}({}) nounwind",
+        args.iter()
+            .map(|(t, _, _)| t.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    )));
+
+    registers.functions.insert(ident.clone(), Function {
+        ident,
+        ret_type,
+        args,
+        operations: Vec::new(),
+    });
+}
+
 /// [`Operation`] generation for function calls.
 pub fn fn_call<'a>(
     ident: String,
@@ -98,7 +377,7 @@ pub fn fn_call<'a>(
                             let content = inner.next().unwrap().as_str();
                             let size = inner.next().unwrap().into_inner().next().unwrap().as_str();
 
-                            let name = random();
+                            let name = unique();
                             regs.extra_header_ir.push_str(&format!(
                                 "@.s_{name} = constant [{size} x i8] c\"{content}\\00\\00\", align 1\n",
                             ));
@@ -153,7 +432,7 @@ pub fn root_function_call<'a>(
             operations.push(Operation::Jump(section_name.to_string()));
         }
         // decay: create C array decay from variable
-        // variable should be `alloca [100 * i8]`
+        // variable should be an array (e.g. `alloca [100 x i8]`)
         "decay" => {
             let ident = inner.next().unwrap().as_str();
             let var = registers.get_var(ident);
@@ -164,16 +443,16 @@ pub fn root_function_call<'a>(
                     prefix: String::new(),
                     label: format!("{ident}.decay"),
                     ident: format!("{ident}.decay"),
-                    size: 100,
-                    align: 16,
+                    size: var.size,
+                    align: var.align,
                     value: String::new(),
                     r#type: "ptr".to_string(),
-                    key: random(),
+                    key: unique(),
                 });
 
             operations.push(Operation::Ir(format!(
-                "%k_{ident}.decay = getelementptr inbounds [100 x i8], ptr %{}.addr, i64 0, i64 0",
-                var.label
+                "%k_{ident}.decay = getelementptr inbounds [{} x {}], ptr %{}.addr, i64 0, i64 0",
+                var.size, var.r#type, var.label
             )));
         }
         // awrite: write to an array variable
@@ -191,10 +470,7 @@ pub fn root_function_call<'a>(
 
             // get value
             inner.next();
-            let value = inner.next().unwrap();
-            let value = match value.as_rule() {
-                _ => value.as_str().to_string(),
-            };
+            let value = argument_source(inner.next().unwrap());
 
             // build index pointers
             let mut index_access_ir = String::new();
@@ -209,7 +485,7 @@ pub fn root_function_call<'a>(
 
                 let idx = pair.as_str();
                 indexes_suffix_string.push_str(&format!(".{idx}")); // this keeps the variable naming predictable
-                last_index_variable = random();
+                last_index_variable = unique();
 
                 index_access_ir.push_str(&format!("%arridx_{last_index_variable} = getelementptr inbounds [{idx} x {type}], ptr %{}.addr, i64 0, i64 {idx}", var.label));
             }
@@ -247,7 +523,7 @@ pub fn root_function_call<'a>(
 
                 let idx = pair.as_str();
                 indexes_suffix_string.push_str(&format!(".{idx}")); // this keeps the variable naming predictable
-                last_index_variable = random();
+                last_index_variable = unique();
 
                 index_access_ir.push_str(&format!("%arridx_{last_index_variable} = getelementptr inbounds [{idx} x {}], ptr %{var_ident}.addr, i64 0, i64 {idx}", var.r#type));
             }
@@ -273,8 +549,8 @@ pub fn root_function_call<'a>(
             let bind_var: Variable = bind_as_name.into();
 
             operations.push(Operation::Ir(format!(
-                "%k_{} = load {}, ptr %{}.addr, align 4",
-                bind_var.label, var.r#type, var.label
+                "%k_{} = load {}, ptr %{}.addr, align {}",
+                bind_var.label, var.r#type, var.label, var.align
             )));
 
             registers
@@ -283,7 +559,7 @@ pub fn root_function_call<'a>(
         }
         // if: compare 2 values
         "if" => {
-            let mut conditional_inner = inner
+            let condition = inner
                 .next()
                 .unwrap()
                 .into_inner()
@@ -291,38 +567,9 @@ pub fn root_function_call<'a>(
                 .unwrap()
                 .into_inner()
                 .next()
-                .unwrap()
-                .into_inner();
-
-            let lhs = conditional_inner.next().unwrap();
-            let lhs = match lhs.as_rule() {
-                Rule::identifier => {
-                    let r = random();
-                    let var = registers.get_var(lhs.as_str());
-                    operations.push(Operation::Ir(format!(
-                        "%k_{r} = load i32, ptr %{}.addr, align 4",
-                        var.label
-                    )));
-                    format!("%k_{r}")
-                }
-                _ => lhs.as_str().to_string(),
-            };
+                .unwrap();
 
-            let op = rule_to_operator(conditional_inner.next().unwrap().as_rule());
-
-            let rhs = conditional_inner.next().unwrap();
-            let rhs = match rhs.as_rule() {
-                Rule::identifier => {
-                    let r = random();
-                    let var = registers.get_var(rhs.as_str());
-                    operations.push(Operation::Ir(format!(
-                        "%k_{r} = load i32, ptr %{}.addr, align 4",
-                        var.label
-                    )));
-                    format!("%k_{r}")
-                }
-                _ => rhs.as_str().to_string(),
-            };
+            let condition = comparison(condition, registers, operations);
 
             inner.next(); // skip
             let goto = inner.next().unwrap().as_str();
@@ -330,32 +577,54 @@ pub fn root_function_call<'a>(
                 // ^ skip
                 let goto_next = inner.next().unwrap().as_str();
                 // has else block
-                let r = random();
                 operations.push(Operation::Ir(format!(
-                    "%k_cmp_{r} = icmp {op} i32 {lhs}, {rhs}\nbr i1 %k_cmp_{r}, label %{goto}, label %{goto_next}"
+                    "br i1 {condition}, label %{goto}, label %{goto_next}"
                 )));
             } else {
-                // doesn't have else block
-                let r = random();
+                // doesn't have else block, continue right after the `if` when false
+                let goto_next = format!("k_if_next_{}", unique());
                 operations.push(Operation::Ir(format!(
-                    "%k_cmp_{r} = icmp {op} i32 {lhs}, {rhs}\nbr i1 %k_cmp_{r}, label %{goto}"
+                    "br i1 {condition}, label %{goto}, label %{goto_next}\n{goto_next}:"
                 )));
             }
         }
-        // addset: add `x` to `ident` and update its value
-        "addset" => {
+        // addset/subset/mulset/divset/modset: apply `x` to `ident` and update its value
+        "addset" | "subset" | "mulset" | "divset" | "modset" => {
             let var_ident = inner.next().unwrap().as_str();
             let var = registers.get_var(var_ident);
 
             inner.next(); // skip
-            let val = inner.next().unwrap().as_str();
+            let val = argument_source(inner.next().unwrap());
+
+            // floats need `fadd` (and a float value)
+            let (r#type, op, val) = if is_float_type(&var.r#type) {
+                let op = match sub_function {
+                    "addset" => "fadd",
+                    "subset" => "fsub",
+                    "mulset" => "fmul",
+                    "divset" => "fdiv",
+                    _ => "frem",
+                };
+
+                (var.r#type.as_str(), op, float_literal(&val, &var.r#type))
+            } else {
+                let op = match sub_function {
+                    "addset" => "add nsw",
+                    "subset" => "sub nsw",
+                    "mulset" => "mul nsw",
+                    "divset" => "sdiv",
+                    _ => "srem",
+                };
+
+                (var.r#type.as_str(), op, val)
+            };
 
-            let r = random();
+            let r = unique();
             operations.push(Operation::Ir(format!(
-                "%k_{r}_v = load i32, ptr %{}.addr
-%k_{r} = add nsw i32 %k_{r}_v, {val}
-store i32 %k_{r}, ptr %{}.addr, align {}",
-                var.label, var.label, var.align
+                "%k_{r}_v = load {type}, ptr %{}.addr, align {}
+%k_{r} = {op} {type} %k_{r}_v, {val}
+store {type} %k_{r}, ptr %{}.addr, align {}",
+                var.label, var.align, var.label, var.align
             )));
         }
         // everything user-defined
@@ -384,10 +653,16 @@ pub fn fn_return<'a>(pair: Pair<'a, Rule>, regs: &Registers) -> String {
                     let var = regs.get_var(value.as_str());
                     format!("%k_{}", var.label)
                 }
-                _ => {
-                    r#type = rule_to_type(value.as_rule());
-                    value.as_str().to_string()
-                }
+                _ => match numeric_literal(&value) {
+                    Some((literal, literal_type, _)) => {
+                        r#type = literal_type;
+                        literal
+                    }
+                    None => {
+                        r#type = rule_to_type(value.as_rule());
+                        value.as_str().to_string()
+                    }
+                },
             };
 
             if let Some(pair) = inner.next() {
@@ -405,8 +680,10 @@ pub fn fn_return<'a>(pair: Pair<'a, Rule>, regs: &Registers) -> String {
 
 /// A value.
 ///
-/// `(value, prefix, size)`
-pub struct Value(pub (String, String, usize));
+/// `(value, prefix, size, type)`
+///
+/// `type` is only known for literals, and is empty otherwise.
+pub struct Value(pub (String, String, usize, String));
 
 impl Value {
     pub fn get<'a>(pair: Pair<'a, Rule>, key: &str, registers: &mut Registers) -> Self {
@@ -422,28 +699,50 @@ impl Value {
                     .1;
 
                 let size = value.len();
-                return Value((value, format!("%k_{key} = __VALUE_INSTEAD\n"), size));
+                return Value((
+                    value,
+                    format!("%k_{key} = __VALUE_INSTEAD\n"),
+                    size,
+                    String::new(),
+                ));
             }
             Rule::identifier => {
                 if pair.as_str() != "void" {
                     let var = registers.get_var(pair.as_str());
-                    return Value((format!("%{}", var.label), String::new(), var.size));
+                    return Value((
+                        format!("%{}", var.label),
+                        String::new(),
+                        var.size,
+                        String::new(),
+                    ));
                 } else {
                     let value = pair.as_str();
-                    return Value((value.to_string(), String::new(), value.len()));
+                    return Value((value.to_string(), String::new(), value.len(), String::new()));
                 }
             }
             Rule::llvm_ir => match llvm_ir(pair.into_inner()) {
                 Operation::Ir(data) => {
                     let size = data.len();
-                    return Value((data, String::new(), size));
+                    return Value((data, String::new(), size, String::new()));
                 }
                 _ => unreachable!(),
             },
+            Rule::float | Rule::hex => {
+                let (value, r#type, size) = numeric_literal(&pair).unwrap();
+                Value((value, String::new(), size, r#type.to_string()))
+            }
             _ => {
                 let value = pair.as_str().to_string();
                 let size = std::mem::size_of_val(value.as_bytes());
-                return Value((value, String::new(), size));
+                return Value((
+                    value,
+                    String::new(),
+                    size,
+                    match rule {
+                        Rule::integer => rule_to_type(rule).to_string(),
+                        _ => String::new(),
+                    },
+                ));
             }
         }
     }
@@ -462,10 +761,11 @@ pub fn var_assign(
     let mut ident: String = overwrite_ident.clone(); // written to ir
     let mut r#type: String = String::new();
     let mut size: usize = 0;
-    let mut align: i32 = 4;
+    let mut align: Option<i32> = None;
     let mut closed_size: bool = false;
     let mut value: String = String::new();
-    let key: String = random();
+    let mut value_type: String = String::new();
+    let key: String = unique();
 
     while let Some(pair) = inner.next() {
         let rule = pair.as_rule();
@@ -474,6 +774,7 @@ pub fn var_assign(
                 let val = Value::get(pair, &key, registers).0;
                 value = val.0;
                 prefix = val.1;
+                value_type = val.3;
 
                 if !closed_size {
                     size = val.2;
@@ -487,19 +788,20 @@ pub fn var_assign(
             }
             Rule::pair_alignment => {
                 let mut inner = pair.into_inner();
-                align = inner.next().unwrap().as_str().parse::<i32>().unwrap();
+                align = Some(inner.next().unwrap().as_str().parse::<i32>().unwrap());
             }
             Rule::identifier => {
                 if ident.is_empty() {
                     let content = pair.as_str();
                     label = content.to_string();
-                    ident = format!("k_{}", random()) // use random variable name by default for avoid collision
+                    ident = format!("k_{}", unique()) // use random variable name by default for avoid collision
                 } else if label.is_empty() {
                     label = pair.as_str().to_string();
                 } else {
                     let val = Value::get(pair, &key, registers).0;
                     value = val.0;
                     prefix = val.1;
+                    value_type = val.3;
 
                     if !closed_size {
                         size = val.2;
@@ -510,6 +812,7 @@ pub fn var_assign(
                 let val = Value::get(pair, &key, registers).0;
                 value = val.0;
                 prefix = val.1;
+                value_type = val.3;
 
                 if !closed_size {
                     size = val.2;
@@ -523,6 +826,7 @@ pub fn var_assign(
                 let val = Value::get(pair, &key, registers).0;
                 value = val.0;
                 prefix = val.1;
+                value_type = val.3;
 
                 if !closed_size {
                     size = val.2;
@@ -531,6 +835,18 @@ pub fn var_assign(
         }
     }
 
+    if r#type.is_empty() {
+        // no type annotation, use the type of the value
+        r#type = value_type;
+    } else if is_float_type(&r#type) && matches!(value_type.as_str(), "i32" | "double") {
+        // literal assigned to a float (integers are converted, and doubles are
+        // rounded to the precision of the type)
+        value = float_literal(&value, &r#type);
+    }
+
+    // an explicit alignment wins, otherwise use the alignment of the type
+    let align = align.unwrap_or_else(|| type_align(&r#type).unwrap_or(4));
+
     registers.variables.insert(label.clone(), Variable {
         prefix: if prefix == "_drop" {
             String::new()
@@ -573,7 +889,7 @@ pub fn var_assign_no_alloca(
         match rule {
             Rule::identifier => {
                 if ident.is_empty() {
-                    ident = format!("k_{}", random());
+                    ident = format!("k_{}", unique());
                     label = pair.as_str().to_string()
                 } else {
                     value = pair.as_str().to_string()
@@ -597,7 +913,7 @@ pub fn var_assign_no_alloca(
         align: 4,
         value: value.clone(),
         r#type: "faraday::no_alloca".to_string(),
-        key: random(),
+        key: unique(),
     });
 
     registers
@@ -607,6 +923,41 @@ pub fn var_assign_no_alloca(
     operations.push(Operation::Assign(label.clone()));
 }
 
+/// Take the `'label:` of a loop (without the `'`), if it has one.
+fn loop_label(inner: &mut Peekable<ParserPairs>) -> Option<String> {
+    inner
+        .next_if(|pair| pair.as_rule() == Rule::loop_label)
+        .map(|pair| pair.as_str()[1..].to_string())
+}
+
+/// [`Operation`] generation for a `break` (or `break 'label`).
+pub fn loop_break<'a>(pair: Pair<'a, Rule>, registers: &Registers) -> Operation {
+    let label = pair
+        .into_inner()
+        .next()
+        .map(|pair| pair.as_str()[1..].to_string());
+
+    let block_end = match label {
+        Some(ref label) => registers
+            .loops
+            .iter()
+            .rev()
+            .find(|(l, _)| l.as_ref() == Some(label)),
+        None => registers.loops.last(),
+    };
+
+    let Some((_, block_end)) = block_end else {
+        match label {
+            Some(label) => icompiler_error!("no enclosing loop has the label: '{label}"),
+            None => icompiler_error!("cannot break outside of a loop"),
+        }
+    };
+
+    // anything after the break in this block is unreachable, but it still
+    // needs to be in a basic block
+    Operation::Ir(format!("br label %{block_end}\nbb_break_{}:", unique()))
+}
+
 /// [`Operation`] generation for a for loop.
 pub fn for_loop<'a>(
     input: ParserPairs,
@@ -621,10 +972,11 @@ pub fn for_loop<'a>(
     // to the body block, we need to jump to the increase block at the end to
     // progress the iteration. The ending block contains everything
     // that comes AFTER the loop.
-    let mut loop_inner = pair.into_inner();
+    let mut loop_inner = pair.into_inner().peekable();
+    let label = loop_label(&mut loop_inner);
 
     // block names
-    let key = random();
+    let key = unique();
     let block_cond = format!("bb_cond_{key}");
     let block_body = format!("bb_body_{key}");
     let block_inc = format!("bb_inc_{key}");
@@ -645,35 +997,71 @@ pub fn for_loop<'a>(
     operations.push(Operation::Ir(format!("br label %{block_cond}")));
 
     // cond
-    let cond_key = random();
+    let cond_key = unique();
 
     let mut comparison = loop_inner.next().unwrap().into_inner();
     comparison.next(); // skip since this is just var_name
 
-    let op = rule_to_operator(comparison.next().unwrap().as_rule());
+    let is_float = is_float_type(&var.r#type);
+    let (cmp, op) = match comparison.next().unwrap().as_rule() {
+        Rule::AND | Rule::OR => icompiler_error!("for loop conditions can't use && or ||"),
+        rule if is_float => ("fcmp", rule_to_float_operator(rule)),
+        rule => ("icmp", rule_to_operator(rule)),
+    };
     let value = Value::get(comparison.next().unwrap(), &cond_key, &mut scoped_regs).0;
     let prefix = value.1;
-    let value = value.0;
+    let value = if is_float && matches!(value.3.as_str(), "i32" | "double") {
+        float_literal(&value.0, &var.r#type)
+    } else {
+        value.0
+    };
 
     operations.push(Operation::Ir(format!(
         "{block_cond}:
 %{var_name}_{cond_key} = load {}, ptr %{var_name}.addr, align {}
 {prefix}
-%{var_name}_cmp_{cond_key} = icmp {op} {} %{var_name}_{cond_key}, {value}
+%{var_name}_cmp_{cond_key} = {cmp} {op} {} %{var_name}_{cond_key}, {value}
 br i1 %{var_name}_cmp_{cond_key}, label %{block_body}, label %{block_end}",
         var.r#type, var.align, var.r#type
     )));
 
+    // step (optional, defaults to 1)
+    let mut step: i64 = 1;
+    let mut next = loop_inner.next().unwrap();
+
+    if next.as_rule() == Rule::for_step {
+        step = match next.as_str().replace("_", "").parse::<i64>() {
+            Ok(s) => s,
+            Err(e) => icompiler_error!("invalid for loop step: {e}"),
+        };
+
+        if step == 0 {
+            icompiler_error!("for loop step cannot be 0");
+        }
+
+        next = loop_inner.next().unwrap();
+    }
+
+    // negative steps count down
+    let (inc_op, inc_amount) = match (step < 0, is_float) {
+        (true, true) => ("fsub", float_literal(&(-step).to_string(), &var.r#type)),
+        (false, true) => ("fadd", float_literal(&step.to_string(), &var.r#type)),
+        (true, false) => ("sub nsw", (-step).to_string()),
+        (false, false) => ("add nsw", step.to_string()),
+    };
+
     // body
-    let block = loop_inner.next().unwrap().into_inner();
+    let block = next.into_inner();
     operations.push(Operation::Ir(format!("{block_body}:")));
+    scoped_regs.loops.push((label, block_end.clone()));
     let res = crate::process(block, file_specifier, scoped_regs);
 
     for operation in res.1 {
         operations.push(operation);
     }
 
-    let scoped_regs = res.0; // use updated version of scoped_regs
+    let mut scoped_regs = res.0; // use updated version of scoped_regs
+    scoped_regs.loops.pop();
     registers
         .extra_header_ir
         .push_str(&scoped_regs.extra_header_ir); // make sure header stuff is still global
@@ -681,14 +1069,14 @@ br i1 %{var_name}_cmp_{cond_key}, label %{block_body}, label %{block_end}",
     operations.push(Operation::Ir(format!("br label %{block_inc}")));
 
     // inc(rease)
-    let inc_key = random();
+    let inc_key = unique();
     operations.push(Operation::Ir(format!(
         "{block_inc}:
 %{var_name}_{inc_key} = load {}, ptr %{var_name}.addr, align {}
-%{var_name}_inc_{inc_key} = add nsw i32 %{var_name}_{inc_key}, 1
-store i32 %{var_name}_inc_{inc_key}, ptr %{var_name}.addr, align {}
+%{var_name}_inc_{inc_key} = {inc_op} {} %{var_name}_{inc_key}, {inc_amount}
+store {} %{var_name}_inc_{inc_key}, ptr %{var_name}.addr, align {}
 br label %{block_cond}",
-        var.r#type, var.align, var.align
+        var.r#type, var.align, var.r#type, var.r#type, var.align
     )));
 
     // end
@@ -714,68 +1102,38 @@ pub fn while_loop<'a>(
     registers: &mut Registers,
 ) -> (Registers, Vec<Operation>) {
     // basically just a modified for loop
-    let mut loop_inner = pair.into_inner();
+    let mut loop_inner = pair.into_inner().peekable();
+    let label = loop_label(&mut loop_inner);
 
     // block names
-    let key = random();
+    let key = unique();
     let block_cond = format!("bb_cond_{key}");
     let block_body = format!("bb_body_{key}");
     let block_end = format!("bb_end_{key}");
 
     // head
-    let scoped_regs = registers.clone(); // create new scope
+    let mut scoped_regs = registers.clone(); // create new scope
     operations.push(Operation::Ir(format!("br label %{block_cond}")));
 
     // cond
     operations.push(Operation::Ir(format!("{block_cond}:")));
-    let mut conditional_inner = loop_inner.next().unwrap().into_inner();
-
-    let lhs = conditional_inner.next().unwrap();
-    let lhs = match lhs.as_rule() {
-        Rule::identifier => {
-            let r = random();
-            let var = registers.get_var(lhs.as_str());
-            operations.push(Operation::Ir(format!(
-                "%k_{r} = load i32, ptr %{}.addr, align {}",
-                var.label, var.align
-            )));
-            format!("%k_{r}")
-        }
-        _ => lhs.as_str().to_string(),
-    };
-
-    let op = rule_to_operator(conditional_inner.next().unwrap().as_rule());
-
-    let rhs = conditional_inner.next().unwrap();
-    let rhs = match rhs.as_rule() {
-        Rule::identifier => {
-            let r = random();
-            let var = registers.get_var(rhs.as_str());
-            operations.push(Operation::Ir(format!(
-                "%k_{r} = load i32, ptr %{}.addr, align {}",
-                var.label, var.align
-            )));
-            format!("%k_{r}")
-        }
-        _ => rhs.as_str().to_string(),
-    };
-
-    let r = random();
+    let condition = comparison(loop_inner.next().unwrap(), registers, &mut operations);
     operations.push(Operation::Ir(format!(
-        "%k_cmp_{r} = icmp {op} i32 {lhs}, {rhs}
-br i1 %k_cmp_{r}, label %{block_body}, label %{block_end}",
+        "br i1 {condition}, label %{block_body}, label %{block_end}",
     )));
 
     // body
     let block = loop_inner.next().unwrap().into_inner();
     operations.push(Operation::Ir(format!("{block_body}:")));
+    scoped_regs.loops.push((label, block_end.clone()));
     let res = crate::process(block, file_specifier, scoped_regs);
 
     for operation in res.1 {
         operations.push(operation);
     }
 
-    let scoped_regs = res.0; // use updated version of scoped_regs
+    let mut scoped_regs = res.0; // use updated version of scoped_regs
+    scoped_regs.loops.pop();
     registers
         .extra_header_ir
         .push_str(&scoped_regs.extra_header_ir); // make sure header stuff is still global
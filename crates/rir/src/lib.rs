@@ -4,7 +4,8 @@ pub mod macros;
 pub mod parser;
 
 use ir::{
-    fn_return, for_loop, llvm_ir, root_function_call, var_assign, var_assign_no_alloca, while_loop,
+    extern_function, fn_return, for_loop, llvm_ir, loop_break, root_function_call, var_assign,
+    var_assign_no_alloca, while_loop,
 };
 use macros::icompiler_error;
 use parser::{InstructionParser, Pairs, Parser, Rule};
@@ -13,20 +14,30 @@ pub type ParserPairs<'a> = Pairs<'a, Rule>;
 use data::{Function, Operation, Registers, Section, ToIr, Variable};
 use pathbufd::PathBufD as PathBuf;
 use std::{
+    collections::HashMap,
     fs::read_to_string,
-    sync::{LazyLock, Mutex},
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
 pub static COMPILER_MARKER: LazyLock<Mutex<(String, String)>> =
     LazyLock::new(|| Mutex::new((String::default(), String::default())));
 
-use rand::{Rng, distributions::Alphanumeric, thread_rng};
-pub fn random() -> String {
-    thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect()
+/// The number of the next name returned by [`unique`].
+static UNIQUE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Get a name which hasn't been used yet (`u0`, `u1`, ...), for labels and temporaries
+/// in the generated IR. Names are given out in order, so the same input always
+/// produces the same IR.
+pub fn unique() -> String {
+    format!("u{}", UNIQUE_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Continue [`unique`] names from `seed`.
+pub fn reset_unique(seed: usize) {
+    UNIQUE_COUNTER.store(seed, Ordering::Relaxed)
 }
 
 pub fn process<'a>(
@@ -75,15 +86,20 @@ pub fn process<'a>(
 
                 operations.push(Operation::Ir(compiled.1));
             }
+            Rule::r#extern => extern_function(pair, &mut operations, &mut registers),
             Rule::section => {
                 let mut inner = pair.into_inner();
 
                 let ident = inner.next().unwrap().as_str().to_string();
 
+                // the section emits its own header ir, don't emit ours twice
+                let mut section_registers = registers.clone();
+                section_registers.extra_header_ir.clear();
+
                 let operations_ = process(
                     inner.next().unwrap().into_inner(), // block
                     file_specifier,
-                    registers.clone(),
+                    section_registers,
                 );
 
                 let ops_regs = operations_.0;
@@ -114,7 +130,7 @@ pub fn process<'a>(
                                 // name
                                 inner.next().unwrap().as_str().to_string(),
                                 // fake name
-                                random(),
+                                unique(),
                             ));
                         }
                         Rule::block => {
@@ -175,12 +191,11 @@ pub fn process<'a>(
                 let ident = inner.next().unwrap().as_str().to_string();
                 let value = inner.next().unwrap().as_str().to_string();
 
+                // the variable's memory is stored under its label, not its name
+                let label = registers.get_var(&ident).label.clone();
+
                 // push operation
-                operations.push(Operation::Pipe((
-                    ident.to_string(),
-                    ident.to_string(),
-                    value.to_string(),
-                )));
+                operations.push(Operation::Pipe((ident, label, value)));
             }
             Rule::read => {
                 let ident = pair.into_inner().next().unwrap().as_str();
@@ -191,6 +206,7 @@ pub fn process<'a>(
                 "ret {}",
                 fn_return(pair, &registers)
             ))),
+            Rule::r#break => operations.push(loop_break(pair, &registers)),
             Rule::for_loop => {
                 return for_loop(input, pair, file_specifier, operations, &mut registers);
             }
@@ -219,13 +235,21 @@ macro_rules! define {
             value: $value.to_string(),
             size: 0,
             align: 0,
-            key: random(),
+            key: unique(),
         });
     };
 }
 
 // ...
 pub fn process_file(path: PathBuf) -> (Registers, String) {
+    process_file_with_entry(path, None)
+}
+
+/// [`process_file`], but start execution at the given top-level `entry` section.
+///
+/// Top-level sections are placed in a generated `main` function which branches to
+/// `entry` (so every top-level section must end with a `return` or `jump`).
+pub fn process_file_with_entry(path: PathBuf, entry: Option<&str>) -> (Registers, String) {
     let mut registers: Registers = Registers::default();
 
     // define some compiler variables
@@ -244,14 +268,38 @@ pub fn process_file(path: PathBuf) -> (Registers, String) {
 
     let mut head: String = String::new();
     let mut body: String = String::new();
+    let mut entry_body: String = String::new();
 
     let file_specifier = path.as_path().to_str().unwrap();
     let mut operations = process(parsed, file_specifier, registers);
 
+    if let Some(entry) = entry {
+        // make sure the entry section exists at the top level
+        if !operations
+            .1
+            .iter()
+            .any(|op| matches!(op, Operation::Section(ident) if ident == entry))
+        {
+            icompiler_error!("entry section is not a top-level section: {entry}")
+        }
+    }
+
     for operation in operations.1 {
+        let is_section = matches!(operation, Operation::Section(_));
         let (head_, body_) = operation.transform(&mut operations.0);
         head.push_str(&format!("{head_}\n"));
-        body.push_str(&format!("{body_}\n"));
+
+        if is_section && entry.is_some() {
+            entry_body.push_str(&format!("{body_}\n"));
+        } else {
+            body.push_str(&format!("{body_}\n"));
+        }
+    }
+
+    if let Some(entry) = entry {
+        body.push_str(&format!(
+            "define i32 @main() {{\nfaraday.entry:\n    br label %{entry}\n{entry_body}}}\n"
+        ));
     }
 
     (
@@ -263,11 +311,11 @@ pub fn process_file(path: PathBuf) -> (Registers, String) {
     )
 }
 
-pub fn process_file_with_bindings(path: PathBuf) -> (Registers, String) {
-    let out = process_file(path);
+pub fn process_file_with_bindings(path: PathBuf, entry: Option<&str>) -> (Registers, String) {
+    let out = process_file_with_entry(path, entry);
     (
         out.0,
-        format!(
+        dedup_declarations(format!(
             "; faraday rir
 declare i32 @puts(i8* nocapture) nounwind
 declare i32 @printf(i8* nocapture) nounwind
@@ -279,6 +327,42 @@ declare ptr @malloc(i32) nounwind
 declare void @free(i8* nocapture) nounwind
 {}",
             out.1
-        ),
+        )),
     )
 }
+
+/// Remove the `declare` lines of functions which are already declared (like externs
+/// from a file that's included more than once).
+fn dedup_declarations(ir: String) -> String {
+    let mut declared: HashMap<String, String> = HashMap::new();
+    let mut out: Vec<&str> = Vec::new();
+
+    for line in ir.lines() {
+        let trimmed = line.trim();
+
+        if let Some(ident) = trimmed
+            .strip_prefix("declare ")
+            .and_then(|declaration| declaration.split_once('@'))
+            .and_then(|(_, rest)| rest.split_once('('))
+            .map(|(ident, _)| ident.to_string())
+        {
+            match declared.get(&ident) {
+                Some(existing) if existing == trimmed => continue,
+                Some(_) => icompiler_error!("function is already declared: {ident}"),
+                None => {
+                    declared.insert(ident, trimmed.to_string());
+                }
+            }
+        }
+
+        out.push(line);
+    }
+
+    let mut out = out.join("\n");
+
+    if ir.ends_with('\n') {
+        out.push('\n');
+    }
+
+    out
+}